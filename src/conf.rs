@@ -1,16 +1,57 @@
+use std::path::PathBuf;
+
 use bevy::prelude::*;
+use bevy_persistent::prelude::*;
 // use bevy_inspector_egui::{prelude::*, quick::ResourceInspectorPlugin};
 
 use serde::{Deserialize, Serialize};
 
-pub struct ConfigPlugin;
+/// overrides where the configuration is kept, e.g. for tests
+pub const CONFIG_DIR_VAR: &str = "TRASHURE_CONFIG_DIR";
+
+pub struct ConfigPlugin {
+    /// `configuration.json` goes in there
+    pub dir: PathBuf,
+}
+
+impl Default for ConfigPlugin {
+    fn default() -> Self {
+        Self { dir: config_dir() }
+    }
+}
+
+/// `$TRASHURE_CONFIG_DIR`, or `config` next to `saves`
+pub fn config_dir() -> PathBuf {
+    std::env::var_os(CONFIG_DIR_VAR).map_or_else(|| PathBuf::from("config"), PathBuf::from)
+}
 
 impl Plugin for ConfigPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(Configuration::default())
-            // .insert_resource(Persistent::<Configuration>::n())
-            .add_systems(Startup, setup)
-            // .add_systems(Update, on_modify_configuration)
+        let path = self.dir.join("configuration.json");
+
+        let pers = Persistent::<Configuration>::builder()
+            .name("configuration")
+            .format(StorageFormat::Json)
+            .path(path.clone())
+            .default(Configuration::default())
+            .build();
+
+        match pers {
+            Ok(pers) => {
+                app.insert_resource(pers.get().clone())
+                    .insert_resource(pers);
+            }
+            // still playable, just nothing gets remembered
+            Err(e) => {
+                warn!(
+                    "failed to load the configuration from {}: {e}",
+                    path.display()
+                );
+                app.insert_resource(Configuration::default());
+            }
+        }
+
+        app.add_systems(Update, (on_modify_configuration, apply_ui_scale))
             .register_type::<Configuration>(); // you need to register your type to display it
                                                // .add_plugins(ResourceInspectorPlugin::<Configuration>::default());
     }
@@ -19,17 +60,18 @@ impl Plugin for ConfigPlugin {
 #[derive(
     Reflect,
     Resource,
-    Default,
     //  InspectorOptions,
     Serialize,
     Deserialize,
     Clone,
 )]
 // #[reflect(Resource, InspectorOptions)]
+#[serde(default)]
 pub struct Configuration {
     // #[inspector(min = 0.0, max = 2.0)]
     // pub track_point_scale: f32,
     // #[inspector(min = 0.0, max = 2000.0)]
+    /// how far the camera is zoomed out at first
     pub camera_scale: f32,
 
     // #[inspector(min = 0.0, max = 200000.0)]
@@ -40,46 +82,276 @@ pub struct Configuration {
 
     // #[inspector(min = 0.0, max = 10.0)]
     pub camera_speed: f32,
+
+    pub shadows: ShadowQuality,
+
+    /// multiplies the size of all UI
+    pub ui_scale: f32,
+
+    pub keys: Keybindings,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self {
+            camera_scale: 20.0,
+            // grid_size: 300.0,
+            grid_size: 50.0,
+            shadow_tint: 0.5,
+            camera_speed: 0.15,
+            shadows: ShadowQuality::Medium,
+            ui_scale: 1.0,
+            keys: Keybindings::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Serialize, Deserialize)]
+pub enum ShadowQuality {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    /// `DirectionalLightShadowMap::size`
+    pub fn map_size(self) -> usize {
+        match self {
+            ShadowQuality::Off | ShadowQuality::Low => 512,
+            ShadowQuality::Medium => 1024,
+            ShadowQuality::High => 4096,
+        }
+    }
+
+    /// the one after it, back to `Off` after `High`
+    pub fn next(self) -> Self {
+        match self {
+            ShadowQuality::Off => ShadowQuality::Low,
+            ShadowQuality::Low => ShadowQuality::Medium,
+            ShadowQuality::Medium => ShadowQuality::High,
+            ShadowQuality::High => ShadowQuality::Off,
+        }
+    }
+}
+
+/// What the player can press something else for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
+pub enum KeyAction {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    TurnLeft,
+    TurnRight,
+    Pause,
+    Step,
+    Quicksave,
+    Quickload,
+    Rotate,
+    Deconstruct,
+    Waypoint,
+    Follow,
+    Speed1,
+    Speed2,
+    Speed4,
+}
+
+impl KeyAction {
+    pub fn all() -> &'static [KeyAction] {
+        &[
+            KeyAction::PanUp,
+            KeyAction::PanDown,
+            KeyAction::PanLeft,
+            KeyAction::PanRight,
+            KeyAction::TurnLeft,
+            KeyAction::TurnRight,
+            KeyAction::Pause,
+            KeyAction::Step,
+            KeyAction::Quicksave,
+            KeyAction::Quickload,
+            KeyAction::Rotate,
+            KeyAction::Deconstruct,
+            KeyAction::Waypoint,
+            KeyAction::Follow,
+            KeyAction::Speed1,
+            KeyAction::Speed2,
+            KeyAction::Speed4,
+        ]
+    }
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            KeyAction::PanUp => "Pan up",
+            KeyAction::PanDown => "Pan down",
+            KeyAction::PanLeft => "Pan left",
+            KeyAction::PanRight => "Pan right",
+            KeyAction::TurnLeft => "Turn camera left",
+            KeyAction::TurnRight => "Turn camera right",
+            KeyAction::Pause => "Pause",
+            KeyAction::Step => "Step while paused",
+            KeyAction::Quicksave => "Quicksave",
+            KeyAction::Quickload => "Quickload",
+            KeyAction::Rotate => "Rotate machine",
+            KeyAction::Deconstruct => "Deconstruct machine",
+            KeyAction::Waypoint => "Send Plower to cursor",
+            KeyAction::Follow => "Follow selected machine",
+            KeyAction::Speed1 => "Normal speed",
+            KeyAction::Speed2 => "Double speed",
+            KeyAction::Speed4 => "Quadruple speed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Reflect, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Keybindings {
+    pub pan_up: KeyCode,
+    pub pan_down: KeyCode,
+    pub pan_left: KeyCode,
+    pub pan_right: KeyCode,
+    pub turn_left: KeyCode,
+    pub turn_right: KeyCode,
+    pub pause: KeyCode,
+    pub step: KeyCode,
+    pub quicksave: KeyCode,
+    pub quickload: KeyCode,
+    pub rotate: KeyCode,
+    pub deconstruct: KeyCode,
+    pub waypoint: KeyCode,
+    pub follow: KeyCode,
+    pub speed_1: KeyCode,
+    pub speed_2: KeyCode,
+    pub speed_4: KeyCode,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            pan_up: KeyCode::Up,
+            pan_down: KeyCode::Down,
+            pan_left: KeyCode::Left,
+            pan_right: KeyCode::Right,
+            turn_left: KeyCode::Q,
+            turn_right: KeyCode::E,
+            pause: KeyCode::Space,
+            step: KeyCode::Period,
+            quicksave: KeyCode::F5,
+            quickload: KeyCode::F9,
+            rotate: KeyCode::R,
+            deconstruct: KeyCode::Delete,
+            waypoint: KeyCode::W,
+            follow: KeyCode::F,
+            speed_1: KeyCode::Key1,
+            speed_2: KeyCode::Key2,
+            speed_4: KeyCode::Key3,
+        }
+    }
+}
+
+impl Keybindings {
+    pub fn key(&self, action: KeyAction) -> KeyCode {
+        match action {
+            KeyAction::PanUp => self.pan_up,
+            KeyAction::PanDown => self.pan_down,
+            KeyAction::PanLeft => self.pan_left,
+            KeyAction::PanRight => self.pan_right,
+            KeyAction::TurnLeft => self.turn_left,
+            KeyAction::TurnRight => self.turn_right,
+            KeyAction::Pause => self.pause,
+            KeyAction::Step => self.step,
+            KeyAction::Quicksave => self.quicksave,
+            KeyAction::Quickload => self.quickload,
+            KeyAction::Rotate => self.rotate,
+            KeyAction::Deconstruct => self.deconstruct,
+            KeyAction::Waypoint => self.waypoint,
+            KeyAction::Follow => self.follow,
+            KeyAction::Speed1 => self.speed_1,
+            KeyAction::Speed2 => self.speed_2,
+            KeyAction::Speed4 => self.speed_4,
+        }
+    }
+
+    pub fn set(&mut self, action: KeyAction, key: KeyCode) {
+        let bound = match action {
+            KeyAction::PanUp => &mut self.pan_up,
+            KeyAction::PanDown => &mut self.pan_down,
+            KeyAction::PanLeft => &mut self.pan_left,
+            KeyAction::PanRight => &mut self.pan_right,
+            KeyAction::TurnLeft => &mut self.turn_left,
+            KeyAction::TurnRight => &mut self.turn_right,
+            KeyAction::Pause => &mut self.pause,
+            KeyAction::Step => &mut self.step,
+            KeyAction::Quicksave => &mut self.quicksave,
+            KeyAction::Quickload => &mut self.quickload,
+            KeyAction::Rotate => &mut self.rotate,
+            KeyAction::Deconstruct => &mut self.deconstruct,
+            KeyAction::Waypoint => &mut self.waypoint,
+            KeyAction::Follow => &mut self.follow,
+            KeyAction::Speed1 => &mut self.speed_1,
+            KeyAction::Speed2 => &mut self.speed_2,
+            KeyAction::Speed4 => &mut self.speed_4,
+        };
+
+        *bound = key;
+    }
+
+    /// binds `key` to `action`; whichever action had it before gets the key
+    /// `action` leaves behind, so no key ends up doing two things
+    pub fn bind(&mut self, action: KeyAction, key: KeyCode) {
+        let previous = self.key(action);
+
+        if let Some(&other) = KeyAction::all()
+            .iter()
+            .find(|&&other| other != action && self.key(other) == key)
+        {
+            self.set(other, previous);
+        }
+
+        self.set(action, key);
+    }
+}
+
+fn on_modify_configuration(
+    config: Res<Configuration>,
+    persistent_config: Option<ResMut<Persistent<Configuration>>>,
+) {
+    if !config.is_changed() || config.is_added() {
+        return;
+    }
+
+    let Some(mut persistent_config) = persistent_config else {
+        return;
+    };
+
+    if let Err(e) = persistent_config.set(config.clone()) {
+        warn!("failed to save the configuration: {e}");
+    }
 }
 
-// fn on_modify_configuration(
-//     config: Res<Configuration>,
-//     mut persistent_config: ResMut<Persistent<Configuration>>,
-// ) {
-//     if !config.is_changed() {
-//         return;
-//     }
-
-//     persistent_config
-//         .update(|pers| {
-//             *pers = config.clone();
-//         })
-//         .expect("failed to update config");
-// }
-
-fn setup(mut commands: Commands) {
-    // let config_dir = dirs::config_dir().unwrap().join("your-amazing-game");
-    //todo
-    // let config_dir = Path::new("/Users/valentin/Work/cw/trashure");
-
-    // let pers = Persistent::<Configuration>::builder()
-    //     .name("configuration")
-    //     .format(StorageFormat::Json)
-    //     .path(config_dir.join("configuration.json"))
-    //     .default(Configuration {
-    //         camera_scale: 2.5,
-    //         // track_point_scale: 1.0,
-    //         // track_point_min_distance: 2.0,
-    //     })
-    //     .build()
-    //     .expect("failed to initialize ");
-
-    commands.insert_resource(Configuration {
-        camera_scale: 2.5,
-        // grid_size: 300.0,
-        grid_size: 50.0,
-        shadow_tint: 0.5,
-        camera_speed: 0.15,
-    });
-    // commands.insert_resource(pers);
+fn apply_ui_scale(config: Res<Configuration>, mut ui_scale: ResMut<UiScale>) {
+    if config.is_changed() {
+        ui_scale.0 = config.ui_scale as f64;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn binding_a_taken_key_swaps() {
+        let mut keys = Keybindings::default();
+
+        keys.bind(KeyAction::Rotate, KeyCode::Space);
+
+        assert_eq!(keys.key(KeyAction::Rotate), KeyCode::Space);
+        assert_eq!(keys.key(KeyAction::Pause), KeyCode::R);
+
+        for (i, &a) in KeyAction::all().iter().enumerate() {
+            for &b in &KeyAction::all()[i + 1..] {
+                assert_ne!(keys.key(a), keys.key(b), "{a:?} and {b:?}");
+            }
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! Conveyor belts: 1-wide segments that catch whatever lands on them and pass it
+//! on to whatever they point at.
+
+use bevy::prelude::*;
+use itertools::Itertools;
+
+use crate::graphics::{
+    flyingvoxel::FlyingVoxel,
+    machines::{BuiltMachine, MyMachine},
+    selectable::Selectable,
+    voxels3d::{
+        changes::VoxelBlockChanges, lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock,
+    },
+};
+
+use super::{
+    machines::GameMachineSettingsDiscriminants,
+    seed::{SessionRng, SessionRngSet},
+    voxelmailbox::VoxelMailbox,
+    Direction2D,
+};
+
+/// seconds a segment takes to pass one voxel on
+const CONVEYOR_STEP: f32 = 0.2;
+
+pub struct ConveyorPlugin;
+
+impl Plugin for ConveyorPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                claim_columns,
+                run_conveyors
+                    .after(claim_columns)
+                    .in_set(SessionRngSet::Conveyors),
+            ),
+        );
+    }
+}
+
+/// Added to conveyors once they're built, carries what's in their `VoxelMailbox`.
+#[derive(Debug, Component)]
+pub struct ConveyorSegment {
+    step: Timer,
+}
+
+impl ConveyorSegment {
+    pub fn new() -> Self {
+        Self {
+            step: Timer::from_seconds(CONVEYOR_STEP, TimerMode::Repeating),
+        }
+    }
+}
+
+/// makes the column under every segment catch voxels, and lets go of the
+/// columns of segments that are gone
+fn claim_columns(
+    q_segments: Query<(Entity, &MyMachine), With<ConveyorSegment>>,
+    lazy_world: Res<LazyWorld>,
+    mut q_blocks: Query<&mut VoxelBlock>,
+) {
+    for (e, mm) in q_segments.iter() {
+        let (part, local_p) = VoxelBlock::normalize_col(mm.pos);
+
+        let Some(mut block) = lazy_world
+            .ground(part)
+            .and_then(|b| q_blocks.get_mut(b).ok())
+        else {
+            continue;
+        };
+
+        // again whenever the block was paged out or loaded from a save
+        if block.catchers.get(&local_p) != Some(&e) {
+            block.catch_column(local_p, e);
+        }
+    }
+
+    for mut block in q_blocks.iter_mut() {
+        if block.catchers.values().all(|e| q_segments.contains(*e)) {
+            continue;
+        }
+
+        let gone = block
+            .catchers
+            .iter()
+            .filter(|(_, e)| !q_segments.contains(**e))
+            .map(|(col, _)| *col)
+            .collect_vec();
+
+        for col in gone {
+            block.release_column(col);
+        }
+    }
+}
+
+fn run_conveyors(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut q_segments: Query<(Entity, &MyMachine, &Direction2D, &mut ConveyorSegment)>,
+    q_machines: Query<(Entity, &MyMachine, &Direction2D, Option<&BuiltMachine>), With<Selectable>>,
+    mut q_mailboxes: Query<&mut VoxelMailbox, Without<VoxelBlock>>,
+    lazy_world: Res<LazyWorld>,
+    blocks: Query<&mut VoxelBlock>,
+    mut changes: ResMut<VoxelBlockChanges>,
+    mut rng: ResMut<SessionRng>,
+) {
+    let rand = &mut rng.0;
+    let mut wbw = WholeBlockWorld { lazy_world, blocks };
+
+    let ready = q_segments
+        .iter_mut()
+        .filter_map(|(e, mm, dir, mut segment)| {
+            segment
+                .step
+                .tick(time.delta())
+                .just_finished()
+                .then(|| (e, mm.pos, mm.pos + IVec2::from(*dir)))
+        })
+        .collect_vec();
+
+    for (e, pos, next_col) in ready {
+        let next = q_machines
+            .iter()
+            .find(|(other, omm, odir, _)| *other != e && omm.covers(**odir, next_col));
+
+        let next_is_conveyor = next
+            .is_some_and(|(_, omm, _, _)| omm.gmt == GameMachineSettingsDiscriminants::Conveyor);
+
+        // the line waits for the next segment to be built
+        if next_is_conveyor && next.is_some_and(|(_, _, _, built)| built.is_none()) {
+            continue;
+        }
+
+        let Some((_, mat, tp)) = q_mailboxes
+            .get_mut(e)
+            .ok()
+            .and_then(|mut mb| mb.0.pop_front())
+        else {
+            continue;
+        };
+
+        match next {
+            Some((next_e, _, _, _)) if next_is_conveyor => {
+                let mut mb = q_mailboxes.get_mut(next_e).unwrap();
+
+                mb.0.push_back((next_col.extend(0).xzy(), mat, tp));
+            }
+            Some((next_e, omm, _, _)) => {
+                commands.spawn(FlyingVoxel {
+                    origin: pos.extend(1).xzy().as_vec3(),
+                    target: omm.pos.extend(3).xzy().as_vec3(),
+                    target_mailbox: next_e,
+                    material: mat,
+                    payload: (omm.pos.extend(3).xzy(), tp),
+                });
+            }
+            None => wbw.drop_block(next_col, mat, &mut changes, rand),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Which columns a machine stands on. Those are kept forbidden, so nothing lands
+//! inside the machine, and follow it as it moves.
+//!
+//! A footprint is given in the machine's own frame, as if it faced `Forward`, and
+//! turns around `MyMachine::pos` the same way the machine's scene does.
+
+use bevy::{prelude::*, utils::HashSet};
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::graphics::{
+    machines::MyMachine,
+    selectable::Selectable,
+    voxels3d::{
+        changes::VoxelBlockChanges, lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock,
+        VOXEL_BLOCK_SIZE,
+    },
+};
+
+use super::{
+    machines::GameMachineSettingsDiscriminants,
+    seed::{SessionRng, SessionRngSet},
+    Direction2D,
+};
+
+/// Columns `min..min + size` around the machine's `pos`, in its own frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Deserialize)]
+pub struct FootprintRect {
+    pub min: IVec2,
+    pub size: IVec2,
+}
+
+impl FootprintRect {
+    /// `size` columns with `pos` in the middle, or just past it for even sizes
+    pub fn centered(size: IVec2) -> Self {
+        Self {
+            min: -size / 2,
+            size,
+        }
+    }
+
+    /// the last column in it
+    pub fn max(&self) -> IVec2 {
+        self.min + self.size - 1
+    }
+
+    /// the same columns turned `dir` around the origin, still a rectangle
+    pub fn rotated(&self, dir: Direction2D) -> Self {
+        let a = dir.rotate_vec(self.min);
+        let b = dir.rotate_vec(self.max());
+        let min = a.min(b);
+
+        Self {
+            min,
+            size: a.max(b) - min + 1,
+        }
+    }
+
+    pub fn contains(&self, cell: IVec2) -> bool {
+        cell.cmpge(self.min).all() && cell.cmple(self.max()).all()
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.cmple(other.max()).all() && other.min.cmple(self.max()).all()
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = IVec2> {
+        let Self { min, size } = *self;
+
+        (0..size.x).flat_map(move |x| (0..size.y).map(move |z| min + IVec2::new(x, z)))
+    }
+
+    /// in the middle of its columns, which sit on whole numbers
+    pub fn center(&self) -> Vec2 {
+        self.min.as_vec2() + (self.size - 1).as_vec2() / 2.0
+    }
+}
+
+/// What a machine stands on, one or more (say, for an L shape) rectangles.
+#[derive(Debug, Clone, PartialEq, Reflect)]
+pub struct Footprint {
+    pub rects: Vec<FootprintRect>,
+}
+
+impl Footprint {
+    pub fn new(rects: Vec<FootprintRect>) -> Self {
+        Self { rects }
+    }
+
+    /// a single rectangle centered on the machine
+    pub fn rect(dims: IVec2) -> Self {
+        Self::new(vec![FootprintRect::centered(dims)])
+    }
+
+    /// the box around all of its rectangles, in the machine's frame
+    pub fn bounds(&self) -> FootprintRect {
+        let min = self.rects.iter().map(|r| r.min).reduce(IVec2::min);
+        let max = self.rects.iter().map(|r| r.max()).reduce(IVec2::max);
+
+        match min.zip(max) {
+            Some((min, max)) => FootprintRect {
+                min,
+                size: max - min + 1,
+            },
+            None => FootprintRect::centered(IVec2::ZERO),
+        }
+    }
+
+    /// the rectangles where they end up with the machine at `pos`, facing `dir`
+    pub fn placed(&self, pos: IVec2, dir: Direction2D) -> impl Iterator<Item = FootprintRect> + '_ {
+        self.rects.iter().map(move |r| {
+            let r = r.rotated(dir);
+
+            FootprintRect {
+                min: r.min + pos,
+                ..r
+            }
+        })
+    }
+
+    /// every column under the machine, once even if its rectangles overlap
+    pub fn columns(&self, pos: IVec2, dir: Direction2D) -> impl Iterator<Item = IVec2> + '_ {
+        self.placed(pos, dir).flat_map(|r| r.cells()).unique()
+    }
+
+    pub fn covers(&self, pos: IVec2, dir: Direction2D, col: IVec2) -> bool {
+        let cell = dir.unrotate_vec(col - pos);
+
+        self.rects.iter().any(|r| r.contains(cell))
+    }
+
+    pub fn intersects(
+        &self,
+        pos: IVec2,
+        dir: Direction2D,
+        other: &Self,
+        other_pos: IVec2,
+        other_dir: Direction2D,
+    ) -> bool {
+        self.placed(pos, dir)
+            .cartesian_product(other.placed(other_pos, other_dir).collect_vec())
+            .any(|(a, b)| a.intersects(&b))
+    }
+
+    /// columns the machine would newly stand on after a step towards `dir`,
+    /// facing that way
+    pub fn leading_edge(&self, pos: IVec2, dir: Direction2D) -> Vec<IVec2> {
+        self.columns(pos + IVec2::from(dir), dir)
+            .filter(|c| !self.covers(pos, dir, *c))
+            .collect()
+    }
+
+    /// where the machine's scene goes relative to `pos`, so it sits right on
+    /// top of its columns
+    pub fn scene_offset(&self, dir: Direction2D) -> Vec2 {
+        dir.rotate_vec2(self.bounds().center())
+    }
+}
+
+pub struct FootprintPlugin;
+
+impl Plugin for FootprintPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, claim_footprints.in_set(SessionRngSet::Footprints));
+    }
+}
+
+fn claim_footprints(
+    // still being built counts too
+    q_machines: Query<(Entity, &MyMachine, &Direction2D), With<Selectable>>,
+    lazy_world: Res<LazyWorld>,
+    blocks: Query<&mut VoxelBlock>,
+    mut changes: ResMut<VoxelBlockChanges>,
+    mut rng: ResMut<SessionRng>,
+) {
+    let rand = &mut rng.0;
+    let mut wbw = WholeBlockWorld { lazy_world, blocks };
+
+    // conveyors catch what lands on them instead
+    let claims: HashSet<(Entity, IVec2)> = q_machines
+        .iter()
+        .filter(|(_, mm, _)| mm.gmt != GameMachineSettingsDiscriminants::Conveyor)
+        .flat_map(|(e, mm, dir)| mm.footprint.columns(mm.pos, *dir).map(move |col| (e, col)))
+        .collect();
+
+    // machines that moved away or are gone
+    for mut block in wbw.blocks.iter_mut() {
+        let block_pos = block.part();
+
+        let left = block
+            .occupied
+            .iter()
+            .filter(|(col, e)| !claims.contains(&(**e, block_pos * VOXEL_BLOCK_SIZE + **col)))
+            .map(|(col, _)| *col)
+            .collect_vec();
+
+        for col in left {
+            block.vacate_column(col);
+        }
+    }
+
+    for (e, col) in claims {
+        // already claimed, by it or by whatever got there first
+        if wbw.column_holder(col).is_some() {
+            continue;
+        }
+
+        wbw.occupy_column(col, e, &mut changes, rand);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{math::IVec2, utils::HashSet};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::game::Direction2D;
+
+    use super::{Footprint, FootprintRect};
+
+    fn directions() -> impl Iterator<Item = Direction2D> {
+        (0..4usize).map(Direction2D::from)
+    }
+
+    /// even and odd, square and not, and an L made of two rectangles
+    fn footprints() -> Vec<Footprint> {
+        let mut footprints = [
+            (1, 1),
+            (2, 2),
+            (3, 3),
+            (2, 3),
+            (3, 2),
+            (4, 1),
+            (1, 5),
+            (4, 7),
+        ]
+        .map(|(x, z)| Footprint::rect(IVec2::new(x, z)))
+        .to_vec();
+
+        footprints.push(Footprint::new(vec![
+            FootprintRect {
+                min: IVec2::new(-1, -2),
+                size: IVec2::new(2, 4),
+            },
+            FootprintRect {
+                min: IVec2::new(1, 0),
+                size: IVec2::new(3, 2),
+            },
+        ]));
+
+        footprints
+    }
+
+    fn column_set(footprint: &Footprint, pos: IVec2, dir: Direction2D) -> HashSet<IVec2> {
+        footprint.columns(pos, dir).collect()
+    }
+
+    #[test]
+    fn rotated_rects_hold_the_rotated_cells() {
+        for footprint in footprints() {
+            for rect in footprint.rects.iter() {
+                for dir in directions() {
+                    let rotated = rect.rotated(dir);
+                    let cells = rect
+                        .cells()
+                        .map(|c| dir.rotate_vec(c))
+                        .collect::<HashSet<_>>();
+
+                    assert_eq!(
+                        rotated.cells().collect::<HashSet<_>>(),
+                        cells,
+                        "{rect:?} {dir:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn covers_agrees_with_columns() {
+        let pos = IVec2::new(7, -3);
+
+        for footprint in footprints() {
+            for dir in directions() {
+                let columns = column_set(&footprint, pos, dir);
+                let area = footprint
+                    .rects
+                    .iter()
+                    .flat_map(|r| r.cells())
+                    .collect::<HashSet<_>>()
+                    .len();
+
+                assert_eq!(columns.len(), area, "{footprint:?} {dir:?}");
+
+                for x in -8..8 {
+                    for z in -8..8 {
+                        let col = pos + IVec2::new(x, z);
+
+                        assert_eq!(
+                            footprint.covers(pos, dir, col),
+                            columns.contains(&col),
+                            "{footprint:?} {dir:?} {col}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn intersects_is_symmetric_and_exact() {
+        let rand = &mut StdRng::seed_from_u64(4);
+        let footprints = footprints();
+
+        for _ in 0..2000 {
+            let a = &footprints[rand.gen_range(0..footprints.len())];
+            let b = &footprints[rand.gen_range(0..footprints.len())];
+            let (a_dir, b_dir) = (
+                Direction2D::from(rand.gen_range(0..4usize)),
+                Direction2D::from(rand.gen_range(0..4usize)),
+            );
+            let a_pos = IVec2::new(rand.gen_range(-6..6), rand.gen_range(-6..6));
+            let b_pos = IVec2::new(rand.gen_range(-6..6), rand.gen_range(-6..6));
+
+            let ab = a.intersects(a_pos, a_dir, b, b_pos, b_dir);
+            let ba = b.intersects(b_pos, b_dir, a, a_pos, a_dir);
+            let overlap = !column_set(a, a_pos, a_dir).is_disjoint(&column_set(b, b_pos, b_dir));
+
+            assert_eq!(ab, ba);
+            assert_eq!(
+                ab, overlap,
+                "{a:?} at {a_pos} {a_dir:?}, {b:?} at {b_pos} {b_dir:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn leading_edge_is_what_a_step_adds() {
+        let pos = IVec2::new(-5, 9);
+
+        for footprint in footprints() {
+            for dir in directions() {
+                let before = column_set(&footprint, pos, dir);
+                let after = column_set(&footprint, pos + IVec2::from(dir), dir);
+
+                let edge = footprint.leading_edge(pos, dir);
+
+                assert_eq!(edge.len(), after.difference(&before).count());
+                assert_eq!(
+                    edge.into_iter().collect::<HashSet<_>>(),
+                    after.difference(&before).copied().collect(),
+                    "{footprint:?} {dir:?}"
+                );
+            }
+        }
+    }
+}
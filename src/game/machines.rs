@@ -1,36 +1,64 @@
-use bevy::{prelude::*, utils::Instant};
+use bevy::{
+    prelude::*,
+    utils::{HashMap, Instant},
+};
 use rand::prelude::Rng;
+use serde::{Deserialize, Serialize};
 use strum::EnumDiscriminants;
 
 use crate::graphics::{
     flyingvoxel::FlyingVoxel,
     gamemenu::tutorial::mark_tutorial_event,
     machines::{
-        radar::{consumption::RadarConsumer, Radar, RadarBundle, RadarType},
+        radar::{consumption::RadarConsumer, Radar, RadarType},
         targets::Target,
         BuiltMachine, MachineType, MyMachine,
     },
     sceneobjectfinder::{SceneFoundObject, SceneObjectsFound},
     stats::StatsValues,
-    voxels3d::{lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock, VOXEL_BLOCK_SIZE},
+    voxels3d::{
+        changes::VoxelBlockChanges, lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock,
+        DROP_HEIGHT, VOXEL_BLOCK_SIZE,
+    },
 };
 
-use super::{material::GameMaterial, voxelmailbox::VoxelMailbox, Direction2D};
+use super::{
+    conveyor::ConveyorSegment,
+    material::GameMaterial,
+    seed::{SessionRng, SessionRngSet},
+    silo::SiloInventory,
+    supply::SupplyLink,
+    voxelmailbox::VoxelMailbox,
+    waypoint::Waypoint,
+    Direction2D,
+};
 
 pub struct MachinesPlugin;
 
 impl Plugin for MachinesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (consume_mailbox, move_machines, toggle_radars))
-            .add_systems(FixedUpdate, add_maintenance);
+        app.add_systems(
+            Update,
+            (
+                consume_mailbox.in_set(SessionRngSet::Mailboxes),
+                move_machines,
+                toggle_radars,
+            ),
+        )
+        .add_systems(
+            FixedUpdate,
+            add_maintenance.in_set(SessionRngSet::Maintenance),
+        );
     }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, EnumDiscriminants)]
-#[strum_discriminants(derive(Reflect, Hash))]
+#[strum_discriminants(derive(Reflect, Hash, Serialize, Deserialize))]
 pub enum GameMachineSettings {
     Recycler { recycling_radar: Entity },
     Plower { plowing_radar: Entity },
+    Conveyor,
+    Silo { gathering_radar: Entity },
 }
 
 impl GameMachineSettings {
@@ -41,102 +69,66 @@ impl GameMachineSettings {
         scob: &SceneObjectsFound,
         q_found_transforms: &Query<&Transform, With<SceneFoundObject>>,
         mt: &MachineType,
+        has_target: bool,
     ) {
-        let fuel_radar = commands
-            .spawn((
-                Name::new("fuel radar"),
-                RadarBundle::new(
-                    &[GameMaterial::Blueish],
-                    None,
-                    RadarConsumer {
-                        flying_target: None,
-                        // target_mailbox: None,
-                        target_mailbox: Some(ghost),
-                    },
-                    0.5,
-                    10.0,
-                    RadarType::Fuel,
-                ),
-                // VoxelMailbox(default()),
-            ))
-            .id();
-
-        let maintenance_radar = commands
-            .spawn((
-                Name::new("maintenance radar"),
-                RadarBundle::new(
-                    &[GameMaterial::Reddish],
-                    None,
-                    RadarConsumer {
-                        flying_target: None,
-                        // target_mailbox: None,
+        let mut radars = vec![];
+
+        // the build radar is already there since placing
+        for def in mt.radars.iter().filter(|r| r.tp != RadarType::Building) {
+            let radar = commands
+                .spawn(
+                    def.bundle(RadarConsumer {
+                        flying_target: def
+                            .target_object
+                            .as_ref()
+                            .and_then(|o| scob.0.get(o.as_str()))
+                            .and_then(|e| q_found_transforms.get(*e).ok())
+                            .map(|t| t.translation),
                         target_mailbox: Some(ghost),
-                    },
-                    0.1,
-                    80.0,
-                    RadarType::Maintenance,
-                ),
-                // VoxelMailbox(default()),
-            ))
-            .id();
-
-        commands
-            .entity(ghost)
-            .push_children(&[fuel_radar, maintenance_radar]);
+                    }),
+                )
+                .id();
+
+            commands.entity(ghost).add_child(radar);
+            radars.push((def.tp, radar));
+        }
+
+        let radar = |tp| radars.iter().find(|(t, _)| *t == tp).map(|(_, e)| *e);
+
+        let work_radar = || {
+            radar(RadarType::Work).unwrap_or_else(|| {
+                warn!("{} has no work radar", mt.name);
+                Entity::PLACEHOLDER
+            })
+        };
+
+        // conveyors run without fuel
+        let fuel_radar = radar(RadarType::Fuel).unwrap_or(Entity::PLACEHOLDER);
 
         let set = match mc.gmt {
-            GameMachineSettingsDiscriminants::Recycler => {
-                let recycling_radar = commands
-                    .spawn((
-                        Name::new("recycling radar"),
-                        RadarBundle::new(
-                            GameMaterial::all(),
-                            Some(Direction2D::Forward),
-                            RadarConsumer {
-                                flying_target: scob
-                                    .0
-                                    .get("RecyclingTarget")
-                                    .and_then(|e| q_found_transforms.get(*e).ok())
-                                    .map(|t| t.translation),
-                                target_mailbox: Some(ghost),
-                            },
-                            mt.work_radar_speed,
-                            10.0,
-                            RadarType::Work,
-                        ),
-                    ))
-                    .id();
-
-                commands.entity(ghost).add_child(recycling_radar);
-
-                GameMachineSettings::Recycler { recycling_radar }
-            }
+            GameMachineSettingsDiscriminants::Recycler => GameMachineSettings::Recycler {
+                recycling_radar: work_radar(),
+            },
             GameMachineSettingsDiscriminants::Plower => {
-                let plowing_radar = commands
-                    .spawn((
-                        Name::new("plowing radar"),
-                        RadarBundle::new(
-                            GameMaterial::all(),
-                            Some(Direction2D::Forward),
-                            RadarConsumer {
-                                flying_target: None,
-                                target_mailbox: Some(ghost),
-                            },
-                            mt.work_radar_speed,
-                            3.0,
-                            RadarType::Work,
-                        ),
-                    ))
-                    .id();
-
-                commands.entity(ghost).add_child(plowing_radar);
-
-                commands
-                    .entity(ghost)
-                    .insert(Target::new(mc.pos + IVec2::new(10, 15)));
-
-                GameMachineSettings::Plower { plowing_radar }
+                // restored machines come with their own target
+                if !has_target {
+                    commands
+                        .entity(ghost)
+                        .insert(Target::new(mc.pos + IVec2::new(10, 15)));
+                }
+
+                GameMachineSettings::Plower {
+                    plowing_radar: work_radar(),
+                }
             }
+            GameMachineSettingsDiscriminants::Conveyor => {
+                commands.entity(ghost).insert(ConveyorSegment::new());
+
+                GameMachineSettings::Conveyor
+            }
+            GameMachineSettingsDiscriminants::Silo => GameMachineSettings::Silo {
+                gathering_radar: work_radar(),
+            },
         };
 
         commands.entity(ghost).insert(BuiltMachine {
@@ -155,15 +147,30 @@ fn consume_mailbox(
         Option<&BuiltMachine>,
         &mut MyMachine,
         &Direction2D,
+        Option<&mut SiloInventory>,
+        Option<&SupplyLink>,
     )>,
     targets: Query<&Target>,
     q_blocks: Query<&VoxelBlock>,
     q_scene_object_finder: Query<&SceneObjectsFound>,
     q_scene_object_transforms: Query<&GlobalTransform, (Without<Radar>, Without<VoxelBlock>)>,
     mut stats: ResMut<StatsValues>,
+    mut changes: ResMut<VoxelBlockChanges>,
+    mut rng: ResMut<SessionRng>,
 ) {
-    let rand = &mut rand::thread_rng();
-    for (e, mut mailbox, bm, mut mm, dir) in q_machines.iter_mut() {
+    let rand = &mut rng.0;
+
+    let positions: HashMap<Entity, IVec2> = q_machines
+        .iter()
+        .map(|(e, _, _, mm, _, _, _)| (e, mm.pos))
+        .collect();
+
+    for (e, mut mailbox, bm, mut mm, dir, silo, supply) in q_machines.iter_mut() {
+        // built conveyors carry their mailbox along themselves
+        if bm.is_some_and(|bm| bm.settings == GameMachineSettings::Conveyor) {
+            continue;
+        }
+
         let Some((_, mut vc, _)) = mailbox.0.pop_front() else {
             continue;
         };
@@ -196,15 +203,28 @@ fn consume_mailbox(
 
         match bm.settings {
             GameMachineSettings::Plower { .. } => {
+                if let Some((to, to_pos)) = SupplyLink::destination(supply, vc, mm.pos, &positions)
+                {
+                    mm.useful_ish_work_done += 1.0;
+
+                    supply_voxel(
+                        &mut commands,
+                        mm.pos.extend(3).xzy().as_vec3(),
+                        to,
+                        to_pos,
+                        vc,
+                    );
+                    continue;
+                }
+
                 let target = targets.get(e).unwrap();
                 let target = target.global_pos;
 
-                let (block_pos, local_p) =
-                    VoxelBlock::normalize_pos(IVec2::ZERO, target.extend(0).xzy());
-                let block_e = lazy_world.known_parts[&block_pos];
-                let block = q_blocks.get(block_e).unwrap();
+                let (part, local_p) = VoxelBlock::normalize_col(target);
+                let block_e = lazy_world.ground(part).unwrap();
+                let stack = lazy_world.stack_blocks(part, &q_blocks);
 
-                let y = if let Some(local_p) = block.empty_at_col(local_p.xz()) {
+                let y = if let Some(local_p) = VoxelBlock::empty_at_col(&stack, local_p) {
                     local_p.y + 3
                 } else {
                     VOXEL_BLOCK_SIZE
@@ -236,6 +256,18 @@ fn consume_mailbox(
                     }
                 }
 
+                mm.useful_ish_work_done += 1.0;
+
+                stats.inc_n("Recycled", 1);
+
+                let origin = rec_exit.unwrap_or_else(|| mm.pos.extend(3).xzy().as_vec3());
+
+                if let Some((to, to_pos)) = SupplyLink::destination(supply, vc, mm.pos, &positions)
+                {
+                    supply_voxel(&mut commands, origin, to, to_pos, vc);
+                    continue;
+                }
+
                 let back_dir = -dir;
 
                 let mut found = None;
@@ -243,29 +275,25 @@ fn consume_mailbox(
                 for i in 1.. {
                     let target = mm.pos + back_dir.random_in_cone(i / 5 + 1, mm.dims, rand);
 
-                    let (block_p, local_p) =
-                        VoxelBlock::normalize_pos(IVec2::ZERO, target.extend(0).xzy());
+                    let (part, local_p) = VoxelBlock::normalize_col(target);
 
-                    let block_e = lazy_world.known_parts[&block_p];
-                    let block = q_blocks.get(block_e).unwrap();
+                    let block_e = lazy_world.ground(part).unwrap();
+                    let stack = lazy_world.stack_blocks(part, &q_blocks);
 
-                    if let Some(local_p) = block.empty_at_col(local_p.xz()) {
-                        found = Some((block_p, local_p, block_e));
+                    if let Some(local_p) = VoxelBlock::empty_at_col(&stack, local_p) {
+                        found = Some((part, local_p, block_e));
                         break;
                     }
                 }
 
-                let (block_p, local_p, block_e) = found.unwrap();
+                let (part, local_p, block_e) = found.unwrap();
 
                 // println!("sending recycled to: {:?}", block_e);
-                let tp = VoxelBlock::real_pos(block_p, local_p).as_ivec3() + IVec3::new(0, 3, 0);
-
-                mm.useful_ish_work_done += 1.0;
-
-                stats.inc_n("Recycled", 1);
+                let tp =
+                    VoxelBlock::global_pos(VoxelBlock::ground(part), local_p) + IVec3::new(0, 3, 0);
 
                 commands.spawn(FlyingVoxel {
-                    origin: rec_exit.unwrap_or_else(|| mm.pos.extend(3).xzy().as_vec3()),
+                    origin,
                     target: tp.as_vec3(),
                     target_mailbox: block_e,
                     material: vc,
@@ -278,25 +306,77 @@ fn consume_mailbox(
                 //     gizmos.sphere(rp, Quat::IDENTITY, 3.0, Color::RED);
                 // });
             }
+            GameMachineSettings::Conveyor => unreachable!("skipped above"),
+            GameMachineSettings::Silo { .. } => {
+                let Some(mut silo) = silo else {
+                    continue;
+                };
+
+                if silo.store(vc) {
+                    stats.inc_n("Stored", 1);
+                    continue;
+                }
+
+                // full, so it spills over the back
+                changes.register_change(
+                    (mm.pos + IVec2::from(-dir) * (mm.dims.max_element() / 2 + 1))
+                        .extend(DROP_HEIGHT)
+                        .xzy(),
+                    vc,
+                );
+            }
         }
     }
 }
 
+/// straight into the linked machine's mailbox
+fn supply_voxel(
+    commands: &mut Commands,
+    origin: Vec3,
+    to: Entity,
+    to_pos: IVec2,
+    mat: GameMaterial,
+) {
+    commands.spawn(FlyingVoxel {
+        origin,
+        target: to_pos.extend(3).xzy().as_vec3(),
+        target_mailbox: to,
+        material: mat,
+        payload: (to_pos.extend(3).xzy(), RadarType::Work),
+    });
+}
+
 fn move_machines(
     lazy_world: Res<LazyWorld>,
     mut q_machines: Query<(
-        // Entity,
+        Entity,
         &BuiltMachine,
         &mut MyMachine,
         &Direction2D,
+        Option<&Waypoint>,
     )>,
     blocks: Query<'_, '_, &mut VoxelBlock, ()>,
     mut stats: ResMut<StatsValues>,
 ) {
     let mut wbw = WholeBlockWorld { lazy_world, blocks };
 
-    for (bm, mut mm, dir) in q_machines.iter_mut() {
-        if matches!(bm.settings, GameMachineSettings::Recycler { .. }) {
+    for (e, bm, mut mm, dir, waypoint) in q_machines.iter_mut() {
+        if !matches!(bm.settings, GameMachineSettings::Plower { .. }) {
+            continue;
+        }
+
+        // waits to be turned by `steer_plowers`, or for a route at all
+        if waypoint.is_some_and(|wp| wp.next_step(mm.pos) != Some(*dir)) {
+            continue;
+        }
+
+        let edge = mm.footprint.leading_edge(mm.pos, *dir);
+
+        // no driving into other machines
+        if edge
+            .iter()
+            .any(|c| wbw.column_holder(*c).is_some_and(|h| h != e))
+        {
             continue;
         }
 
@@ -308,8 +388,10 @@ fn move_machines(
 
         stats.inc_n("Fuel Consumed", mm.max_fuel as usize);
 
-        if !dir
-            .line_in_direction(mm.pos, mm.dims)
+        // the ground has to be plowed first, `claim_footprints` pushes aside what's
+        // left higher up
+        if !edge
+            .iter()
             .any(|p| wbw.get_block_value(p.extend(0).xzy()).is_full())
         {
             mm.pos += Into::<IVec2>::into(*dir);
@@ -320,11 +402,11 @@ fn move_machines(
 }
 
 fn toggle_radars(
-    mut q_machines: Query<(&mut MyMachine, &Children)>,
+    mut q_machines: Query<(&mut MyMachine, &Children, Option<&SiloInventory>)>,
     mut q_radars: Query<&mut Radar>,
     q_types: Query<&MachineType>,
 ) {
-    for (mut mm, children) in q_machines.iter_mut() {
+    for (mut mm, children, silo) in q_machines.iter_mut() {
         let Ok(mt) = q_types.get(mm.tp) else {
             continue;
         };
@@ -336,7 +418,11 @@ fn toggle_radars(
 
             let must_pause = match radar.tp {
                 RadarType::Fuel => mm.needed_maintenance > 0 || mm.fuel >= mm.max_fuel,
-                RadarType::Work => mm.needed_maintenance > 0 || mm.fuel == 0,
+                RadarType::Work => {
+                    mm.needed_maintenance > 0
+                        || (mm.max_fuel > 0 && mm.fuel == 0)
+                        || silo.is_some_and(|s| s.is_full())
+                }
                 RadarType::Maintenance => mm.needed_maintenance == 0,
                 RadarType::Building => mm.still_building == 0,
             };
@@ -347,7 +433,8 @@ fn toggle_radars(
 
             radar.paused = must_pause;
 
-            if radar.tp == RadarType::Work {
+            // machines without a tank always work at full speed
+            if radar.tp == RadarType::Work && mm.max_fuel > 0 {
                 radar.speed = (mm.fuel as f32 / mm.max_fuel as f32) * mt.work_radar_speed;
             }
 
@@ -358,14 +445,19 @@ fn toggle_radars(
     }
 }
 
-fn add_maintenance(fixed_time: Res<Time<Fixed>>, mut q_machines: Query<&mut MyMachine>) {
-    let rand = &mut rand::thread_rng();
+fn add_maintenance(
+    fixed_time: Res<Time<Fixed>>,
+    mut q_machines: Query<&mut MyMachine>,
+    mut rng: ResMut<SessionRng>,
+) {
+    let rand = &mut rng.0;
     for mut mm in q_machines.iter_mut() {
         if mm.needed_maintenance > 0 {
             continue;
         }
 
-        if mm.gmt == GameMachineSettingsDiscriminants::Recycler {
+        // only plowers wear out
+        if mm.gmt != GameMachineSettingsDiscriminants::Plower {
             continue;
         }
 
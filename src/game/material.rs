@@ -1,7 +1,8 @@
 use bevy::prelude::*;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Reflect, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Reflect, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GameMaterial {
     /// maintenance
     Reddish = 0b1,
@@ -13,6 +14,9 @@ pub enum GameMaterial {
     // Golden = 0b10000,
 }
 
+/// how many `GameMaterial`s there are, for per-material arrays
+pub const MATERIAL_COUNT: usize = 4;
+
 impl From<&GameMaterial> for Color {
     fn from(val: &GameMaterial) -> Self {
         match val {
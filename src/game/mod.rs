@@ -5,25 +5,40 @@ use std::{
 
 use bevy::{
     app::Plugin,
-    math::{IVec2, Quat, Vec2Swizzles},
+    math::{IVec2, Quat, Vec2},
     prelude::Component,
     reflect::Reflect,
 };
+use serde::{Deserialize, Serialize};
 
+pub mod conveyor;
+pub mod footprint;
 pub mod machines;
 pub mod material;
+pub mod save;
+pub mod seed;
+pub mod silo;
+pub mod speed;
+pub mod supply;
 pub mod voxelmailbox;
+pub mod waypoint;
 
 pub struct GameUtilsPlugin;
 
 impl Plugin for GameUtilsPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        app.register_type::<Direction2D>()
-            .add_plugins(machines::MachinesPlugin);
+        app.register_type::<Direction2D>().add_plugins((
+            seed::SeedPlugin,
+            machines::MachinesPlugin,
+            conveyor::ConveyorPlugin,
+            footprint::FootprintPlugin,
+            supply::SupplyPlugin,
+            waypoint::WaypointPlugin,
+        ));
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Component)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Reflect, Component, Serialize, Deserialize)]
 pub enum Direction2D {
     Forward = 0,
     Right = 1,
@@ -86,10 +101,32 @@ impl Direction2D {
         ((self as usize) + 1).into()
     }
 
-    pub fn rotate_size(self, size: IVec2) -> IVec2 {
+    /// turns a vector in a machine's frame the same way `Quat::from` turns its scene
+    pub fn rotate_vec(self, v: IVec2) -> IVec2 {
         match self {
-            Direction2D::Backward | Direction2D::Forward => size,
-            Direction2D::Left | Direction2D::Right => size.yx(),
+            Direction2D::Forward => v,
+            Direction2D::Left => IVec2::new(v.y, -v.x),
+            Direction2D::Backward => -v,
+            Direction2D::Right => IVec2::new(-v.y, v.x),
+        }
+    }
+
+    pub fn rotate_vec2(self, v: Vec2) -> Vec2 {
+        match self {
+            Direction2D::Forward => v,
+            Direction2D::Left => Vec2::new(v.y, -v.x),
+            Direction2D::Backward => -v,
+            Direction2D::Right => Vec2::new(-v.y, v.x),
+        }
+    }
+
+    /// back into the machine's frame
+    pub fn unrotate_vec(self, v: IVec2) -> IVec2 {
+        match self {
+            Direction2D::Forward => v,
+            Direction2D::Left => IVec2::new(-v.y, v.x),
+            Direction2D::Backward => -v,
+            Direction2D::Right => IVec2::new(v.y, -v.x),
         }
     }
 
@@ -113,22 +150,4 @@ impl Direction2D {
             }
         }
     }
-
-    pub fn line_in_direction(self, center: IVec2, size: IVec2) -> impl Iterator<Item = IVec2> {
-        let width = match self {
-            Direction2D::Forward | Direction2D::Backward => size.x,
-            Direction2D::Left | Direction2D::Right => size.y,
-        };
-        let start = match self {
-            Direction2D::Forward => center + IVec2::new(-width / 2, -size.y / 2),
-            Direction2D::Backward => center + IVec2::new(-width / 2, size.y / 2),
-            Direction2D::Left => center + IVec2::new(-size.x / 2, -width / 2),
-            Direction2D::Right => center + IVec2::new(size.x / 2, -width / 2),
-        };
-
-        (0..width).map(move |i| match self {
-            Direction2D::Forward | Direction2D::Backward => start + IVec2::new(i, 0),
-            Direction2D::Left | Direction2D::Right => start + IVec2::new(0, i),
-        })
-    }
 }
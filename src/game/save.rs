@@ -0,0 +1,743 @@
+use std::{borrow::Cow, path::PathBuf, time::Duration};
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    conf::Configuration,
+    graphics::{
+        flyingvoxel::FlyingVoxel,
+        gamemenu::{
+            tutorial::{restore_tutorial_state, tutorial_state},
+            GameMenu, GameMenuState,
+        },
+        machines::{
+            building::{machine_bundle, place_machine, MachineCounter, MachineGhost},
+            radar::{Radar, RadarType},
+            targets::{Target, TargetInst},
+            MachineType, MyMachine,
+        },
+        selectable::{CurrentlySelected, Selectable},
+        stats::StatsValues,
+        voxels3d::{
+            changes::VoxelBlockChanges, lazyworld::LazyWorld, paging::StoredPart, VoxelBlock,
+            VoxelGraphics,
+        },
+    },
+};
+
+use super::{
+    machines::GameMachineSettingsDiscriminants,
+    material::GameMaterial,
+    seed::{SessionRng, WorldSeed},
+    silo::SiloInventory,
+    supply::SupplyLink,
+    voxelmailbox::VoxelMailbox,
+    waypoint::Waypoint,
+    Direction2D,
+};
+
+/// bump this whenever `SaveGame` changes shape
+pub const SAVE_VERSION: u32 = 4;
+
+pub struct SavePlugin;
+
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SaveGameRequest>()
+            .add_event::<LoadGameRequest>()
+            .add_systems(Update, (handle_keyboard, save_game, restore_radars))
+            // before anything in Update gets to see half of the old world
+            .add_systems(PreUpdate, load_game);
+    }
+}
+
+#[derive(Event)]
+pub struct SaveGameRequest(pub PathBuf);
+
+#[derive(Event)]
+pub struct LoadGameRequest(pub PathBuf);
+
+pub fn quicksave_path() -> PathBuf {
+    PathBuf::from("saves").join("quicksave.json")
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SaveGame {
+    pub version: u32,
+    /// parts that were never visited will still generate the same way
+    pub seed: u64,
+    /// every chunk of spawned and paged out parts alike
+    pub parts: Vec<StoredPart>,
+    /// global positions, including whatever was still waiting in block mailboxes
+    pub pending_changes: Vec<(IVec3, GameMaterial)>,
+    pub machines: Vec<SavedMachine>,
+    pub flying: Vec<SavedFlyingVoxel>,
+    pub stats: Vec<(String, usize)>,
+    pub machine_counter: Vec<(GameMachineSettingsDiscriminants, usize)>,
+    pub tutorial: Vec<(String, bool)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedMachine {
+    pub name: String,
+    pub gmt: GameMachineSettingsDiscriminants,
+    pub pos: IVec2,
+    pub direction: Direction2D,
+    pub fuel: u8,
+    pub needed_maintenance: u8,
+    pub still_building: u8,
+    pub useful_ish_work_done: f32,
+    pub target: Option<IVec2>,
+    pub mailbox: Vec<(IVec3, GameMaterial, RadarType)>,
+    pub radars: Vec<SavedRadar>,
+    #[serde(default)]
+    pub silo: Option<SiloInventory>,
+    #[serde(default)]
+    pub supply: Option<SavedSupplyLink>,
+    /// where a Plower was driving itself to, the route gets planned again
+    #[serde(default)]
+    pub waypoint: Option<IVec2>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SavedSupplyLink {
+    /// index into `SaveGame::machines`
+    pub to: usize,
+    pub material_mask: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedRadar {
+    pub tp: RadarType,
+    pub elapsed: f32,
+    pub material_mask: u8,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum SavedMailbox {
+    /// the chunk, see `VoxelBlock::pos`
+    Block(IVec3),
+    /// index into `SaveGame::machines`
+    Machine(usize),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedFlyingVoxel {
+    pub origin: Vec3,
+    pub target: Vec3,
+    pub mailbox: SavedMailbox,
+    pub material: GameMaterial,
+    pub payload: (IVec3, RadarType),
+}
+
+/// radar state to put back once the machine has (re)created its radars
+#[derive(Component)]
+struct RestoredRadars(Vec<SavedRadar>);
+
+fn handle_keyboard(
+    keys: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
+    mut save: EventWriter<SaveGameRequest>,
+    mut load: EventWriter<LoadGameRequest>,
+) {
+    if keys.just_pressed(conf.keys.quicksave) {
+        save.send(SaveGameRequest(quicksave_path()));
+    }
+
+    if keys.just_pressed(conf.keys.quickload) {
+        load.send(LoadGameRequest(quicksave_path()));
+    }
+}
+
+fn save_game(
+    mut requests: EventReader<SaveGameRequest>,
+    lazy_world: Res<LazyWorld>,
+    q_blocks: Query<(&VoxelBlock, &VoxelMailbox)>,
+    changes: Res<VoxelBlockChanges>,
+    q_machines: Query<
+        (
+            Entity,
+            &Name,
+            &MyMachine,
+            &Direction2D,
+            &VoxelMailbox,
+            Option<&Target>,
+            Option<&Children>,
+            Option<&SiloInventory>,
+            Option<&Waypoint>,
+        ),
+        With<Selectable>,
+    >,
+    q_radars: Query<&Radar>,
+    q_links: Query<(Entity, &SupplyLink)>,
+    q_flying: Query<&FlyingVoxel>,
+    stats: Res<StatsValues>,
+    counter: Res<MachineCounter>,
+    seed: Res<WorldSeed>,
+) {
+    let Some(SaveGameRequest(path)) = requests.read().last() else {
+        return;
+    };
+
+    let mut parts = vec![];
+    let mut pending_changes = vec![];
+
+    for (_, &e) in lazy_world.known_parts.iter() {
+        let Ok((block, mailbox)) = q_blocks.get(e) else {
+            continue;
+        };
+
+        parts.push(StoredPart::new(block));
+        pending_changes.extend(mailbox.0.iter().map(|(pos, mat, _)| (*pos, *mat)));
+    }
+
+    parts.extend(lazy_world.paged_out.values().flatten().cloned());
+
+    for (block_pos, ch) in changes.added.iter() {
+        pending_changes.extend(ch.iter().map(|(local_pos, mat)| {
+            let ground = VoxelBlock::ground(*block_pos);
+
+            (VoxelBlock::global_pos(ground, *local_pos), *mat)
+        }));
+    }
+
+    let mut machine_ids = HashMap::new();
+    let mut machines = vec![];
+
+    for (e, name, mm, dir, mailbox, target, children, silo, waypoint) in q_machines.iter() {
+        machine_ids.insert(e, machines.len());
+
+        let radars = children
+            .map(|ch| q_radars.iter_many(ch).collect::<Vec<_>>())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|r| SavedRadar {
+                tp: r.tp,
+                elapsed: r.watch.elapsed_secs(),
+                material_mask: r.material_mask,
+            })
+            .collect();
+
+        machines.push(SavedMachine {
+            name: name.to_string(),
+            gmt: mm.gmt,
+            pos: mm.pos,
+            direction: *dir,
+            fuel: mm.fuel,
+            needed_maintenance: mm.needed_maintenance,
+            still_building: mm.still_building,
+            useful_ish_work_done: mm.useful_ish_work_done,
+            target: target.map(|t| t.global_pos),
+            mailbox: mailbox.0.iter().copied().collect(),
+            radars,
+            silo: silo.cloned(),
+            supply: None,
+            waypoint: waypoint.map(|w| w.goal),
+        });
+    }
+
+    // now that every machine has its index
+    for (e, link) in q_links.iter() {
+        if let (Some(&from), Some(&to)) = (machine_ids.get(&e), machine_ids.get(&link.to)) {
+            machines[from].supply = Some(SavedSupplyLink {
+                to,
+                material_mask: link.material_mask,
+            });
+        }
+    }
+
+    let flying = q_flying
+        .iter()
+        .filter_map(|fv| {
+            let mailbox = if let Some(i) = machine_ids.get(&fv.target_mailbox) {
+                SavedMailbox::Machine(*i)
+            } else {
+                // whatever it was flying to (e.g. a ghost) is not saved
+                let (block, _) = q_blocks.get(fv.target_mailbox).ok()?;
+                SavedMailbox::Block(block.pos)
+            };
+
+            Some(SavedFlyingVoxel {
+                origin: fv.origin,
+                target: fv.target,
+                mailbox,
+                material: fv.material,
+                payload: fv.payload,
+            })
+        })
+        .collect();
+
+    let save = SaveGame {
+        version: SAVE_VERSION,
+        seed: seed.0,
+        parts,
+        pending_changes,
+        machines,
+        flying,
+        stats: stats.0.iter().map(|(k, v)| (k.to_string(), *v)).collect(),
+        machine_counter: counter.0.iter().map(|(k, v)| (*k, *v)).collect(),
+        tutorial: tutorial_state()
+            .into_iter()
+            .map(|(k, v)| (k.to_owned(), v))
+            .collect(),
+    };
+
+    let written = serde_json::to_string(&save)
+        .map_err(|e| e.to_string())
+        .and_then(|json| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+            }
+            std::fs::write(path, json).map_err(|e| e.to_string())
+        });
+
+    match written {
+        Ok(()) => info!("saved the game to {}", path.display()),
+        Err(e) => warn!("failed to save the game to {}: {e}", path.display()),
+    }
+}
+
+fn load_game(
+    mut commands: Commands,
+    mut requests: EventReader<LoadGameRequest>,
+    q_old: Query<
+        Entity,
+        Or<(
+            With<VoxelBlock>,
+            With<MyMachine>,
+            With<FlyingVoxel>,
+            With<TargetInst>,
+        )>,
+    >,
+    q_types: Query<(Entity, &MachineType)>,
+    mut lazy_world: ResMut<LazyWorld>,
+    mut changes: ResMut<VoxelBlockChanges>,
+    mut graphics: VoxelGraphics,
+    mut stats: ResMut<StatsValues>,
+    mut counter: ResMut<MachineCounter>,
+    mut ghost: ResMut<MachineGhost>,
+    mut selected: ResMut<CurrentlySelected>,
+    mut menu_state: ResMut<GameMenu>,
+    mut seed: ResMut<WorldSeed>,
+    mut rng: ResMut<SessionRng>,
+) {
+    let Some(LoadGameRequest(path)) = requests.read().last() else {
+        return;
+    };
+
+    let save = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str::<SaveGame>(&json).map_err(|e| e.to_string()));
+
+    let save = match save {
+        Ok(save) if save.version == SAVE_VERSION => save,
+        Ok(save) => {
+            warn!(
+                "can't load {}: save version {} is not {SAVE_VERSION}",
+                path.display(),
+                save.version
+            );
+            return;
+        }
+        Err(e) => {
+            warn!("failed to load the game from {}: {e}", path.display());
+            return;
+        }
+    };
+
+    for e in q_old.iter() {
+        commands.entity(e).despawn_recursive();
+    }
+
+    ghost.0 = None;
+    selected.0 = None;
+    menu_state.0 = GameMenuState::ToPickBuilding;
+
+    *seed = WorldSeed(save.seed);
+    *rng = SessionRng::new(*seed);
+
+    lazy_world.known_parts.clear();
+    changes.added.clear();
+    changes.wanted.clear();
+
+    lazy_world.paged_out.clear();
+
+    for part in save.parts.iter() {
+        let Some(bundle) = part.restore(&mut graphics) else {
+            warn!("corrupted part {:?} in the save, skipping", part.pos);
+            continue;
+        };
+
+        lazy_world
+            .known_parts
+            .insert(part.pos, commands.spawn(bundle).id());
+    }
+
+    for (pos, mat) in save.pending_changes.iter() {
+        changes.register_change(*pos, *mat);
+    }
+
+    let mut machines = vec![];
+
+    let links = save
+        .machines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, sm)| sm.supply.map(|link| (i, link)))
+        .collect::<Vec<_>>();
+
+    for sm in save.machines {
+        let Some((tp, mt)) = q_types.iter().find(|(_, mt)| mt.gmt == sm.gmt) else {
+            warn!("unknown machine type {:?} in the save, skipping", sm.gmt);
+            machines.push(None);
+            continue;
+        };
+
+        let mut mm = MyMachine::new(tp, mt, sm.pos);
+        mm.fuel = sm.fuel;
+        mm.needed_maintenance = sm.needed_maintenance;
+        mm.still_building = sm.still_building;
+        mm.useful_ish_work_done = sm.useful_ish_work_done;
+
+        let e = commands
+            .spawn(machine_bundle(sm.name.clone(), mt, mm, sm.direction))
+            .insert(VoxelMailbox(sm.mailbox.into()))
+            .id();
+
+        place_machine(&mut commands, e, mt, sm.name);
+
+        if let Some(silo) = sm.silo {
+            commands.entity(e).insert(silo);
+        }
+
+        if let Some(target) = sm.target {
+            commands.entity(e).insert(Target::new(target));
+        }
+
+        if let Some(goal) = sm.waypoint {
+            commands.entity(e).insert(Waypoint::new(goal));
+        }
+
+        if !sm.radars.is_empty() {
+            commands.entity(e).insert(RestoredRadars(sm.radars));
+        }
+
+        machines.push(Some(e));
+    }
+
+    for (from, link) in links {
+        let (Some(from), Some(to)) = (machines[from], machines.get(link.to).copied().flatten())
+        else {
+            continue;
+        };
+
+        commands.entity(from).insert(SupplyLink {
+            to,
+            material_mask: link.material_mask,
+        });
+    }
+
+    for fv in save.flying {
+        let target_mailbox = match fv.mailbox {
+            SavedMailbox::Block(pos) => lazy_world.known_parts.get(&pos).copied(),
+            SavedMailbox::Machine(i) => machines.get(i).copied().flatten(),
+        };
+
+        let Some(target_mailbox) = target_mailbox else {
+            continue;
+        };
+
+        commands.spawn(FlyingVoxel {
+            origin: fv.origin,
+            target: fv.target,
+            target_mailbox,
+            material: fv.material,
+            payload: fv.payload,
+        });
+    }
+
+    stats.0 = save
+        .stats
+        .into_iter()
+        .map(|(k, v)| (Cow::Owned(k), v))
+        .collect();
+    counter.0 = save.machine_counter.into_iter().collect();
+    restore_tutorial_state(&save.tutorial);
+
+    info!("loaded the game from {}", path.display());
+}
+
+fn restore_radars(
+    mut commands: Commands,
+    mut q_machines: Query<(Entity, &mut RestoredRadars, &Children)>,
+    mut q_radars: Query<&mut Radar>,
+) {
+    for (e, mut restored, children) in q_machines.iter_mut() {
+        for ch in children {
+            let Ok(mut radar) = q_radars.get_mut(*ch) else {
+                continue;
+            };
+
+            let Some(i) = restored.0.iter().position(|r| r.tp == radar.tp) else {
+                continue;
+            };
+
+            let saved = restored.0.swap_remove(i);
+
+            radar
+                .watch
+                .set_elapsed(Duration::from_secs_f32(saved.elapsed));
+            radar.material_mask = saved.material_mask;
+        }
+
+        if restored.0.is_empty() {
+            commands.entity(e).remove::<RestoredRadars>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{
+        ecs::system::CommandQueue,
+        prelude::*,
+        utils::{HashMap, Instant},
+    };
+
+    use crate::{
+        game::{
+            machines::GameMachineSettingsDiscriminants,
+            material::GameMaterial,
+            seed::{SessionRng, WorldSeed},
+            supply::SupplyLink,
+            waypoint::Waypoint,
+            Direction2D,
+        },
+        graphics::{
+            gamemenu::{GameMenu, GameMenuState},
+            machines::{
+                building::{machine_bundle, place_machine, MachineCounter, MachineGhost},
+                definition::{MachineDefinition, MACHINE_DEFINITIONS},
+                radar::Radar,
+                targets::Target,
+                MachineType, MyMachine,
+            },
+            selectable::CurrentlySelected,
+            stats::StatsValues,
+            voxels3d::{
+                changes::VoxelBlockChanges, generate_voxel_block, lazyworld::LazyWorld,
+                paging::StoredPart, VoxelBlock, VoxelGraphics,
+            },
+        },
+    };
+
+    use super::{load_game, restore_radars, save_game, LoadGameRequest, SaveGameRequest};
+
+    fn app() -> App {
+        let mut app = App::new();
+        let seed = WorldSeed(3);
+
+        app.add_event::<SaveGameRequest>()
+            .add_event::<LoadGameRequest>()
+            .insert_resource(LazyWorld {
+                known_parts: default(),
+                paged_out: default(),
+            })
+            .insert_resource(VoxelBlockChanges::default())
+            .insert_resource(StatsValues::new())
+            .insert_resource(MachineCounter(default()))
+            .insert_resource(MachineGhost(None, false, Instant::now()))
+            .insert_resource(CurrentlySelected(None))
+            .insert_resource(GameMenu(GameMenuState::ToPickBuilding))
+            .insert_resource(seed)
+            .insert_resource(SessionRng::new(seed))
+            .add_systems(Update, (save_game, restore_radars))
+            .add_systems(PreUpdate, load_game);
+
+        app
+    }
+
+    /// a part with a few voxels, something waiting to land on it, a Plower
+    /// headed somewhere and sending to a Silo
+    fn populate(world: &mut World) {
+        let mut graphics = VoxelGraphics {
+            meshes: None,
+            voxel_resources: None,
+        };
+
+        let mut bundle = generate_voxel_block(VoxelBlock::ground(IVec2::ZERO), &mut graphics);
+        bundle.voxel_block.forbid_column(IVec2::new(9, 9));
+
+        for (y, mat) in GameMaterial::all().iter().enumerate() {
+            bundle
+                .voxel_block
+                ._add_block(IVec3::new(3, y as i32, 4), *mat);
+        }
+
+        let block = world.spawn(bundle).id();
+
+        world
+            .resource_mut::<LazyWorld>()
+            .known_parts
+            .insert(VoxelBlock::ground(IVec2::ZERO), block);
+
+        world
+            .resource_mut::<VoxelBlockChanges>()
+            .register_change(IVec3::new(5, 40, 6), GameMaterial::Blueish);
+
+        let types = MACHINE_DEFINITIONS
+            .iter()
+            .map(|path| {
+                let def = MachineDefinition::read(path).unwrap();
+                let mt = MachineType::new(&def, Handle::default(), Handle::default());
+
+                (mt.gmt, (world.spawn_empty().id(), mt))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, world);
+        let mut spawn = |gmt, name: &str, pos| {
+            let (tp, mt) = &types[&gmt];
+            let mut mm = MyMachine::new(*tp, mt, pos);
+            mm.fuel = 2;
+            mm.useful_ish_work_done = 1.5;
+
+            let e = commands
+                .spawn(machine_bundle(
+                    name.into(),
+                    mt,
+                    mm,
+                    Direction2D::from(1usize),
+                ))
+                .id();
+
+            place_machine(&mut commands, e, mt, name.into());
+
+            e
+        };
+
+        let plower = spawn(
+            GameMachineSettingsDiscriminants::Plower,
+            "Plower (1)",
+            IVec2::new(40, 2),
+        );
+        let silo = spawn(
+            GameMachineSettingsDiscriminants::Silo,
+            "Silo (1)",
+            IVec2::new(50, 10),
+        );
+
+        commands.entity(plower).insert((
+            Target::new(IVec2::new(30, 30)),
+            Waypoint::new(IVec2::new(60, -4)),
+            SupplyLink {
+                to: silo,
+                material_mask: 0b101,
+            },
+        ));
+
+        queue.apply(world);
+
+        for (tp, mt) in types.into_values() {
+            world.entity_mut(tp).insert(mt);
+        }
+
+        for mut radar in world.query::<&mut Radar>().iter_mut(world) {
+            radar.material_mask = 0b110;
+        }
+    }
+
+    /// the grids, what's waiting to land and the machines, in a fixed order
+    fn snapshot(world: &mut World) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut parts = world
+            .query::<&VoxelBlock>()
+            .iter(world)
+            .map(|block| serde_json::to_string(&StoredPart::new(block)).unwrap())
+            .collect::<Vec<_>>();
+        parts.sort();
+
+        let mut pending = world
+            .resource::<VoxelBlockChanges>()
+            .added
+            .iter()
+            .flat_map(|(part, ch)| ch.iter().map(move |change| format!("{part} {change:?}")))
+            .collect::<Vec<_>>();
+        pending.sort();
+
+        let names = world
+            .query::<(Entity, &Name)>()
+            .iter(world)
+            .map(|(e, name)| (e, name.to_string()))
+            .collect::<HashMap<_, _>>();
+
+        let mut radars = world.query::<&Radar>();
+        let mut machines = vec![];
+
+        let mut q_machines = world.query::<(
+            &Name,
+            &MyMachine,
+            &Direction2D,
+            Option<&Target>,
+            Option<&Waypoint>,
+            Option<&SupplyLink>,
+            Option<&Children>,
+        )>();
+
+        for (name, mm, dir, target, waypoint, link, children) in q_machines.iter(world) {
+            let mut masks = children
+                .map(|ch| radars.iter_many(world, ch).collect::<Vec<_>>())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|r| format!("{:?} {}", r.tp, r.material_mask))
+                .collect::<Vec<_>>();
+            masks.sort();
+
+            machines.push(format!(
+                "{name} {:?} at {} facing {dir:?}, fuel {}, maintenance {}, building {}, work {}, target {:?}, waypoint {:?}, supplies {:?}, radars {masks:?}",
+                mm.gmt,
+                mm.pos,
+                mm.fuel,
+                mm.needed_maintenance,
+                mm.still_building,
+                mm.useful_ish_work_done,
+                target.map(|t| t.global_pos),
+                waypoint.map(|w| w.goal),
+                link.map(|l| (&names[&l.to], l.material_mask)),
+            ));
+        }
+        machines.sort();
+
+        (parts, pending, machines)
+    }
+
+    #[test]
+    fn saved_game_loads_back_the_same() {
+        let path = std::env::temp_dir().join(format!("trashure-save-{}.json", std::process::id()));
+        let mut app = app();
+
+        populate(&mut app.world);
+        app.update();
+
+        let saved = snapshot(&mut app.world);
+
+        assert!(saved.2.iter().all(|m| m.contains("radars [\"")));
+
+        app.world.send_event(SaveGameRequest(path.clone()));
+        app.update();
+
+        // so there's something to undo
+        app.world.resource_mut::<VoxelBlockChanges>().added.clear();
+
+        app.world.send_event(LoadGameRequest(path.clone()));
+        app.update();
+        app.update();
+
+        let loaded = snapshot(&mut app.world);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved, loaded);
+    }
+}
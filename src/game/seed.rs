@@ -0,0 +1,96 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+pub struct SeedPlugin;
+
+impl Plugin for SeedPlugin {
+    fn build(&self, app: &mut App) {
+        // someone (e.g. a test) may have picked the seed already
+        if !app.world.contains_resource::<WorldSeed>() {
+            let seed = std::env::var("TRASHURE_SEED")
+                .ok()
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_else(rand::random);
+
+            app.insert_resource(WorldSeed(seed));
+        }
+
+        let seed = *app.world.resource::<WorldSeed>();
+
+        info!("world seed: {}", seed.0);
+
+        app.insert_resource(SessionRng::new(seed))
+            .configure_sets(
+                Update,
+                (
+                    SessionRngSet::Mailboxes,
+                    SessionRngSet::Footprints,
+                    SessionRngSet::Conveyors,
+                    SessionRngSet::Deconstruction,
+                    SessionRngSet::RadarConsumption,
+                    SessionRngSet::FlyingVoxels,
+                    SessionRngSet::Changes,
+                )
+                    .chain(),
+            )
+            .configure_sets(
+                FixedUpdate,
+                (SessionRngSet::Maintenance, SessionRngSet::RadarSearch).chain(),
+            );
+    }
+}
+
+/// Everything random in the game comes from this seed: world generation through
+/// `WorldSeed::part_rng`, everything else through `SessionRng`.
+#[derive(Debug, Resource, Clone, Copy, PartialEq, Eq)]
+pub struct WorldSeed(pub u64);
+
+/// The rng for the simulation (settling, radars, recycling, ...). Systems using
+/// it go into a `SessionRngSet`, so they draw from it in the same order every
+/// frame.
+#[derive(Resource, Deref, DerefMut)]
+pub struct SessionRng(pub StdRng);
+
+/// One per system drawing from `SessionRng`, chained in the order listed; the
+/// executor would otherwise pick a different order every frame.
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionRngSet {
+    // Update
+    Mailboxes,
+    Footprints,
+    Conveyors,
+    Deconstruction,
+    RadarConsumption,
+    FlyingVoxels,
+    Changes,
+    // FixedUpdate
+    Maintenance,
+    RadarSearch,
+}
+
+impl SessionRng {
+    pub fn new(seed: WorldSeed) -> Self {
+        SessionRng(StdRng::seed_from_u64(splitmix64(seed.0)))
+    }
+}
+
+impl WorldSeed {
+    /// same part always gets the same rng, no matter when it's generated
+    pub fn part_rng(&self, part: IVec2) -> StdRng {
+        StdRng::seed_from_u64(self.hash(0, part))
+    }
+
+    /// a well-mixed hash of the seed, a `salt` and a point
+    pub fn hash(&self, salt: u64, p: IVec2) -> u64 {
+        let coords = ((p.x as u32 as u64) << 32) | p.y as u32 as u64;
+
+        splitmix64(self.0 ^ splitmix64(salt ^ splitmix64(coords)))
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
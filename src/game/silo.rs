@@ -0,0 +1,80 @@
+//! Silos keep what their radar gathers, and hand it out to other machines' radars.
+
+use bevy::prelude::*;
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use super::material::{GameMaterial, MATERIAL_COUNT};
+
+/// What a silo holds, by `GameMaterial::as_usize`.
+#[derive(Debug, Clone, Component, Serialize, Deserialize)]
+pub struct SiloInventory {
+    pub stored: [u32; MATERIAL_COUNT],
+    pub capacity: u32,
+}
+
+impl SiloInventory {
+    pub fn new(capacity: u32) -> Self {
+        Self {
+            stored: [0; MATERIAL_COUNT],
+            capacity,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.stored.iter().sum()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.total() >= self.capacity
+    }
+
+    /// voxels matching the material mask
+    pub fn count(&self, mask: u8) -> u32 {
+        GameMaterial::all()
+            .iter()
+            .filter(|m| m.mask_contains(mask))
+            .map(|m| self.stored[m.as_usize()])
+            .sum()
+    }
+
+    /// `false` if there's no room left
+    pub fn store(&mut self, mat: GameMaterial) -> bool {
+        if self.is_full() {
+            return false;
+        }
+
+        self.stored[mat.as_usize()] += 1;
+        true
+    }
+
+    /// `false` if it ran out meanwhile
+    pub fn take(&mut self, mat: GameMaterial) -> bool {
+        let n = &mut self.stored[mat.as_usize()];
+
+        if *n == 0 {
+            return false;
+        }
+
+        *n -= 1;
+        true
+    }
+
+    /// a random stored material matching the mask, the more of it the likelier
+    pub fn pick(&self, mask: u8, rand: &mut impl Rng) -> Option<GameMaterial> {
+        GameMaterial::all()
+            .iter()
+            .filter(|m| m.mask_contains(mask))
+            .collect::<Vec<_>>()
+            .choose_weighted(rand, |m| self.stored[m.as_usize()])
+            .ok()
+            .map(|m| **m)
+    }
+
+    /// everything in it, one by one
+    pub fn contents(&self) -> impl Iterator<Item = GameMaterial> + '_ {
+        GameMaterial::all()
+            .iter()
+            .flat_map(|m| std::iter::repeat(*m).take(self.stored[m.as_usize()] as usize))
+    }
+}
@@ -0,0 +1,104 @@
+//! How fast the game runs. Everything simulated goes by `Time<Virtual>`, and so
+//! does `FixedUpdate` (`radar_search`, `add_maintenance`), which just runs more
+//! often at higher speeds with the same timestep. A machine gets just as much done
+//! per simulated second at 4x; the camera and the UI go by `Time<Real>` instead.
+
+use bevy::prelude::*;
+
+use crate::conf::{Configuration, KeyAction};
+
+/// what the speed keys switch between
+pub const GAME_SPEEDS: [(KeyAction, f32); 3] = [
+    (KeyAction::Speed1, 1.0),
+    (KeyAction::Speed2, 2.0),
+    (KeyAction::Speed4, 4.0),
+];
+
+pub struct GameSpeedPlugin;
+
+impl Plugin for GameSpeedPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(GameSpeed::default())
+            .add_systems(Update, handle_keyboard)
+            // `Time<Virtual>` has just been updated in `First`, `FixedUpdate` is next
+            .add_systems(PreUpdate, apply_game_speed);
+    }
+}
+
+#[derive(Debug, Resource)]
+pub struct GameSpeed {
+    pub speed: f32,
+    pub paused: bool,
+    step: bool,
+}
+
+impl Default for GameSpeed {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            paused: false,
+            step: false,
+        }
+    }
+}
+
+impl GameSpeed {
+    /// advances a paused game by one `FixedUpdate` timestep next frame
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.step = true;
+    }
+
+    pub fn describe(&self) -> String {
+        if self.paused {
+            "Paused".into()
+        } else {
+            format!("{}x", self.speed)
+        }
+    }
+}
+
+fn handle_keyboard(
+    keys: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
+    mut speed: ResMut<GameSpeed>,
+) {
+    if keys.just_pressed(conf.keys.pause) {
+        speed.paused = !speed.paused;
+    }
+
+    // one `FixedUpdate` timestep further, while paused
+    if keys.just_pressed(conf.keys.step) {
+        speed.step();
+    }
+
+    for (action, s) in GAME_SPEEDS {
+        if keys.just_pressed(conf.keys.key(action)) {
+            speed.speed = s;
+            speed.paused = false;
+        }
+    }
+}
+
+fn apply_game_speed(
+    mut speed: ResMut<GameSpeed>,
+    mut virt: ResMut<Time<Virtual>>,
+    fixed: Res<Time<Fixed>>,
+) {
+    if virt.relative_speed() != speed.speed {
+        virt.set_relative_speed(speed.speed);
+    }
+
+    if speed.paused != virt.is_paused() {
+        if speed.paused {
+            virt.pause();
+        } else {
+            virt.unpause();
+        }
+    }
+
+    // the paused clock stood still this frame, so this is the whole delta
+    if std::mem::take(&mut speed.step) && virt.is_paused() {
+        virt.advance_by(fixed.timestep());
+    }
+}
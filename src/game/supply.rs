@@ -0,0 +1,139 @@
+//! Supply links: a machine's output of some materials flies straight to another
+//! machine, e.g. a recycler's Reddish to a plower that needs maintenance far away.
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::graphics::{machines::MyMachine, selectable::CurrentlySelected};
+
+use super::material::GameMaterial;
+
+/// columns between two machines for a link to carry anything
+pub const SUPPLY_RANGE: f32 = 48.0;
+
+pub struct SupplyPlugin;
+
+impl Plugin for SupplyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, drop_dangling_links);
+    }
+}
+
+/// Linking machines by clicking them and drawing the links, not for headless runs.
+pub struct SupplyControlsPlugin;
+
+impl Plugin for SupplyControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LinkingSupply(None))
+            .add_systems(Update, (finish_linking, draw_links));
+    }
+}
+
+/// Sends the output matching `material_mask` to the `to` machine's `VoxelMailbox`.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct SupplyLink {
+    pub to: Entity,
+    pub material_mask: u8,
+}
+
+impl SupplyLink {
+    pub fn new(to: Entity) -> Self {
+        Self {
+            to,
+            // maintenance is what they're usually for
+            material_mask: GameMaterial::Reddish as u8,
+        }
+    }
+
+    pub fn in_range(from: IVec2, to: IVec2) -> bool {
+        from.as_vec2().distance(to.as_vec2()) <= SUPPLY_RANGE
+    }
+
+    /// where `mat` goes instead of the machine's usual output, if anywhere
+    pub fn destination(
+        link: Option<&Self>,
+        mat: GameMaterial,
+        from: IVec2,
+        positions: &HashMap<Entity, IVec2>,
+    ) -> Option<(Entity, IVec2)> {
+        let link = link.filter(|l| mat.mask_contains(l.material_mask))?;
+        let to = *positions.get(&link.to)?;
+
+        Self::in_range(from, to).then_some((link.to, to))
+    }
+}
+
+/// The machine whose link is waiting for the player to click its other end.
+#[derive(Resource, Deref, DerefMut)]
+pub struct LinkingSupply(pub Option<Entity>);
+
+/// the next machine selected while linking is the other end
+fn finish_linking(
+    mut commands: Commands,
+    mut linking: ResMut<LinkingSupply>,
+    mut selected: ResMut<CurrentlySelected>,
+    q_machines: Query<(), With<MyMachine>>,
+) {
+    let Some(from) = linking.0 else {
+        return;
+    };
+
+    if !selected.is_changed() || selected.0 == Some(from) {
+        return;
+    }
+
+    linking.0 = None;
+
+    // deselected instead
+    let Some(to) = selected.0.filter(|e| q_machines.contains(*e)) else {
+        return;
+    };
+
+    if q_machines.contains(from) {
+        commands.entity(from).insert(SupplyLink::new(to));
+        selected.0 = Some(from);
+    }
+}
+
+fn drop_dangling_links(
+    mut commands: Commands,
+    q_links: Query<(Entity, &SupplyLink)>,
+    q_machines: Query<(), With<MyMachine>>,
+) {
+    for (e, link) in q_links.iter() {
+        if !q_machines.contains(link.to) {
+            commands.entity(e).remove::<SupplyLink>();
+        }
+    }
+}
+
+fn draw_links(
+    mut gizmos: Gizmos,
+    q_links: Query<(Entity, &MyMachine, &SupplyLink)>,
+    q_machines: Query<&MyMachine>,
+    selected: Res<CurrentlySelected>,
+) {
+    for (e, from, link) in q_links.iter() {
+        let Ok(to) = q_machines.get(link.to) else {
+            continue;
+        };
+
+        let color = if SupplyLink::in_range(from.pos, to.pos) {
+            Color::GREEN
+        } else {
+            Color::RED
+        };
+
+        // the others stay in the background
+        let color = if selected.0 == Some(e) || selected.0 == Some(link.to) {
+            color
+        } else {
+            color.with_a(0.3)
+        };
+
+        gizmos.line(
+            from.pos.extend(3).xzy().as_vec3(),
+            to.pos.extend(3).xzy().as_vec3(),
+            color,
+        );
+    }
+}
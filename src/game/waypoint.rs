@@ -0,0 +1,263 @@
+//! Plowers driving themselves to a waypoint, along an A* route that goes around
+//! columns that are slow to plow and the ones that can't be plowed at all.
+
+use std::{cmp::Reverse, collections::BinaryHeap};
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    conf::Configuration,
+    graphics::{
+        cursor::CursorOver,
+        machines::{radar::Radar, BuiltMachine, MyMachine},
+        selectable::CurrentlySelected,
+        voxels3d::{lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock},
+    },
+};
+
+use super::{footprint::Footprint, machines::GameMachineSettingsDiscriminants, Direction2D};
+
+/// seconds between re-plans, so the route keeps up with the terrain
+const REPLAN_EVERY: f32 = 2.0;
+
+/// the search gives up after looking at this many columns
+const MAX_EXPANDED: usize = 20_000;
+
+pub struct WaypointPlugin;
+
+impl Plugin for WaypointPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (plan_routes, steer_plowers.after(plan_routes)));
+    }
+}
+
+/// Setting waypoints and showing routes, not for headless runs.
+pub struct WaypointControlsPlugin;
+
+impl Plugin for WaypointControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (set_waypoint.before(plan_routes), draw_routes));
+    }
+}
+
+/// Where a Plower drives itself to. `move_machines` only moves it along the route,
+/// and it waits there once arrived.
+#[derive(Debug, Component)]
+pub struct Waypoint {
+    pub goal: IVec2,
+    /// columns left to drive through, the next one last
+    route: Vec<IVec2>,
+    stale: bool,
+    replan: Timer,
+}
+
+impl Waypoint {
+    pub fn new(goal: IVec2) -> Self {
+        Self {
+            goal,
+            route: vec![],
+            stale: true,
+            replan: Timer::from_seconds(REPLAN_EVERY, TimerMode::Repeating),
+        }
+    }
+
+    /// which way to go from `pos`, `None` without a route to follow
+    pub fn next_step(&self, pos: IVec2) -> Option<Direction2D> {
+        let next = *self.route.last()?;
+
+        (0..4)
+            .map(Direction2D::from)
+            .find(|d| pos + IVec2::from(*d) == next)
+    }
+}
+
+fn set_waypoint(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
+    cursor: Res<CursorOver>,
+    selected: Res<CurrentlySelected>,
+    q_plowers: Query<&MyMachine, With<BuiltMachine>>,
+) {
+    if !keys.just_pressed(conf.keys.waypoint) {
+        return;
+    }
+
+    let Some(e) = selected.0.filter(|e| {
+        q_plowers
+            .get(*e)
+            .is_ok_and(|mm| mm.gmt == GameMachineSettingsDiscriminants::Plower)
+    }) else {
+        return;
+    };
+
+    if keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        commands.entity(e).remove::<Waypoint>();
+    } else {
+        commands.entity(e).insert(Waypoint::new(cursor.block.xz()));
+    }
+}
+
+fn plan_routes(
+    time: Res<Time>,
+    mut q_plowers: Query<(Entity, &MyMachine, &mut Waypoint), With<BuiltMachine>>,
+    lazy_world: Res<LazyWorld>,
+    blocks: Query<&mut VoxelBlock>,
+) {
+    let wbw = WholeBlockWorld { lazy_world, blocks };
+
+    for (e, mm, mut wp) in q_plowers.iter_mut() {
+        // parks there until the player clears the waypoint
+        if mm.pos == wp.goal {
+            wp.route.clear();
+            continue;
+        }
+
+        // where it just drove to
+        if wp.route.last() == Some(&mm.pos) {
+            wp.route.pop();
+        }
+
+        let blocked = !wp.route.is_empty()
+            && wp.next_step(mm.pos).map_or(true, |d| {
+                mm.footprint
+                    .leading_edge(mm.pos, d)
+                    .into_iter()
+                    .any(|c| wbw.column_holder(c).is_some_and(|h| h != e))
+            });
+
+        if !wp.replan.tick(time.delta()).just_finished() && !wp.stale && !blocked {
+            continue;
+        }
+
+        // its own columns never stand in its way
+        let cost = |col: IVec2| match wbw.column_holder(col) {
+            Some(h) if h != e => None,
+            _ => Some(wbw.column_voxels(col)),
+        };
+
+        wp.route = find_route(mm.pos, wp.goal, &mm.footprint, cost).unwrap_or_default();
+        wp.stale = false;
+    }
+}
+
+/// A* from `start` to `goal`, stepping costs 1 plus the voxels in the way, in
+/// the same cells `move_machines` checks. The route comes back with the next step last.
+fn find_route(
+    start: IVec2,
+    goal: IVec2,
+    footprint: &Footprint,
+    column_cost: impl Fn(IVec2) -> Option<usize>,
+) -> Option<Vec<IVec2>> {
+    let heuristic = |p: IVec2| {
+        let d = (goal - p).abs();
+
+        (d.x + d.y) as usize
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<IVec2, (usize, IVec2)> = HashMap::default();
+
+    open.push((Reverse(heuristic(start)), 0, (start.x, start.y)));
+    came_from.insert(start, (0, start));
+
+    let mut expanded = 0;
+
+    while let Some((_, g, (x, y))) = open.pop() {
+        let pos = IVec2::new(x, y);
+
+        if pos == goal {
+            let mut route = vec![];
+            let mut p = goal;
+
+            while p != start {
+                route.push(p);
+                p = came_from[&p].1;
+            }
+
+            return Some(route);
+        }
+
+        // an outdated entry, found a cheaper way since
+        if came_from[&pos].0 < g {
+            continue;
+        }
+
+        expanded += 1;
+
+        if expanded > MAX_EXPANDED {
+            return None;
+        }
+
+        for dir in (0..4).map(Direction2D::from) {
+            let next = pos + IVec2::from(dir);
+
+            let Some(plowing) = footprint
+                .leading_edge(pos, dir)
+                .into_iter()
+                .map(&column_cost)
+                .sum::<Option<usize>>()
+            else {
+                continue;
+            };
+
+            let next_g = g + 1 + plowing;
+
+            if came_from.get(&next).is_some_and(|(old, _)| *old <= next_g) {
+                continue;
+            }
+
+            came_from.insert(next, (next_g, pos));
+            open.push((Reverse(next_g + heuristic(next)), next_g, (next.x, next.y)));
+        }
+    }
+
+    None
+}
+
+/// turns plowers toward their next step, one quarter at a time
+fn steer_plowers(
+    mut q_plowers: Query<(&MyMachine, &mut Direction2D, &Waypoint, &Children), With<BuiltMachine>>,
+    mut q_radars: Query<&mut Radar>,
+) {
+    for (mm, mut dir, wp, children) in q_plowers.iter_mut() {
+        let Some(want) = wp.next_step(mm.pos) else {
+            continue;
+        };
+
+        if want == *dir {
+            continue;
+        }
+
+        *dir = dir.rotate();
+
+        // like turning them with R
+        let mut radars = q_radars.iter_many_mut(children);
+
+        while let Some(mut radar) = radars.fetch_next() {
+            radar.watch.reset();
+        }
+    }
+}
+
+fn draw_routes(
+    mut gizmos: Gizmos,
+    selected: Res<CurrentlySelected>,
+    q_plowers: Query<(&MyMachine, &Waypoint)>,
+) {
+    let Some((mm, wp)) = selected.0.and_then(|e| q_plowers.get(e).ok()) else {
+        return;
+    };
+
+    let points = std::iter::once(mm.pos)
+        .chain(wp.route.iter().rev().copied())
+        .map(|p| p.extend(1).xzy().as_vec3());
+
+    gizmos.linestrip(points, Color::ORANGE);
+    gizmos.circle(
+        wp.goal.extend(1).xzy().as_vec3(),
+        Vec3::Y,
+        1.0,
+        Color::ORANGE,
+    );
+}
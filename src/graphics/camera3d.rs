@@ -1,54 +1,142 @@
+use std::f32::consts::FRAC_PI_2;
+
 use bevy::{
     core_pipeline::clear_color::ClearColorConfig,
+    input::mouse::{MouseScrollUnit, MouseWheel},
     pbr::DirectionalLightShadowMap,
     prelude::*,
     render::{camera::ScalingMode, view::RenderLayers},
+    window::PrimaryWindow,
 };
 // use bevy_inspector_egui::bevy_egui::EguiContext;
 
-use crate::conf::Configuration;
+use crate::conf::{Configuration, KeyAction, ShadowQuality};
 
-use super::voxels3d::lazyworld::WorldGenTrigger;
+use super::{selectable::CurrentlySelected, voxels3d::lazyworld::WorldGenTrigger};
 
+/// where the camera sits relative to what it looks at, before any turning
 pub static CAMERA_OFFSET: Vec3 = Vec3::new(50.0, 50.0, 50.0);
 
+const MIN_SCALE: f32 = 5.0;
+const MAX_SCALE: f32 = 80.0;
+/// how much one wheel notch zooms
+const ZOOM_STEP: f32 = 1.1;
+/// pixels from the window's edge where the cursor pans the camera
+const EDGE_MARGIN: f32 = 8.0;
+
 pub struct Camera3dPlugin;
 impl Plugin for Camera3dPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup)
+        app.insert_resource(CameraRig::default())
+            .add_systems(Startup, setup)
             .add_systems(Update, camera_setup)
-            .add_systems(Update, handle_camera_move);
+            .add_systems(
+                Update,
+                (
+                    handle_camera_move,
+                    handle_camera_zoom,
+                    handle_camera_turn,
+                    follow_selected.after(handle_camera_move),
+                    sync_cameras
+                        .after(camera_setup)
+                        .after(handle_camera_zoom)
+                        .after(handle_camera_turn),
+                ),
+            );
     }
 }
 
 #[derive(Component)]
 pub struct MainCamera;
 
+/// Which way the camera looks at the ground, and whether it sticks to the
+/// selected machine.
+#[derive(Debug, Default, Resource)]
+pub struct CameraRig {
+    /// quarter turns around the point the camera looks at
+    pub turns: i32,
+    pub follow: bool,
+}
+
+impl CameraRig {
+    fn rotation(&self) -> Quat {
+        Quat::from_rotation_y(FRAC_PI_2 * self.turns as f32)
+    }
+
+    /// `CAMERA_OFFSET` turned the way the camera is
+    pub fn offset(&self) -> Vec3 {
+        self.rotation() * CAMERA_OFFSET
+    }
+
+    /// the camera's transform for looking at `ground`
+    pub fn look_at(&self, ground: Vec3) -> Transform {
+        Transform::from_translation(ground + self.offset()).looking_at(ground, Vec3::Y)
+    }
+}
+
 fn camera_setup(
+    mut commands: Commands,
     conf: Res<Configuration>,
-    mut camera: Query<(&mut OrthographicProjection, With<Camera>)>,
+    mut camera: Query<&mut Projection, With<MainCamera>>,
+    mut lights: Query<&mut DirectionalLight>,
+) {
+    if !conf.is_changed() {
+        return;
+    }
+
+    // the wheel takes it from there
+    if conf.is_added() {
+        for mut proj in camera.iter_mut() {
+            if let Projection::Orthographic(proj) = &mut *proj {
+                proj.scale = conf.camera_scale;
+            }
+        }
+    }
+
+    commands.insert_resource(DirectionalLightShadowMap {
+        size: conf.shadows.map_size(),
+    });
+
+    for mut light in lights.iter_mut() {
+        light.shadows_enabled = conf.shadows != ShadowQuality::Off;
+    }
+}
+
+/// the other cameras see what the main one does, at the same zoom
+fn sync_cameras(
+    q_main: Query<&Projection, (With<MainCamera>, Changed<Projection>)>,
+    mut q_others: Query<&mut Projection, (With<Camera3d>, Without<MainCamera>)>,
+    rig: Res<CameraRig>,
+    mut q_triggers: Query<&mut WorldGenTrigger, With<MainCamera>>,
 ) {
-    if conf.is_changed() {
-        for (mut proj, _) in camera.iter_mut() {
-            proj.scale = conf.camera_scale;
+    if let Ok(Projection::Orthographic(main)) = q_main.get_single() {
+        for mut proj in q_others.iter_mut() {
+            if let Projection::Orthographic(proj) = &mut *proj {
+                proj.scale = main.scale;
+            }
+        }
+    }
+
+    // parts get generated around what the camera looks at
+    if rig.is_changed() {
+        for mut trigger in q_triggers.iter_mut() {
+            trigger.0 = rig.offset().xz();
         }
     }
 }
 
-fn setup(mut commands: Commands, _conf: Res<Configuration>) {
+fn setup(mut commands: Commands, conf: Res<Configuration>) {
     // ambient light
     commands.insert_resource(AmbientLight {
         color: Color::WHITE,
         brightness: 0.32,
     });
 
-    commands.insert_resource(DirectionalLightShadowMap { size: 1024 });
-
     commands.spawn(DirectionalLightBundle {
         directional_light: DirectionalLight {
             color: Color::WHITE,
             illuminance: 15000.0,
-            shadows_enabled: true,
+            shadows_enabled: conf.shadows != ShadowQuality::Off,
             // shadows_enabled: false,
             shadow_depth_bias: 0.02,
             shadow_normal_bias: 1.8,
@@ -147,28 +235,142 @@ fn setup(mut commands: Commands, _conf: Res<Configuration>) {
     // });
 }
 
-static KEY_TO_DIRECTION: &[(KeyCode, Vec3)] = &[
+static KEY_TO_DIRECTION: &[(KeyAction, Vec3)] = &[
     // (KeyCode::Up, Vec2::Z),
     // (KeyCode::Down, Vec2::new(0.0, -1.0)),
     // (KeyCode::Left, Vec2::new(-1.0, 0.0)),
     // (KeyCode::Right, Vec2::X),
-    (KeyCode::Up, Vec3::new(-1.0, 0.0, -1.0)),
-    (KeyCode::Down, Vec3::new(1.0, 0.0, 1.0)),
-    (KeyCode::Left, Vec3::new(-1.0, 0.0, 1.0)),
-    (KeyCode::Right, Vec3::new(1.0, 0.0, -1.0)),
+    (KeyAction::PanUp, Vec3::new(-1.0, 0.0, -1.0)),
+    (KeyAction::PanDown, Vec3::new(1.0, 0.0, 1.0)),
+    (KeyAction::PanLeft, Vec3::new(-1.0, 0.0, 1.0)),
+    (KeyAction::PanRight, Vec3::new(1.0, 0.0, -1.0)),
 ];
 
+/// with the arrow keys, or the cursor at the window's edge
 fn handle_camera_move(
     keys: Res<Input<KeyCode>>,
     conf: Res<Configuration>,
     mut camera: Query<(&mut Transform, (With<Camera3d>, Without<Parent>))>,
-    time: Res<Time>,
+    q_windows: Query<&Window, With<PrimaryWindow>>,
+    mut rig: ResMut<CameraRig>,
+    // still moves while the game is paused
+    time: Res<Time<Real>>,
 ) {
-    for (key, dir) in KEY_TO_DIRECTION.iter() {
-        if keys.pressed(*key) {
-            for (mut transform, _) in camera.iter_mut() {
-                transform.translation += *dir * conf.camera_speed * time.delta_seconds() * 100.0;
-            }
+    let mut pressed = KEY_TO_DIRECTION
+        .iter()
+        .filter(|(action, _)| keys.pressed(conf.keys.key(*action)))
+        .map(|(_, dir)| *dir)
+        .collect::<Vec<_>>();
+
+    if let Some(cursor) = q_windows
+        .get_single()
+        .ok()
+        .filter(|w| w.focused)
+        .and_then(|w| Some((w.cursor_position()?, w.width(), w.height())))
+    {
+        let (pos, width, height) = cursor;
+        let edges = [
+            (pos.y < EDGE_MARGIN, KeyAction::PanUp),
+            (pos.y > height - EDGE_MARGIN, KeyAction::PanDown),
+            (pos.x < EDGE_MARGIN, KeyAction::PanLeft),
+            (pos.x > width - EDGE_MARGIN, KeyAction::PanRight),
+        ];
+
+        pressed.extend(
+            KEY_TO_DIRECTION
+                .iter()
+                .filter(|(action, _)| edges.contains(&(true, *action)))
+                .map(|(_, dir)| *dir),
+        );
+    }
+
+    if pressed.is_empty() {
+        return;
+    }
+
+    // moving it around by hand lets go of the machine
+    if rig.follow {
+        rig.follow = false;
+    }
+
+    for dir in pressed {
+        let dir = rig.rotation() * dir;
+
+        for (mut transform, _) in camera.iter_mut() {
+            transform.translation += dir * conf.camera_speed * time.delta_seconds() * 100.0;
         }
     }
 }
+
+fn handle_camera_zoom(
+    mut wheel: EventReader<MouseWheel>,
+    mut camera: Query<&mut Projection, With<MainCamera>>,
+) {
+    let notches: f32 = wheel
+        .read()
+        .map(|ev| match ev.unit {
+            MouseScrollUnit::Line => ev.y,
+            MouseScrollUnit::Pixel => ev.y / 100.0,
+        })
+        .sum();
+
+    if notches == 0.0 {
+        return;
+    }
+
+    for mut proj in camera.iter_mut() {
+        if let Projection::Orthographic(proj) = &mut *proj {
+            proj.scale = (proj.scale * ZOOM_STEP.powf(-notches)).clamp(MIN_SCALE, MAX_SCALE);
+        }
+    }
+}
+
+/// a quarter turn around the point the camera looks at
+fn handle_camera_turn(
+    keys: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
+    mut rig: ResMut<CameraRig>,
+    mut camera: Query<(&mut Transform, (With<Camera3d>, Without<Parent>))>,
+) {
+    let turn = if keys.just_pressed(conf.keys.turn_left) {
+        -1
+    } else if keys.just_pressed(conf.keys.turn_right) {
+        1
+    } else {
+        return;
+    };
+
+    let offset = rig.offset();
+    rig.turns = (rig.turns + turn).rem_euclid(4);
+
+    for (mut transform, _) in camera.iter_mut() {
+        *transform = rig.look_at(transform.translation - offset);
+    }
+}
+
+fn follow_selected(
+    keys: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
+    mut rig: ResMut<CameraRig>,
+    selected: Res<CurrentlySelected>,
+    q_machines: Query<&GlobalTransform>,
+    mut camera: Query<(&mut Transform, (With<Camera3d>, Without<Parent>))>,
+) {
+    if keys.just_pressed(conf.keys.follow) {
+        rig.follow = !rig.follow;
+    }
+
+    if !rig.follow {
+        return;
+    }
+
+    let Some(tr) = selected.0.and_then(|e| q_machines.get(e).ok()) else {
+        return;
+    };
+
+    let tr = rig.look_at(tr.translation());
+
+    for (mut cam, _) in camera.iter_mut() {
+        *cam = tr;
+    }
+}
@@ -1,20 +1,30 @@
 use bevy::{prelude::*, time::Stopwatch};
 use rand::prelude::Rng;
 
-use crate::game::{material::GameMaterial, voxelmailbox::VoxelMailbox};
+use crate::game::{
+    material::GameMaterial,
+    seed::{SessionRng, SessionRngSet},
+    voxelmailbox::VoxelMailbox,
+};
 
 use super::{
     machines::{
         radar::{Radar, RadarType},
         MachineResources,
     },
-    voxels3d::{VoxelResources, VOXEL_BLOCK_SIZE},
+    voxels3d::{changes::VoxelBlockChanges, VoxelResources, VOXEL_BLOCK_SIZE},
 };
 
 pub struct FlyingVoxelPlugin;
 impl Plugin for FlyingVoxelPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, (initialize_voxel, fly_voxel));
+        app.add_systems(
+            Update,
+            (
+                initialize_voxel.in_set(SessionRngSet::FlyingVoxels),
+                fly_voxel,
+            ),
+        );
     }
 }
 
@@ -38,8 +48,10 @@ struct FlyingVoxelState {
 fn initialize_voxel(
     mut commands: Commands,
     q_fv: Query<(Entity, &FlyingVoxel), Without<FlyingVoxelState>>,
-    res: Res<MachineResources>,
-    vres: Res<VoxelResources>,
+    // missing when headless, the voxel still flies, just unseen
+    res: Option<Res<MachineResources>>,
+    vres: Option<Res<VoxelResources>>,
+    mut rng: ResMut<SessionRng>,
 ) {
     for (e, fv) in q_fv.iter() {
         // println!("new target:{:?}", fv.target_mailbox);
@@ -58,7 +70,7 @@ fn initialize_voxel(
             if max_max_y <= min_max_y {
                 min_max_y
             } else {
-                rand::thread_rng().gen_range(min_max_y..max_max_y)
+                rng.gen_range(min_max_y..max_max_y)
             }
         };
         let x1 = x0 / 2.0;
@@ -66,29 +78,36 @@ fn initialize_voxel(
         let a = (-x0 * y1 + x1 * y0) / (x0 * x0 * x1 - x0 * x1 * x1);
         let b = (y0 - a * x0.powi(2)) / x0;
 
-        commands.entity(e).insert((
-            FlyingVoxelState {
-                a,
-                b,
-                t: Stopwatch::new(),
-                max_t: target_reorig.length() * rand::thread_rng().gen_range(0.5..1.0) / 5.0,
-            },
-            PbrBundle {
+        commands.entity(e).insert(FlyingVoxelState {
+            a,
+            b,
+            t: Stopwatch::new(),
+            max_t: target_reorig.length() * rng.gen_range(0.5..1.0) / 5.0,
+        });
+
+        if let (Some(res), Some(vres)) = (&res, &vres) {
+            commands.entity(e).insert(PbrBundle {
                 mesh: res.cube.clone(),
                 material: vres.material_handles[fv.material.as_usize()].clone(),
                 // transform: Transform::from_scale(Vec3::new(tp.dims.x as f32, 32.0, tp.dims.y as f32)),
                 ..default()
-            },
-        ));
+            });
+        }
     }
 }
 fn fly_voxel(
     mut commands: Commands,
     time: Res<Time>,
-    mut q_fvs: Query<(Entity, &FlyingVoxel, &mut FlyingVoxelState, &mut Transform)>,
+    mut q_fvs: Query<(
+        Entity,
+        &FlyingVoxel,
+        &mut FlyingVoxelState,
+        Option<&mut Transform>,
+    )>,
     mut q_mailboxes: Query<&mut VoxelMailbox>,
+    mut changes: ResMut<VoxelBlockChanges>,
 ) {
-    for (e, fv, mut fvs, mut tr) in q_fvs.iter_mut() {
+    for (e, fv, mut fvs, tr) in q_fvs.iter_mut() {
         fvs.t.tick(time.delta());
 
         let t = fvs.t.elapsed().as_secs_f32() / fvs.max_t;
@@ -98,11 +117,18 @@ fn fly_voxel(
 
             // println!("sedning to: {:?}", fv.target_mailbox);
 
-            let mut mb = q_mailboxes.get_mut(fv.target_mailbox).unwrap();
-
-            mb.0.push_back((fv.payload.0, fv.material, fv.payload.1));
+            if let Ok(mut mb) = q_mailboxes.get_mut(fv.target_mailbox) {
+                mb.0.push_back((fv.payload.0, fv.material, fv.payload.1));
+            } else {
+                // the machine got deconstructed meanwhile, the voxel lands where it was going
+                changes.register_change(fv.target.as_ivec3(), fv.material);
+            }
         }
 
+        let Some(mut tr) = tr else {
+            continue;
+        };
+
         let target = fv.target;
         let origin = fv.origin;
 
@@ -1,10 +1,17 @@
 use bevy::prelude::*;
 
-use crate::graphics::{
-    gamemenu::{
-        GameMenuNode, GameMenuToPickBuildingForMachineButton, LeftBottomUiNode, TutorialNode,
+use crate::{
+    conf::KeyAction,
+    game::material::GameMaterial,
+    graphics::{
+        gamemenu::{
+            GameMenuDeconstructButton, GameMenuKeyButton, GameMenuNode, GameMenuRadarFilterButton,
+            GameMenuShadowsButton, GameMenuSupplyFilterButton, GameMenuSupplyLinkButton,
+            GameMenuToPickBuildingForMachineButton, GameMenuUiScaleButton, GameMenuUiScaleText,
+            LeftBottomUiNode, TutorialNode,
+        },
+        machines::MachineType,
     },
-    machines::MachineType,
 };
 
 // whiteish-blue
@@ -81,11 +88,21 @@ pub fn setup_menu(
     let tpbm = to_pick_building_menu(&mut commands, q_mtypes);
 
     let selected_building_text = selected_building(&mut commands);
+    let radar_filters = radar_filters(&mut commands);
+    let supply_link_button = supply_link_button(&mut commands);
+    let supply_filters = supply_filters(&mut commands);
+    let deconstruct_button = deconstruct_button(&mut commands);
+    let settings = settings_menu(&mut commands);
 
     commands.entity(menu_root).push_children(&[
         currently_creating_text,
         tpbm,
         selected_building_text,
+        radar_filters,
+        supply_link_button,
+        supply_filters,
+        deconstruct_button,
+        settings,
     ]);
 
     commands
@@ -109,6 +126,7 @@ fn make_menu_buttons(commands: &mut Commands<'_, '_>) -> Entity {
             for (st, txt) in [
                 (GameMenuState::ToPickBuilding, "Build Menu"),
                 (GameMenuState::SelectedMachine, "Selected Machine"),
+                (GameMenuState::Settings, "Settings"),
             ] {
                 commands
                     .spawn((
@@ -166,7 +184,8 @@ fn currently_creating(commands: &mut Commands<'_, '_>) -> Entity {
                         },
                     },
                     TextSection {
-                        value: "\nPress R to rotate.\nPress Esc to cancel.".into(),
+                        // the keys, filled in by `redraw_key_hints`
+                        value: String::new(),
                         style: TextStyle {
                             font_size: 20.0,
                             color: Color::WHITE,
@@ -179,9 +198,11 @@ fn currently_creating(commands: &mut Commands<'_, '_>) -> Entity {
         ))
         .id();
 
-    commands
-        .entity(currently_creating_text)
-        .insert(TextRefs::new().with("name", currently_creating_text, 1));
+    commands.entity(currently_creating_text).insert(
+        TextRefs::new()
+            .with("name", currently_creating_text, 1)
+            .with("keys", currently_creating_text, 2),
+    );
 
     currently_creating_text
 }
@@ -211,8 +232,8 @@ fn selected_building(commands: &mut Commands<'_, '_>) -> Entity {
                         },
                     },
                     TextSection {
-                        value: "\nPress R to rotate.\nPress Esc to deselect.\n\nFuel (blue): "
-                            .into(),
+                        // the keys, filled in by `redraw_key_hints`
+                        value: String::new(),
                         style: TextStyle {
                             font_size: 20.0,
                             color: Color::WHITE,
@@ -259,6 +280,22 @@ fn selected_building(commands: &mut Commands<'_, '_>) -> Entity {
                             ..default()
                         },
                     },
+                    TextSection {
+                        value: "\nStored: ".into(),
+                        style: TextStyle {
+                            font_size: 20.0,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    },
+                    TextSection {
+                        value: "?".into(),
+                        style: TextStyle {
+                            font_size: 20.0,
+                            color: HIGHLIGHTED_TEXT_COLOR,
+                            ..default()
+                        },
+                    },
                 ]),
                 ..Default::default()
             },
@@ -268,14 +305,151 @@ fn selected_building(commands: &mut Commands<'_, '_>) -> Entity {
     commands.entity(selected_building_text).insert(
         TextRefs::new()
             .with("name", selected_building_text, 1)
+            .with("keys", selected_building_text, 2)
             .with("fuel", selected_building_text, 3)
             .with("maintenance", selected_building_text, 5)
-            .with("build", selected_building_text, 7),
+            .with("build", selected_building_text, 7)
+            .with("stored", selected_building_text, 9),
     );
 
     selected_building_text
 }
 
+fn radar_filters(commands: &mut Commands) -> Entity {
+    material_toggles(commands, "Work radar takes:", GameMenuRadarFilterButton)
+}
+
+fn supply_filters(commands: &mut Commands) -> Entity {
+    material_toggles(commands, "Supply link sends:", GameMenuSupplyFilterButton)
+}
+
+/// a row of buttons, one per material
+fn material_toggles<B: Bundle>(
+    commands: &mut Commands,
+    label: &str,
+    button: impl Fn(GameMaterial) -> B,
+) -> Entity {
+    commands
+        .spawn((
+            GameMenuPart(GameMenuState::SelectedMachine),
+            NodeBundle {
+                visibility: Visibility::Hidden,
+                style: Style {
+                    margin: UiRect::top(Val::Px(8.0)),
+                    flex_direction: FlexDirection::Row,
+                    align_items: AlignItems::Center,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ))
+        .with_children(|commands| {
+            commands.spawn(TextBundle {
+                text: Text::from_sections([TextSection {
+                    value: label.into(),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                }]),
+                ..Default::default()
+            });
+
+            for mat in GameMaterial::all() {
+                commands
+                    .spawn((
+                        button(*mat),
+                        ButtonBundle {
+                            style: Style {
+                                margin: UiRect::left(Val::Px(4.0)),
+                                padding: UiRect::all(Val::Px(4.0)),
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        },
+                    ))
+                    .with_children(|c| {
+                        c.spawn(TextBundle {
+                            text: Text::from_sections([TextSection {
+                                value: format!("{mat:?}"),
+                                style: TextStyle {
+                                    font_size: 16.0,
+                                    color: Color::BLACK,
+                                    ..default()
+                                },
+                            }]),
+                            ..Default::default()
+                        });
+                    });
+            }
+        })
+        .id()
+}
+
+fn supply_link_button(commands: &mut Commands) -> Entity {
+    commands
+        .spawn((
+            GameMenuPart(GameMenuState::SelectedMachine),
+            GameMenuSupplyLinkButton,
+            ButtonBundle {
+                visibility: Visibility::Hidden,
+                style: Style {
+                    margin: UiRect::top(Val::Px(8.0)),
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ))
+        .with_children(|c| {
+            c.spawn(TextBundle {
+                text: Text::from_sections([TextSection {
+                    // set by `redraw_supply_link`
+                    value: "Link supply".into(),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::GREEN,
+                        ..default()
+                    },
+                }]),
+                ..Default::default()
+            });
+        })
+        .id()
+}
+
+fn deconstruct_button(commands: &mut Commands) -> Entity {
+    commands
+        .spawn((
+            GameMenuPart(GameMenuState::SelectedMachine),
+            GameMenuDeconstructButton,
+            ButtonBundle {
+                visibility: Visibility::Hidden,
+                style: Style {
+                    margin: UiRect::top(Val::Px(8.0)),
+                    padding: UiRect::all(Val::Px(4.0)),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ))
+        .with_children(|c| {
+            c.spawn(TextBundle {
+                text: Text::from_sections([TextSection {
+                    value: "Deconstruct".into(),
+                    style: TextStyle {
+                        font_size: 20.0,
+                        color: Color::RED,
+                        ..default()
+                    },
+                }]),
+                ..Default::default()
+            });
+        })
+        .id()
+}
+
 fn make_tutorial_root(commands: &mut Commands<'_, '_>) -> Entity {
     println!("Making tutorial root");
     let tutorial_root = commands
@@ -389,3 +563,88 @@ fn to_pick_building_menu(
 
     root
 }
+
+/// labels are set by `redraw_settings`
+fn settings_menu(commands: &mut Commands) -> Entity {
+    let text = |value: &str| TextBundle {
+        text: Text::from_sections([TextSection {
+            value: value.into(),
+            style: TextStyle {
+                font_size: 20.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        }]),
+        ..Default::default()
+    };
+
+    let button = || ButtonBundle {
+        style: Style {
+            margin: UiRect::top(Val::Px(4.0)),
+            padding: UiRect::all(Val::Px(4.0)),
+            ..Default::default()
+        },
+        background_color: Color::DARK_GRAY.with_a(0.5).into(),
+        ..Default::default()
+    };
+
+    commands
+        .spawn((
+            GameMenuPart(GameMenuState::Settings),
+            NodeBundle {
+                visibility: Visibility::Hidden,
+                style: Style {
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::FlexStart,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        ))
+        .with_children(|commands| {
+            commands
+                .spawn((GameMenuShadowsButton, button()))
+                .with_children(|c| {
+                    c.spawn(text("Shadows"));
+                });
+
+            commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        margin: UiRect::top(Val::Px(4.0)),
+                        flex_direction: FlexDirection::Row,
+                        align_items: AlignItems::Center,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                })
+                .with_children(|commands| {
+                    commands.spawn((GameMenuUiScaleText, text("UI scale")));
+
+                    for (label, step) in [(" - ", -0.25), (" + ", 0.25)] {
+                        commands
+                            .spawn((GameMenuUiScaleButton(step), button()))
+                            .with_children(|c| {
+                                c.spawn(text(label));
+                            });
+                    }
+                });
+
+            commands.spawn(TextBundle {
+                style: Style {
+                    margin: UiRect::top(Val::Px(8.0)),
+                    ..Default::default()
+                },
+                ..text("Keys (click one to change it):")
+            });
+
+            for action in KeyAction::all() {
+                commands
+                    .spawn((GameMenuKeyButton(*action), button()))
+                    .with_children(|c| {
+                        c.spawn(text(action.describe()));
+                    });
+            }
+        })
+        .id()
+}
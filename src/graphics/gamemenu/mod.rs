@@ -1,10 +1,24 @@
-use bevy::prelude::*;
+use bevy::{input::InputSystem, prelude::*};
+use itertools::Itertools;
+
+use crate::{
+    conf::{Configuration, KeyAction},
+    game::{
+        material::GameMaterial,
+        silo::SiloInventory,
+        supply::{LinkingSupply, SupplyLink},
+    },
+};
 
 use self::textref::{QueryTexts, TextRefs};
 
 use super::{
     cursor::CursorOver,
-    machines::{building::MachineGhost, MachineResources, MachineType, MyMachine},
+    machines::{
+        building::{DeconstructMachine, MachineGhost},
+        radar::{Radar, RadarType},
+        MachineResources, MachineType, MyMachine,
+    },
     selectable::CurrentlySelected,
 };
 
@@ -17,12 +31,27 @@ impl Plugin for GameMenuPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(tutorial::TutorialPlugin)
             .insert_resource(GameMenu(GameMenuState::ToPickBuilding))
+            .insert_resource(RebindingKey(None))
             .add_systems(Update, looks::setup_menu)
+            .add_systems(PreUpdate, rebind_key.after(InputSystem))
             .add_systems(
                 Update,
                 (
                     handle_build_click,
+                    handle_deconstruct_click,
+                    handle_radar_filter_click,
+                    redraw_radar_filters,
+                    handle_supply_link_click,
+                    handle_supply_filter_click,
+                    redraw_supply_link,
+                    (
+                        handle_shadows_click,
+                        handle_ui_scale_click,
+                        handle_key_click,
+                        redraw_settings,
+                    ),
                     redraw_menu,
+                    redraw_key_hints,
                     redraw_tabs,
                     handle_tabs_click,
                 ),
@@ -37,6 +66,7 @@ pub enum GameMenuState {
     ToPickBuilding,
     CurrentlyCreating,
     SelectedMachine,
+    Settings,
 }
 
 #[derive(Resource, Deref)]
@@ -60,6 +90,40 @@ struct GameMenuToPickBuildingForMachineButton(Entity);
 #[derive(Component, Deref, Reflect)]
 pub struct GameMenuButton(GameMenuState);
 
+#[derive(Component)]
+struct GameMenuDeconstructButton;
+
+/// turns a material on or off for the selected machine's work radars
+#[derive(Component, Deref)]
+struct GameMenuRadarFilterButton(GameMaterial);
+
+/// starts linking the selected machine to another one, or removes its link
+#[derive(Component)]
+struct GameMenuSupplyLinkButton;
+
+/// turns a material on or off for the selected machine's supply link
+#[derive(Component, Deref)]
+struct GameMenuSupplyFilterButton(GameMaterial);
+
+/// goes through the shadow qualities
+#[derive(Component)]
+struct GameMenuShadowsButton;
+
+/// changes the UI scale by this much
+#[derive(Component, Deref)]
+struct GameMenuUiScaleButton(f32);
+
+#[derive(Component)]
+struct GameMenuUiScaleText;
+
+/// waits for the key to do this with from now on
+#[derive(Component, Deref)]
+struct GameMenuKeyButton(KeyAction);
+
+/// The action whose key button was clicked, the next key pressed is bound to it.
+#[derive(Resource, Deref, DerefMut)]
+struct RebindingKey(Option<KeyAction>);
+
 fn handle_build_click(
     mut commands: Commands,
     q_interaction: Query<
@@ -85,13 +149,264 @@ fn handle_build_click(
     }
 }
 
+fn handle_deconstruct_click(
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<GameMenuDeconstructButton>)>,
+    selected: Res<CurrentlySelected>,
+    mut deconstruct: EventWriter<DeconstructMachine>,
+) {
+    for interaction in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            if let Some(machine) = selected.0 {
+                deconstruct.send(DeconstructMachine(machine));
+            }
+        }
+    }
+}
+
+fn handle_radar_filter_click(
+    q_interaction: Query<(&GameMenuRadarFilterButton, &Interaction), Changed<Interaction>>,
+    selected: Res<CurrentlySelected>,
+    q_machines: Query<&Children, With<MyMachine>>,
+    mut q_radars: Query<&mut Radar>,
+) {
+    let Some(children) = selected.0.and_then(|e| q_machines.get(e).ok()) else {
+        return;
+    };
+
+    for (button, interaction) in q_interaction.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        let mut radars = q_radars.iter_many_mut(children);
+
+        // all of them follow the first one, should they ever disagree
+        let mut on = None;
+
+        while let Some(mut radar) = radars.fetch_next() {
+            if radar.tp != RadarType::Work {
+                continue;
+            }
+
+            let on = *on.get_or_insert(!button.mask_contains(radar.material_mask));
+
+            if on {
+                radar.material_mask |= button.0 as u8;
+            } else {
+                radar.material_mask &= !(button.0 as u8);
+            }
+        }
+    }
+}
+
+fn redraw_radar_filters(
+    mut q_buttons: Query<(&GameMenuRadarFilterButton, &mut BackgroundColor)>,
+    selected: Res<CurrentlySelected>,
+    q_machines: Query<&Children, With<MyMachine>>,
+    q_radars: Query<&Radar>,
+) {
+    let mask = selected
+        .0
+        .and_then(|e| q_machines.get(e).ok())
+        .and_then(|children| {
+            q_radars
+                .iter_many(children)
+                .find(|r| r.tp == RadarType::Work)
+        })
+        .map(|r| r.material_mask);
+
+    for (button, mut color) in q_buttons.iter_mut() {
+        color.0 = toggle_color(button.0, mask);
+    }
+}
+
+/// lit up if it's in the mask, grayed out without anything to toggle
+fn toggle_color(mat: GameMaterial, mask: Option<u8>) -> Color {
+    match mask {
+        Some(mask) if mat.mask_contains(mask) => Color::from(&mat),
+        Some(_) => Color::from(&mat).with_a(0.15),
+        None => Color::DARK_GRAY.with_a(0.15),
+    }
+}
+
+fn handle_supply_link_click(
+    mut commands: Commands,
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<GameMenuSupplyLinkButton>)>,
+    selected: Res<CurrentlySelected>,
+    q_links: Query<(), With<SupplyLink>>,
+    mut linking: ResMut<LinkingSupply>,
+) {
+    let Some(machine) = selected.0 else {
+        return;
+    };
+
+    for interaction in q_interaction.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        if linking.0.is_some() {
+            linking.0 = None;
+        } else if q_links.contains(machine) {
+            commands.entity(machine).remove::<SupplyLink>();
+        } else {
+            linking.0 = Some(machine);
+        }
+    }
+}
+
+fn handle_supply_filter_click(
+    q_interaction: Query<(&GameMenuSupplyFilterButton, &Interaction), Changed<Interaction>>,
+    selected: Res<CurrentlySelected>,
+    mut q_links: Query<&mut SupplyLink>,
+) {
+    let Some(mut link) = selected.0.and_then(|e| q_links.get_mut(e).ok()) else {
+        return;
+    };
+
+    for (button, interaction) in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            link.material_mask ^= button.0 as u8;
+        }
+    }
+}
+
+fn redraw_supply_link(
+    q_button: Query<&Children, With<GameMenuSupplyLinkButton>>,
+    mut q_texts: Query<&mut Text>,
+    mut q_filters: Query<(&GameMenuSupplyFilterButton, &mut BackgroundColor)>,
+    selected: Res<CurrentlySelected>,
+    q_links: Query<&SupplyLink>,
+    linking: Res<LinkingSupply>,
+) {
+    let link = selected.0.and_then(|e| q_links.get(e).ok());
+
+    let label = if linking.0.is_some() {
+        "Click the machine to supply (or here to cancel)"
+    } else if link.is_some() {
+        "Unlink supply"
+    } else {
+        "Link supply"
+    };
+
+    let mut texts = q_texts.iter_many_mut(q_button.iter().flatten());
+
+    while let Some(mut text) = texts.fetch_next() {
+        if text.sections[0].value != label {
+            text.sections[0].value = label.into();
+        }
+    }
+
+    for (button, mut color) in q_filters.iter_mut() {
+        color.0 = toggle_color(button.0, link.map(|l| l.material_mask));
+    }
+}
+
+fn handle_shadows_click(
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<GameMenuShadowsButton>)>,
+    mut conf: ResMut<Configuration>,
+) {
+    for interaction in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            conf.shadows = conf.shadows.next();
+        }
+    }
+}
+
+fn handle_ui_scale_click(
+    q_interaction: Query<(&GameMenuUiScaleButton, &Interaction), Changed<Interaction>>,
+    mut conf: ResMut<Configuration>,
+) {
+    for (button, interaction) in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            conf.ui_scale = (conf.ui_scale + button.0).clamp(0.5, 2.0);
+        }
+    }
+}
+
+fn handle_key_click(
+    q_interaction: Query<(&GameMenuKeyButton, &Interaction), Changed<Interaction>>,
+    mut rebinding: ResMut<RebindingKey>,
+) {
+    for (button, interaction) in q_interaction.iter() {
+        if *interaction == Interaction::Pressed {
+            rebinding.0 = Some(button.0);
+        }
+    }
+}
+
+/// runs right after the input is read, so the key it takes never reaches
+/// whatever it was bound to before
+fn rebind_key(
+    mut keys: ResMut<Input<KeyCode>>,
+    mut rebinding: ResMut<RebindingKey>,
+    mut conf: ResMut<Configuration>,
+) {
+    let Some(action) = rebinding.0 else {
+        return;
+    };
+
+    let Some(&key) = keys.get_just_pressed().next() else {
+        return;
+    };
+
+    // not pressed as far as anything else can tell, not even when it's let go
+    keys.reset(key);
+
+    // Esc only cancels
+    if key != KeyCode::Escape {
+        conf.keys.bind(action, key);
+    }
+
+    rebinding.0 = None;
+}
+
+fn redraw_settings(
+    q_shadows: Query<&Children, With<GameMenuShadowsButton>>,
+    q_keys: Query<(&GameMenuKeyButton, &Children)>,
+    mut q_ui_scale: Query<&mut Text, With<GameMenuUiScaleText>>,
+    mut q_texts: Query<&mut Text, Without<GameMenuUiScaleText>>,
+    q_new: Query<(), Added<GameMenuShadowsButton>>,
+    conf: Res<Configuration>,
+    rebinding: Res<RebindingKey>,
+) {
+    // the menu only shows up once the machine types are loaded
+    if !conf.is_changed() && !rebinding.is_changed() && q_new.is_empty() {
+        return;
+    }
+
+    let mut texts = q_texts.iter_many_mut(q_shadows.iter().flatten());
+
+    while let Some(mut text) = texts.fetch_next() {
+        text.sections[0].value = format!("Shadows: {:?}", conf.shadows);
+    }
+
+    for mut text in q_ui_scale.iter_mut() {
+        text.sections[0].value = format!("UI scale: {:.0}%", conf.ui_scale * 100.0);
+    }
+
+    for (button, children) in q_keys.iter() {
+        let key = if rebinding.0 == Some(button.0) {
+            "press a key (Esc cancels)".to_owned()
+        } else {
+            format!("{:?}", conf.keys.key(button.0))
+        };
+
+        let mut texts = q_texts.iter_many_mut(children);
+
+        while let Some(mut text) = texts.fetch_next() {
+            text.sections[0].value = format!("{}: {key}", button.describe());
+        }
+    }
+}
+
 fn redraw_menu(
     menu_state: Res<GameMenu>,
     mut q_menu_parts: Query<(&mut Visibility, &GameMenuPart, Option<&TextRefs>)>,
     ghost: Res<MachineGhost>,
     q_types: Query<&MachineType>,
     selected: Res<CurrentlySelected>,
-    q_machines: Query<(&MyMachine, &Name)>,
+    q_machines: Query<(&MyMachine, &Name, Option<&SiloInventory>)>,
     mut q_texts: QueryTexts,
 ) {
     let state = menu_state.0;
@@ -100,7 +415,7 @@ fn redraw_menu(
     for (mut vis, part, textrefs) in q_menu_parts.iter_mut() {
         if part.0 == state {
             *vis = Visibility::Visible;
-            textref = textrefs;
+            textref = textref.or(textrefs);
         } else {
             *vis = Visibility::Hidden;
         }
@@ -127,7 +442,7 @@ fn redraw_menu(
                 return;
             };
 
-            let (mm, name) = q_machines.get(tp).unwrap();
+            let (mm, name, silo) = q_machines.get(tp).unwrap();
 
             textref.update(&mut q_texts, "name", name.to_string(), None);
             textref.update(
@@ -148,11 +463,57 @@ fn redraw_menu(
                 format!("{}", mm.still_building),
                 None,
             );
+            textref.update(
+                &mut q_texts,
+                "stored",
+                silo.map_or("-".into(), |s| {
+                    let each = GameMaterial::all()
+                        .iter()
+                        .map(|m| format!("{m:?} {}", s.stored[m.as_usize()]))
+                        .join(", ");
+
+                    format!("{}/{} ({each})", s.total(), s.capacity)
+                }),
+                None,
+            );
         }
         _ => {}
     }
 }
 
+/// the key hints under the machine's name, in case they were rebound
+fn redraw_key_hints(
+    menu_state: Res<GameMenu>,
+    q_menu_parts: Query<(&GameMenuPart, &TextRefs)>,
+    q_new: Query<(), Added<TextRefs>>,
+    conf: Res<Configuration>,
+    mut q_texts: QueryTexts,
+) {
+    // the menu only shows up once the machine types are loaded
+    if !conf.is_changed() && !menu_state.is_changed() && q_new.is_empty() {
+        return;
+    }
+
+    let keys = &conf.keys;
+
+    let hints = match menu_state.0 {
+        GameMenuState::CurrentlyCreating => {
+            format!("\nPress {:?} to rotate.\nPress Esc to cancel.", keys.rotate)
+        }
+        GameMenuState::SelectedMachine => format!(
+            "\nPress {:?} to rotate.\nPress Esc to deselect.\nPress {:?} to deconstruct.\nPress {:?} to send a Plower to the cursor, Shift+{:?} to stop.\nPress {:?} to keep the camera on it.\n\nFuel (blue): ",
+            keys.rotate, keys.deconstruct, keys.waypoint, keys.waypoint, keys.follow,
+        ),
+        _ => return,
+    };
+
+    for (part, textrefs) in q_menu_parts.iter() {
+        if part.0 == menu_state.0 {
+            textrefs.update(&mut q_texts, "keys", hints.clone(), None);
+        }
+    }
+}
+
 fn redraw_tabs(
     menu_state: Res<GameMenu>,
     mut q_menu_buttons: Query<(&GameMenuButton, &mut BackgroundColor, &Children)>,
@@ -218,5 +579,8 @@ fn handle_tabs_click(
 
             menu_state.0 = GameMenuState::SelectedMachine;
         }
+        GameMenuState::Settings => {
+            menu_state.0 = GameMenuState::Settings;
+        }
     }
 }
@@ -32,6 +32,22 @@ pub fn mark_tutorial_event(name: &'static str) {
     warn!("Unknown tutorial event: {}", name);
 }
 
+/// completion flags of all tutorial events, in order
+pub fn tutorial_state() -> Vec<(&'static str, bool)> {
+    EVENTS
+        .iter()
+        .map(|(name, event, _)| (*name, event.load(Ordering::Relaxed)))
+        .collect()
+}
+
+pub fn restore_tutorial_state(state: &[(String, bool)]) {
+    for (event_name, event, _) in EVENTS.iter() {
+        let done = state.iter().any(|(name, done)| name == event_name && *done);
+
+        event.store(done, Ordering::Relaxed);
+    }
+}
+
 fn earliest_event() -> Option<(&'static str, &'static str)> {
     for (event_name, event, txt) in EVENTS.iter() {
         if !event.load(Ordering::Relaxed) {
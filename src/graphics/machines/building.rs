@@ -3,18 +3,23 @@ use std::hash::BuildHasher;
 use bevy::{
     prelude::*,
     render::view::RenderLayers,
-    utils::{HashMap, Instant},
+    utils::{HashMap, HashSet, Instant},
 };
+use rand::Rng;
 
 use crate::{
+    conf::Configuration,
     game::{
         machines::{GameMachineSettings, GameMachineSettingsDiscriminants},
         material::GameMaterial,
+        seed::{SessionRng, SessionRngSet},
+        silo::SiloInventory,
         voxelmailbox::VoxelMailbox,
         Direction2D,
     },
     graphics::{
         cursor::CursorOver,
+        flyingvoxel::FlyingVoxel,
         gamemenu::{tutorial::mark_tutorial_event, GameMenu, GameMenuState},
         recolor::Tinted,
         sceneobjectfinder::{SceneFoundObject, SceneObjectFinder, SceneObjectsFound},
@@ -27,7 +32,8 @@ use crate::{
 
 use super::{
     colors::MachineRecolor,
-    radar::{consumption::RadarConsumer, RadarBundle, RadarType},
+    radar::{consumption::RadarConsumer, RadarType},
+    targets::Target,
     BuiltMachine, MachineResources, MachineType, MyMachine,
 };
 
@@ -43,9 +49,12 @@ impl Plugin for MachinesBuildingPlugin {
                 check_placement,
                 place_ghost.after(check_placement),
                 handle_esc,
+                handle_delete,
                 finish_building,
+                deconstruct_machines.in_set(SessionRngSet::Deconstruction),
             ),
         )
+        .add_event::<DeconstructMachine>()
         .insert_resource(MachineGhost(
             None,
             false,
@@ -59,12 +68,26 @@ impl Plugin for MachinesBuildingPlugin {
 /// MachineType, MyMachine
 pub struct MachineGhost(pub Option<(Entity, Entity)>, pub bool, pub Instant);
 
+/// how many of each type were ever placed; it never goes down, so names stay
+/// unique and a machine keeps its name when another one is deconstructed
 #[derive(Resource)]
 pub struct MachineCounter(pub HashMap<GameMachineSettingsDiscriminants, usize>);
 
 #[derive(Component)]
 pub struct GhostMachineFloor;
 
+/// tears a placed machine down and gives some of it back
+#[derive(Event)]
+pub struct DeconstructMachine(pub Entity);
+
+/// share of the Greenish spent on building that a deconstructed machine gives back
+const DECONSTRUCT_REFUND: f32 = 0.5;
+
+/// names look like "Plower (3)", numbered by `MachineCounter`
+fn machine_name(gmt: GameMachineSettingsDiscriminants, n: usize) -> String {
+    format!("{gmt:?} ({n})")
+}
+
 impl MachineGhost {
     pub fn start(
         tp: Entity,
@@ -74,43 +97,34 @@ impl MachineGhost {
         machine_res: &Res<MachineResources>,
     ) -> Self {
         let ent = commands
-            .spawn((
-                Name::new(format!("{} Ghost", machine_type.name)),
-                VoxelMailbox(default()),
-                Into::<Tinted>::into(MachineRecolor::Ghost),
-                WorldGenTrigger(Vec2::ZERO),
-                // BuiltMachine,
-                MyMachine {
-                    tp,
-                    gmt: machine_type.gmt,
-                    dims: machine_type.dims,
-                    pos: cursor.block.xz(),
-                    fuel: 0,
-                    max_fuel: machine_type.max_fuel,
-                    needed_maintenance: 0,
-                    still_building: 20,
-                    useful_ish_work_done: 0.0,
-                    last_slow_work: None,
-                },
+            .spawn(machine_bundle(
+                format!("{} Ghost", machine_type.name),
+                machine_type,
+                MyMachine::new(tp, machine_type, cursor.block.xz()),
                 Direction2D::Backward,
-                SceneObjectFinder::new(["RecycledOrigin", "RecyclingTarget"]),
-                VisibilityBundle::default(),
-                TransformBundle::default(),
             ))
             .with_children(|b| {
-                b.spawn((
-                    GhostMachineFloor,
-                    PbrBundle {
-                        mesh: machine_res.floor.clone(),
-                        transform: Transform::from_scale(Vec3::new(
-                            machine_type.dims.x as f32,
-                            1.0,
-                            machine_type.dims.y as f32,
-                        )),
-                        material: machine_res.white_floor.clone(),
-                        ..Default::default()
-                    },
-                ));
+                // the machine's transform is at the middle of the footprint already
+                let center = machine_type.footprint.bounds().center();
+
+                for rect in machine_type.footprint.rects.iter() {
+                    b.spawn((
+                        GhostMachineFloor,
+                        PbrBundle {
+                            mesh: machine_res.floor.clone(),
+                            transform: Transform::from_translation(
+                                (rect.center() - center).extend(0.0).xzy(),
+                            )
+                            .with_scale(Vec3::new(
+                                rect.size.x as f32,
+                                1.0,
+                                rect.size.y as f32,
+                            )),
+                            material: machine_res.white_floor.clone(),
+                            ..Default::default()
+                        },
+                    ));
+                }
             })
             .id();
 
@@ -122,11 +136,63 @@ impl MachineGhost {
     }
 }
 
+/// everything a machine needs before it's placed, shared with the save loader
+pub fn machine_bundle(
+    name: String,
+    mt: &MachineType,
+    mm: MyMachine,
+    dir: Direction2D,
+) -> impl Bundle {
+    (
+        Name::new(name),
+        VoxelMailbox(default()),
+        Into::<Tinted>::into(MachineRecolor::Ghost),
+        WorldGenTrigger(Vec2::ZERO),
+        mm,
+        dir,
+        SceneObjectFinder::new(mt.scene_objects()),
+        VisibilityBundle::default(),
+        TransformBundle::default(),
+    )
+}
+
+/// turns a ghost into a machine that is being built
+pub fn place_machine(commands: &mut Commands, machine: Entity, mt: &MachineType, name: String) {
+    commands.entity(machine).insert((
+        Name::new(name),
+        Tinted::new(Color::rgb(0.0, 0.1, 0.0)),
+        VisibilityBundle::default(),
+        Selectable,
+        SceneRenderLayers(
+            RenderLayers::default(), // .with(6)
+        ),
+    ));
+
+    if mt.capacity > 0 {
+        commands
+            .entity(machine)
+            .insert(SiloInventory::new(mt.capacity));
+    }
+
+    for def in mt.radars.iter().filter(|r| r.tp == RadarType::Building) {
+        let build_radar = commands
+            .spawn(def.bundle(RadarConsumer {
+                flying_target: None,
+                // target_mailbox: None,
+                target_mailbox: Some(machine),
+            }))
+            .id();
+
+        commands.entity(machine).add_child(build_radar);
+    }
+}
+
 fn move_ghost(
     ghost: ResMut<MachineGhost>,
     mut q_machines: Query<(&mut MyMachine, &mut Direction2D), Without<BuiltMachine>>,
     cursor: Res<CursorOver>,
     keyb: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
 ) {
     let Some((_, ghost)) = ghost.0 else {
         return;
@@ -137,7 +203,7 @@ fn move_ghost(
 
     m.pos = cursor.block.xz();
 
-    if keyb.just_released(KeyCode::R) {
+    if keyb.just_released(conf.keys.rotate) {
         *dir = dir.rotate();
     }
 }
@@ -145,14 +211,17 @@ fn move_ghost(
 fn place_ghost(
     mut commands: Commands,
     mut mghost: ResMut<MachineGhost>,
-    q_machines: Query<(&MyMachine, &Children), Without<BuiltMachine>>,
+    q_machines: Query<(&MyMachine, &Children, &Direction2D), Without<BuiltMachine>>,
     cursor: Res<Input<MouseButton>>, // keyb: Res<Input<KeyCode>>,
+    cursor_over: Res<CursorOver>,
+    machine_res: Res<MachineResources>,
 
     mut selected: ResMut<CurrentlySelected>,
     mut menu_state: ResMut<GameMenu>,
 
     mut machine_counter: ResMut<MachineCounter>,
     q_floors: Query<Entity, With<GhostMachineFloor>>,
+    q_types: Query<&MachineType>,
 ) {
     if !mghost.1 {
         return;
@@ -162,11 +231,15 @@ fn place_ghost(
         return;
     }
 
-    let Some((_tp, ghost)) = mghost.0 else {
+    let Some((tp, ghost)) = mghost.0 else {
         return;
     };
 
-    let Ok((m, children)) = q_machines.get(ghost) else {
+    let Ok((m, children, dir)) = q_machines.get(ghost) else {
+        return;
+    };
+
+    let Ok(mt) = q_types.get(tp) else {
         return;
     };
 
@@ -181,36 +254,7 @@ fn place_ghost(
             .and_modify(|c| *c += 1)
             .or_insert(1);
 
-        commands.entity(ghost).insert((
-            Name::new(format!("{:?} ({})", m.gmt, v)),
-            Tinted::new(Color::rgb(0.0, 0.1, 0.0)),
-            VisibilityBundle::default(),
-            Selectable,
-            SceneRenderLayers(
-                RenderLayers::default(), // .with(6)
-            ),
-        ));
-
-        let build_radar = commands
-            .spawn((
-                Name::new("build radar"),
-                RadarBundle::new(
-                    &[GameMaterial::Greenish],
-                    None,
-                    RadarConsumer {
-                        flying_target: None,
-                        // target_mailbox: None,
-                        target_mailbox: Some(ghost),
-                    },
-                    4.0,
-                    15.0,
-                    RadarType::Building,
-                ),
-                // VoxelMailbox(default()),
-            ))
-            .id();
-
-        commands.entity(ghost).push_children(&[build_radar]);
+        place_machine(&mut commands, ghost, mt, machine_name(m.gmt, *v));
 
         q_floors
             .iter_many(children)
@@ -218,6 +262,17 @@ fn place_ghost(
 
         mghost.0 = None;
 
+        // conveyors are laid one segment after the other, until Esc
+        if m.gmt == GameMachineSettingsDiscriminants::Conveyor {
+            *mghost = MachineGhost::start(tp, &mut commands, &cursor_over, mt, &machine_res);
+
+            if let Some((_, next)) = mghost.0 {
+                commands.entity(next).insert(*dir);
+            }
+
+            return;
+        }
+
         // selected.0 = Some(ghost);
         // menu_state.0 = GameMenuState::SelectedMachine;
         selected.0 = None;
@@ -225,15 +280,18 @@ fn place_ghost(
     }
 }
 
-fn finish_building(
+pub fn finish_building(
     mut commands: Commands,
-    q_machines: Query<(Entity, &MyMachine, &SceneObjectsFound), Without<BuiltMachine>>,
+    q_machines: Query<
+        (Entity, &MyMachine, &SceneObjectsFound, Option<&Target>),
+        Without<BuiltMachine>,
+    >,
 
     q_found_transforms: Query<&Transform, With<SceneFoundObject>>,
 
     q_types: Query<&MachineType>,
 ) {
-    for (ghost, mm, scob) in q_machines.iter() {
+    for (ghost, mm, scob, target) in q_machines.iter() {
         let Ok(mt) = q_types.get(mm.tp) else {
             continue;
         };
@@ -256,6 +314,7 @@ fn finish_building(
                 scob,
                 &q_found_transforms,
                 &mt,
+                target.is_some(),
             );
         }
     }
@@ -268,43 +327,33 @@ fn check_placement(
         (Changed<MyMachine>, Without<BuiltMachine>),
     >,
 
-    q_existing_machines: Query<(&MyMachine, &Direction2D), With<BuiltMachine>>,
+    // still being built counts too
+    q_existing_machines: Query<(&MyMachine, &Direction2D), With<Selectable>>,
 
     lazyworld: Res<LazyWorld>,
     blocks: Query<&VoxelBlock>,
-    q_types: Query<&MachineType>,
 ) {
-    let Some((mt_e, ghost_e)) = mghost.0 else {
+    let Some((_, ghost_e)) = mghost.0 else {
         return;
     };
     let Ok((ghost, mut tinted, dir)) = q_machines.get_mut(ghost_e) else {
         return;
     };
-    let Ok(mt) = q_types.get(mt_e) else {
-        return;
-    };
-
-    let center = mt.dims / 2;
-
     let mut bad = q_existing_machines
         .iter()
         .any(|(m, mdir)| m.intersects(*mdir, ghost, *dir));
 
     if !bad {
-        'outer: for x in 0..mt.dims.x {
-            for z in 0..mt.dims.y {
-                let pos = ghost.pos + dir.rotate_size(IVec2::new(x, z) - center);
-
-                let (block_i, inner) = VoxelBlock::normalize_pos(IVec2::ZERO, pos.extend(0).xzy());
+        for pos in ghost.footprint.columns(ghost.pos, *dir) {
+            let (part, inner) = VoxelBlock::normalize_col(pos);
 
-                let Ok(block) = blocks.get(lazyworld.known_parts[&block_i]) else {
-                    continue;
-                };
+            let Some(block) = lazyworld.ground(part).and_then(|e| blocks.get(e).ok()) else {
+                continue;
+            };
 
-                if block[inner].is_some() {
-                    bad = true;
-                    break 'outer;
-                }
+            if block.height_at(inner) > 0 {
+                bad = true;
+                break;
             }
         }
     }
@@ -335,3 +384,110 @@ fn handle_esc(
         menu_state.0 = GameMenuState::ToPickBuilding;
     }
 }
+
+fn handle_delete(
+    keyboard: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
+    selected: Res<CurrentlySelected>,
+    mut deconstruct: EventWriter<DeconstructMachine>,
+) {
+    if keyboard.just_pressed(conf.keys.deconstruct) {
+        if let Some(machine) = selected.0 {
+            deconstruct.send(DeconstructMachine(machine));
+        }
+    }
+}
+
+fn deconstruct_machines(
+    mut commands: Commands,
+    mut events: EventReader<DeconstructMachine>,
+    q_machines: Query<(&MyMachine, &Name, &VoxelMailbox, Option<&SiloInventory>), With<Selectable>>,
+    q_types: Query<&MachineType>,
+    lazy_world: Res<LazyWorld>,
+    q_blocks: Query<&VoxelBlock>,
+    mut selected: ResMut<CurrentlySelected>,
+    mut menu_state: ResMut<GameMenu>,
+    mut rng: ResMut<SessionRng>,
+) {
+    let rand = &mut rng.0;
+
+    // the button and the key can both fire in the same frame
+    let machines = events.read().map(|ev| ev.0).collect::<HashSet<_>>();
+
+    for e in machines {
+        let Ok((mm, name, mailbox, silo)) = q_machines.get(e) else {
+            continue;
+        };
+
+        let spent = q_types
+            .get(mm.tp)
+            .map_or(0, |mt| mt.still_building.saturating_sub(mm.still_building));
+
+        let refund = (spent as f32 * DECONSTRUCT_REFUND) as usize;
+
+        // whatever it got but didn't use up yet comes back as is
+        let ejected = std::iter::repeat((GameMaterial::Greenish, RadarType::Building))
+            .take(refund)
+            .chain(
+                std::iter::repeat((GameMaterial::Blueish, RadarType::Fuel)).take(mm.fuel as usize),
+            )
+            .chain(mailbox.0.iter().map(|(_, mat, tp)| (*mat, *tp)))
+            .chain(
+                silo.into_iter()
+                    .flat_map(|s| s.contents())
+                    .map(|mat| (mat, RadarType::Work)),
+            )
+            .collect::<Vec<_>>();
+
+        let origin = mm.pos.extend(3).xzy().as_vec3();
+        let reach = mm.dims.max_element() / 2;
+
+        for (mat, tp) in ejected {
+            let mut found = None;
+
+            for i in 1..200 {
+                let d = reach + i / 5;
+                let target = mm.pos + IVec2::new(rand.gen_range(-d..=d), rand.gen_range(-d..=d));
+
+                let (part, local_p) = VoxelBlock::normalize_col(target);
+
+                let Some(block_e) = lazy_world.ground(part) else {
+                    continue;
+                };
+                let stack = lazy_world.stack_blocks(part, &q_blocks);
+
+                if let Some(local_p) = VoxelBlock::empty_at_col(&stack, local_p) {
+                    found = Some((part, local_p, block_e));
+                    break;
+                }
+            }
+
+            let Some((part, local_p, block_e)) = found else {
+                warn!("no room around {name} for its {mat:?}, it's lost");
+                continue;
+            };
+
+            // lands on the block's mailbox, which pushes it into the world
+            let tp_pos =
+                VoxelBlock::global_pos(VoxelBlock::ground(part), local_p) + IVec3::new(0, 3, 0);
+
+            commands.spawn(FlyingVoxel {
+                origin,
+                target: tp_pos.as_vec3(),
+                target_mailbox: block_e,
+                material: mat,
+                payload: (tp_pos, tp),
+            });
+        }
+
+        info!("deconstructed {name}");
+
+        // radars are children, the target and the list row notice the machine is gone
+        commands.entity(e).despawn_recursive();
+
+        if selected.0 == Some(e) {
+            selected.0 = None;
+            menu_state.0 = GameMenuState::ToPickBuilding;
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use std::path::Path;
+
+use bevy::{asset::LoadState, prelude::*};
+use bevy_common_assets::json::JsonAssetPlugin;
+use serde::Deserialize;
+
+use crate::game::{
+    footprint::{Footprint, FootprintRect},
+    machines::GameMachineSettingsDiscriminants,
+    material::GameMaterial,
+    silo::SiloInventory,
+    Direction2D,
+};
+
+use super::{
+    radar::{consumption::RadarConsumer, Radar, RadarBundle, RadarType},
+    MachineType, MyMachine,
+};
+
+/// in the order they show up in the build menu
+pub const MACHINE_DEFINITIONS: &[&str] = &[
+    "machines/recycler.machine.json",
+    "machines/plower.machine.json",
+    "machines/conveyor.machine.json",
+    "machines/silo.machine.json",
+];
+
+pub struct MachineDefinitionPlugin;
+
+impl Plugin for MachineDefinitionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(JsonAssetPlugin::<MachineDefinition>::new(&["machine.json"]))
+            .add_systems(Startup, load_definitions)
+            .add_systems(Update, (spawn_machine_types, reload_definitions));
+    }
+}
+
+/// A `.machine.json` asset: what a machine type looks like and which radars it has.
+#[derive(Debug, Clone, Asset, TypePath, Deserialize)]
+pub struct MachineDefinition {
+    /// what the machine does once built
+    pub gmt: GameMachineSettingsDiscriminants,
+    pub name: String,
+    pub scene: String,
+    /// the box around the footprint
+    pub dims: IVec2,
+    /// in the machine's own frame, facing `Forward`, e.g. two rectangles for an
+    /// L shape; a `dims` rectangle centered on the machine if missing
+    #[serde(default)]
+    pub footprint: Vec<FootprintRect>,
+    pub max_fuel: u8,
+    /// Greenish voxels it takes to build it
+    pub still_building: u8,
+    /// voxels it can store, only silos have any room
+    #[serde(default)]
+    pub capacity: u32,
+    pub radars: Vec<RadarDefinition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RadarDefinition {
+    pub name: String,
+    pub tp: RadarType,
+    pub materials: Vec<GameMaterial>,
+    #[serde(default)]
+    pub direction: Option<Direction2D>,
+    pub speed: f32,
+    pub fast_distance: f32,
+    /// scene object the found voxels fly to, the radar itself if missing
+    #[serde(default)]
+    pub target_object: Option<String>,
+}
+
+impl MachineDefinition {
+    /// straight from the disk, for when there's no `AssetServer`
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = Path::new("assets").join(path);
+
+        std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()))
+            .map_err(|e| format!("can't read {}: {e}", path.display()))
+    }
+
+    pub fn footprint(&self) -> Footprint {
+        if self.footprint.is_empty() {
+            Footprint::rect(self.dims)
+        } else {
+            Footprint::new(self.footprint.clone())
+        }
+    }
+
+    /// speed of the work radar with a full tank
+    pub fn work_radar_speed(&self) -> f32 {
+        self.radars
+            .iter()
+            .find(|r| r.tp == RadarType::Work)
+            .map_or(0.0, |r| r.speed)
+    }
+}
+
+impl RadarDefinition {
+    pub fn bundle(&self, consumer: RadarConsumer) -> impl Bundle {
+        (
+            Name::new(self.name.clone()),
+            RadarBundle::new(
+                &self.materials,
+                self.direction,
+                consumer,
+                self.speed,
+                self.fast_distance,
+                self.tp,
+            ),
+        )
+    }
+
+    /// keeps the radar's progress, only its settings change
+    pub fn apply(&self, radar: &mut Radar) {
+        // what work radars take is up to the player
+        if self.tp != RadarType::Work {
+            radar.material_mask = GameMaterial::any_of_mask(&self.materials);
+        }
+
+        radar.direction = self.direction;
+        radar.speed = self.speed;
+        radar.fast_distance = self.fast_distance;
+    }
+}
+
+#[derive(Resource)]
+pub struct MachineDefinitions(pub Vec<Handle<MachineDefinition>>);
+
+fn load_definitions(mut commands: Commands, ass: Res<AssetServer>) {
+    commands.insert_resource(MachineDefinitions(
+        MACHINE_DEFINITIONS.iter().map(|p| ass.load(*p)).collect(),
+    ));
+}
+
+/// all at once when they're loaded, so the build menu gets them in order
+fn spawn_machine_types(
+    mut commands: Commands,
+    ass: Res<AssetServer>,
+    handles: Res<MachineDefinitions>,
+    definitions: Res<Assets<MachineDefinition>>,
+    mut spawned: Local<bool>,
+) {
+    if *spawned {
+        return;
+    }
+
+    let loading = handles.0.iter().any(|h| {
+        !matches!(
+            ass.get_load_state(h),
+            Some(LoadState::Loaded) | Some(LoadState::Failed)
+        )
+    });
+
+    if loading {
+        return;
+    }
+
+    *spawned = true;
+
+    for (path, h) in MACHINE_DEFINITIONS.iter().zip(handles.0.iter()) {
+        let Some(def) = definitions.get(h) else {
+            warn!("failed to load the machine definition {path}");
+            continue;
+        };
+
+        commands.spawn(MachineType::new(def, h.clone(), ass.load(&def.scene)));
+    }
+}
+
+fn reload_definitions(
+    mut events: EventReader<AssetEvent<MachineDefinition>>,
+    ass: Res<AssetServer>,
+    definitions: Res<Assets<MachineDefinition>>,
+    mut q_types: Query<(Entity, &mut MachineType)>,
+    mut q_machines: Query<(
+        &mut MyMachine,
+        Option<&mut Handle<Scene>>,
+        Option<&Children>,
+        Option<&mut SiloInventory>,
+    )>,
+    mut q_radars: Query<(&Name, &mut Radar)>,
+) {
+    for ev in events.read() {
+        let AssetEvent::Modified { id } = ev else {
+            continue;
+        };
+
+        let Some(def) = definitions.get(*id) else {
+            continue;
+        };
+
+        for (tp, mut mt) in q_types.iter_mut() {
+            if mt.definition.id() != *id {
+                continue;
+            }
+
+            // same handle as before if the scene didn't change
+            *mt = MachineType::new(def, mt.definition.clone(), ass.load(&def.scene));
+
+            info!("reloaded the {} definition", mt.name);
+
+            for (mut mm, machine_scene, children, silo) in q_machines.iter_mut() {
+                if mm.tp != tp {
+                    continue;
+                }
+
+                mm.dims = def.dims;
+                mm.footprint = def.footprint();
+                mm.max_fuel = def.max_fuel;
+                mm.fuel = mm.fuel.min(mm.max_fuel);
+                mm.still_building = mm.still_building.min(def.still_building);
+
+                // what's over the new capacity stays until it's taken out
+                if let Some(mut silo) = silo {
+                    silo.capacity = def.capacity;
+                }
+
+                if let Some(mut machine_scene) = machine_scene {
+                    if *machine_scene != mt.scene {
+                        *machine_scene = mt.scene.clone();
+                    }
+                }
+
+                // radars added to or removed from the definition only show up
+                // on newly built machines
+                for ch in children.into_iter().flatten() {
+                    let Ok((name, mut radar)) = q_radars.get_mut(*ch) else {
+                        continue;
+                    };
+
+                    if let Some(rdef) = def.radars.iter().find(|r| r.name == name.as_str()) {
+                        rdef.apply(&mut radar);
+                    }
+                }
+            }
+        }
+    }
+}
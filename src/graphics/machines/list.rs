@@ -1,9 +1,12 @@
 use bevy::prelude::*;
 
-use crate::graphics::{
-    camera3d::CAMERA_OFFSET,
-    gamemenu::{GameMenu, GameMenuState},
-    selectable::{CurrentlySelected, Selectable},
+use crate::{
+    game::silo::SiloInventory,
+    graphics::{
+        camera3d::CameraRig,
+        gamemenu::{GameMenu, GameMenuState},
+        selectable::{CurrentlySelected, Selectable},
+    },
 };
 
 use super::{BuiltMachine, MyMachine};
@@ -131,13 +134,27 @@ impl MachineListPlugin {
     }
 
     fn redraw_ui_nodes(
-        q_nodes: Query<(&MachineListUiMachine, &Children)>,
+        mut commands: Commands,
+        q_nodes: Query<(Entity, &MachineListUiMachine, &Children)>,
         mut q_text_nodes: Query<&mut Text, With<Parent>>,
-        q_machines: Query<(Entity, &Name, &GlobalTransform, &MyMachine), With<Selectable>>,
+        q_machines: Query<
+            (
+                Entity,
+                &Name,
+                &GlobalTransform,
+                &MyMachine,
+                Option<&SiloInventory>,
+            ),
+            With<Selectable>,
+        >,
         selected: Res<CurrentlySelected>,
     ) {
-        for (mach, children) in q_nodes.iter() {
-            let (ent, name, tr, mm) = q_machines.get(mach.0).unwrap();
+        for (node, mach, children) in q_nodes.iter() {
+            let Ok((ent, name, tr, mm, silo)) = q_machines.get(mach.0) else {
+                // the machine is gone
+                commands.entity(node).despawn_recursive();
+                continue;
+            };
 
             // if bm.is_none() && mm.still_building == 0 {
             //     continue;
@@ -152,8 +169,10 @@ impl MachineListPlugin {
                     " (still constructing)"
                 } else if mm.needed_maintenance > 0 {
                     " (needs maintenance)"
-                } else if mm.fuel < 2 {
+                } else if mm.max_fuel > 0 && mm.fuel < 2 {
                     " (low fuel)"
+                } else if silo.is_some_and(|s| s.is_full()) {
+                    " (full)"
                 } else if mm
                     .last_slow_work
                     .is_some_and(|t| t.elapsed().as_secs_f32() < 3.0)
@@ -184,15 +203,17 @@ impl MachineListPlugin {
         mut selected: ResMut<CurrentlySelected>,
         mut menu_state: ResMut<GameMenu>,
         mut camera: Query<&mut Transform, (With<Camera3d>, Without<Parent>)>,
+        rig: Res<CameraRig>,
     ) {
         for (node, inter) in q_nodes.iter() {
             if *inter == Interaction::Pressed {
                 selected.0 = Some(node.0);
                 menu_state.0 = GameMenuState::SelectedMachine;
 
-                let tr = q_machines.get(node.0).unwrap();
-                let tr = Transform::from_translation(tr.translation() + CAMERA_OFFSET)
-                    .looking_at(tr.translation(), Vec3::Y);
+                let Ok(tr) = q_machines.get(node.0) else {
+                    continue;
+                };
+                let tr = rig.look_at(tr.translation());
 
                 for mut cam in camera.iter_mut() {
                     *cam = tr;
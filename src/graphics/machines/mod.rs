@@ -5,12 +5,19 @@ use bevy::{
     utils::Instant,
 };
 
-use crate::game::{
-    machines::{GameMachineSettings, GameMachineSettingsDiscriminants},
-    Direction2D,
+use crate::{
+    conf::Configuration,
+    game::{
+        footprint::Footprint,
+        machines::{GameMachineSettings, GameMachineSettingsDiscriminants},
+        Direction2D,
+    },
 };
 
-use self::radar::Radar;
+use self::{
+    definition::{MachineDefinition, RadarDefinition},
+    radar::Radar,
+};
 
 use super::selectable::CurrentlySelected;
 
@@ -18,6 +25,7 @@ use super::selectable::CurrentlySelected;
 
 pub mod building;
 mod colors;
+pub mod definition;
 mod list;
 pub mod radar;
 pub mod targets;
@@ -27,6 +35,7 @@ pub struct MachinesPlugin;
 impl Plugin for MachinesPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
+            definition::MachineDefinitionPlugin,
             building::MachinesBuildingPlugin,
             radar::RadarPlugin,
             targets::TargetsPlugin,
@@ -66,8 +75,47 @@ pub struct MachineType {
     pub name: Cow<'static, str>,
     scene: Handle<Scene>,
     pub dims: IVec2,
+    pub footprint: Footprint,
     pub max_fuel: u8,
+    pub still_building: u8,
+    pub capacity: u32,
     pub work_radar_speed: f32,
+    #[reflect(ignore)]
+    pub radars: Vec<RadarDefinition>,
+    /// where it came from, to find it again on hot reload
+    #[reflect(ignore)]
+    pub definition: Handle<MachineDefinition>,
+}
+
+impl MachineType {
+    pub fn new(
+        def: &MachineDefinition,
+        definition: Handle<MachineDefinition>,
+        scene: Handle<Scene>,
+    ) -> Self {
+        MachineType {
+            gmt: def.gmt,
+            name: def.name.clone().into(),
+            scene,
+            dims: def.dims,
+            footprint: def.footprint(),
+            max_fuel: def.max_fuel,
+            still_building: def.still_building,
+            capacity: def.capacity,
+            work_radar_speed: def.work_radar_speed(),
+            radars: def.radars.clone(),
+            definition,
+        }
+    }
+
+    /// scene objects the machine needs found, for `SceneObjectFinder`
+    pub fn scene_objects(&self) -> Vec<String> {
+        let mut objects = vec!["RecycledOrigin".to_string()];
+
+        objects.extend(self.radars.iter().filter_map(|r| r.target_object.clone()));
+
+        objects
+    }
 }
 
 #[derive(Debug, Component, Reflect)]
@@ -76,7 +124,9 @@ pub struct MyMachine {
     pub tp: Entity,
     pub pos: IVec2,
     // pub direction: Direction2D,
+    /// the size of the box around its footprint
     pub dims: IVec2,
+    pub footprint: Footprint,
     pub fuel: u8,
     pub max_fuel: u8,
     pub needed_maintenance: u8,
@@ -87,16 +137,30 @@ pub struct MyMachine {
 }
 
 impl MyMachine {
-    pub fn intersects(&self, self_dir: Direction2D, other: &Self, other_dir: Direction2D) -> bool {
-        let (x1, y1) = (self.pos.x, self.pos.y);
-        let (x2, y2) = (other.pos.x, other.pos.y);
-
-        let (w1, h1) = self_dir.rotate_size(self.dims).into();
-        let (w2, h2) = other_dir.rotate_size(other.dims).into();
+    pub fn new(tp: Entity, machine_type: &MachineType, pos: IVec2) -> Self {
+        MyMachine {
+            tp,
+            gmt: machine_type.gmt,
+            dims: machine_type.dims,
+            footprint: machine_type.footprint.clone(),
+            pos,
+            fuel: 0,
+            max_fuel: machine_type.max_fuel,
+            needed_maintenance: 0,
+            still_building: machine_type.still_building,
+            useful_ish_work_done: 0.0,
+            last_slow_work: None,
+        }
+    }
 
-        // we don't care about recentering them...
+    pub fn intersects(&self, self_dir: Direction2D, other: &Self, other_dir: Direction2D) -> bool {
+        self.footprint
+            .intersects(self.pos, self_dir, &other.footprint, other.pos, other_dir)
+    }
 
-        x1 < x2 + w2 && x1 + w1 > x2 && y1 < y2 + h2 && y1 + h1 > y2
+    /// whether the column is under the machine
+    pub fn covers(&self, dir: Direction2D, col: IVec2) -> bool {
+        self.footprint.covers(self.pos, dir, col)
     }
 }
 
@@ -128,32 +192,13 @@ pub fn load_machines(
         debug_reddish: debug_reddish.clone(),
         radar: ass.load("objects/radar.glb#Scene0"),
     });
+}
 
-    commands.spawn(MachineType {
-        gmt: GameMachineSettingsDiscriminants::Recycler,
-        name: "Recycler".into(),
-        scene: ass.load("objects/recycler.glb#Scene0"),
-        // scenes: RecoloredScenes::new(ass, "objects/recycler.glb#Scene0"),
-        // dims: IVec2 { x: 7, y: 12 },
-
-        // always square for now
-        dims: IVec2 { x: 10, y: 10 },
-        max_fuel: 10,
-        work_radar_speed: 2.0,
-    });
+/// where the machine's scene and radars go, right over its footprint
+pub fn machine_transform(machine: &MyMachine, dir: &Direction2D) -> Transform {
+    let center = machine.pos.as_vec2() + machine.footprint.scene_offset(*dir);
 
-    commands.spawn(MachineType {
-        gmt: GameMachineSettingsDiscriminants::Plower,
-        name: "Plower".into(),
-        scene: ass.load("objects/plower.glb#Scene0"),
-        // scenes: RecoloredScenes::new(ass, "objects/recycler.glb#Scene0"),
-        // dims: IVec2 { x: 7, y: 12 },
-
-        // always square for now
-        dims: IVec2 { x: 6, y: 6 },
-        max_fuel: 5,
-        work_radar_speed: 1.0,
-    });
+    Transform::from_translation(center.extend(0.0).xzy()).with_rotation(dir.into())
 }
 
 fn update_machines(
@@ -170,8 +215,7 @@ fn update_machines(
     _mres: Res<MachineResources>,
 ) {
     for (e, machine, dir, spawn, scene_exists) in q_machines.iter_mut() {
-        let trans = Transform::from_translation(machine.pos.extend(0).xzy().as_vec3())
-            .with_rotation(dir.into());
+        let trans = machine_transform(machine, dir);
 
         match scene_exists.and(spawn) {
             None => {
@@ -215,9 +259,10 @@ fn rotate_selected_machine(
     selected: Res<CurrentlySelected>,
     mut q_machines: Query<(&mut Direction2D, &Children), With<BuiltMachine>>,
     keyb: Res<Input<KeyCode>>,
+    conf: Res<Configuration>,
     mut q_radars: Query<&mut Radar>,
 ) {
-    if !keyb.just_released(KeyCode::R) {
+    if !keyb.just_released(conf.keys.rotate) {
         return;
     }
 
@@ -1,9 +1,16 @@
 use bevy::prelude::*;
 
-use crate::graphics::{
-    flyingvoxel::FlyingVoxel,
-    voxels3d::{
-        changes::VoxelBlockChanges, lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock,
+use crate::{
+    game::{
+        seed::{SessionRng, SessionRngSet},
+        silo::SiloInventory,
+    },
+    graphics::{
+        flyingvoxel::FlyingVoxel,
+        voxels3d::{
+            changes::VoxelBlockChanges, lazyworld::LazyWorld, wholeworld::WholeBlockWorld,
+            VoxelBlock,
+        },
     },
 };
 
@@ -20,7 +27,12 @@ pub struct RadarConsumer {
 
 impl Plugin for RadarConsumptionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, Self::consume_radars.after(super::radar_search));
+        app.add_systems(
+            Update,
+            Self::consume_radars
+                .after(super::radar_search)
+                .in_set(SessionRngSet::RadarConsumption),
+        );
     }
 }
 
@@ -31,23 +43,33 @@ impl RadarConsumptionPlugin {
         q_radar_consumers: Query<(Entity, &RadarConsumer, &GlobalTransform)>,
         lazy_world: Res<LazyWorld>,
         blocks: Query<&mut VoxelBlock>,
+        mut q_silos: Query<&mut SiloInventory>,
         mut blockchanges: ResMut<VoxelBlockChanges>,
+        mut rng: ResMut<SessionRng>,
     ) {
         if q_events.is_empty() {
             return;
         }
 
         let mut whole_world = WholeBlockWorld { lazy_world, blocks };
-        let rand = &mut rand::thread_rng();
+        let rand = &mut rng.0;
 
         for ev in q_events.read() {
             let (e, cons, tr) = q_radar_consumers.get(ev.radar).unwrap();
 
             let target = tr.transform_point(cons.flying_target.unwrap_or_default());
 
-            let Some(_mat) = whole_world.steal_block(ev.pos, &mut blockchanges, rand) else {
+            if let Some(silo) = ev.silo {
+                // another radar may have emptied it this frame
+                if !q_silos.get_mut(silo).is_ok_and(|mut s| s.take(ev.material)) {
+                    continue;
+                }
+            } else if whole_world
+                .steal_block(ev.pos, &mut blockchanges, rand)
+                .is_none()
+            {
                 continue;
-            };
+            }
 
             // if mat != ev.material {
             //     continue;
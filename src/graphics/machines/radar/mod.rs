@@ -1,8 +1,14 @@
 use bevy::{prelude::*, time::Stopwatch};
 use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    game::{material::GameMaterial, Direction2D},
+    game::{
+        material::GameMaterial,
+        seed::{SessionRng, SessionRngSet},
+        silo::SiloInventory,
+        Direction2D,
+    },
     graphics::voxels3d::{lazyworld::LazyWorld, VoxelBlock, VOXEL_BLOCK_SIZE},
 };
 
@@ -16,20 +22,26 @@ pub struct RadarPlugin;
 
 impl Plugin for RadarPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((
-            consumption::RadarConsumptionPlugin,
-            graphics_shader::RadarGraphicsPlugin,
-        ))
-        .add_systems(FixedUpdate, radar_search)
-        .add_event::<RadarFoundVoxel>()
-        .register_type::<Radar>()
-        .register_type::<RadarConsumer>()
-        .register_type::<RadarFoundVoxel>()
-        .register_type::<RadarScene>();
+        app.add_plugins((RadarSimPlugin, graphics_shader::RadarGraphicsPlugin));
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect)]
+/// Searching and consuming, without the looks.
+pub struct RadarSimPlugin;
+
+impl Plugin for RadarSimPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(consumption::RadarConsumptionPlugin)
+            .add_systems(FixedUpdate, radar_search.in_set(SessionRngSet::RadarSearch))
+            .add_event::<RadarFoundVoxel>()
+            .register_type::<Radar>()
+            .register_type::<RadarConsumer>()
+            .register_type::<RadarFoundVoxel>()
+            .register_type::<RadarScene>();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Reflect, Serialize, Deserialize)]
 pub enum RadarType {
     Fuel,
     Work,
@@ -69,12 +81,14 @@ pub struct RadarFoundVoxel {
     pub material: GameMaterial,
     pub pos: IVec3,
     tp: RadarType,
+    /// taken out of a silo rather than the landfill
+    pub silo: Option<Entity>,
 }
 
 #[derive(Component, Reflect)]
 pub struct Radar {
     pub tp: RadarType,
-    material_mask: u8,
+    pub material_mask: u8,
     pub watch: Stopwatch,
     scene: Option<Entity>,
 
@@ -122,14 +136,16 @@ impl Radar {
 fn radar_search(
     mut found_events: EventWriter<RadarFoundVoxel>,
     time: Res<Time>,
-    mut q_radars: Query<(Entity, &mut Radar, &Parent, &GlobalTransform)>,
+    mut q_radars: Query<(Entity, &mut Radar, &Parent)>,
     q_parent_machines: Query<(&Direction2D, &MyMachine), With<Children>>,
+    q_silos: Query<(Entity, &MyMachine, &SiloInventory)>,
     lazyworld: Res<LazyWorld>,
     q_blocks: Query<&VoxelBlock>,
+    mut rng: ResMut<SessionRng>,
 ) {
-    let rand = &mut rand::thread_rng();
+    let rand = &mut rng.0;
 
-    for (e, mut r, rpar, gt) in q_radars.iter_mut() {
+    for (e, mut r, rpar) in q_radars.iter_mut() {
         if r.paused {
             continue;
         }
@@ -143,42 +159,79 @@ fn radar_search(
         // make it more interesting lmao
         let dist = dist + rand.gen_range(0.0..3.0);
 
-        let radar_ipos = gt.translation().xz().as_ivec2();
+        // the scene may sit off the middle of a column, the machine itself doesn't
+        let radar_ipos = machine.pos;
+
+        // silos in range go first, work radars are the ones filling them though
+        if r.tp != RadarType::Work {
+            let silo = q_silos
+                .iter()
+                .filter(|(s, _, inv)| *s != **rpar && inv.count(r.material_mask) > 0)
+                .map(|(s, smm, inv)| (s, smm, inv, smm.pos.distance_squared(radar_ipos)))
+                .filter(|(_, _, _, d2)| (*d2 as f32) <= dist * dist)
+                .min_by_key(|(_, _, _, d2)| *d2);
+
+            if let Some((s, smm, inv, _)) = silo {
+                if let Some(mat) = inv.pick(r.material_mask, rand) {
+                    found_events.send(RadarFoundVoxel {
+                        radar: e,
+                        material: mat,
+                        pos: smm.pos.extend(3).xzy(),
+                        tp: r.tp,
+                        silo: Some(s),
+                    });
+
+                    r.watch.reset();
+                    continue;
+                }
+            }
+        }
 
+        // matching columns, weighted by how many matching voxels they hold,
+        // so every voxel in range still has the same chance
         let mut candidates = vec![];
 
-        for (bigblock_pos, ent) in lazyworld.lookup_around(radar_ipos, dist) {
-            let Ok(voxel_block) = q_blocks.get(ent) else {
+        for bigblock_pos in lazyworld.lookup_around(radar_ipos, dist) {
+            // columns go up through the whole stack
+            let stack = lazyworld.stack_blocks(bigblock_pos, &q_blocks);
+
+            if !stack.iter().any(|b| b.index.contains_any(r.material_mask)) {
                 continue;
-            };
+            }
 
             let local_pos = radar_ipos - bigblock_pos * VOXEL_BLOCK_SIZE;
 
-            for col in voxel_block.closest_columns(local_pos, dist) {
-                for (mat, pos) in voxel_block.material_in_col(col, r.material_mask) {
-                    if let Some(dir) = r.direction {
-                        let radar_local_pos = (bigblock_pos * VOXEL_BLOCK_SIZE) - radar_ipos + col;
+            for col in stack[0].closest_columns(local_pos, dist) {
+                let cnt = VoxelBlock::count_in_col(&stack, col, r.material_mask);
 
-                        if !(dir * *rpardir).within_cone(radar_local_pos, machine.dims) {
-                            continue;
-                        }
-                    }
+                if cnt == 0 {
+                    continue;
+                }
 
-                    let full_pos = (bigblock_pos * VOXEL_BLOCK_SIZE).extend(0).xzy() + pos;
+                if let Some(dir) = r.direction {
+                    let radar_local_pos = (bigblock_pos * VOXEL_BLOCK_SIZE) - radar_ipos + col;
 
-                    candidates.push((mat, full_pos));
+                    if !(dir * *rpardir).within_cone(radar_local_pos, machine.dims) {
+                        continue;
+                    }
                 }
+
+                candidates.push((cnt, bigblock_pos, col));
             }
         }
 
-        if !candidates.is_empty() {
-            let winner = candidates.choose(rand).unwrap();
+        if let Ok((cnt, bigblock_pos, col)) = candidates.choose_weighted(rand, |c| c.0) {
+            let stack = lazyworld.stack_blocks(*bigblock_pos, &q_blocks);
+            let (mat, pos) = VoxelBlock::material_in_col(&stack, *col, r.material_mask)
+                .nth(rand.gen_range(0..*cnt))
+                .unwrap();
 
             found_events.send(RadarFoundVoxel {
                 radar: e,
-                material: winner.0,
-                pos: winner.1,
+                material: mat,
+                pos: VoxelBlock::global_pos(VoxelBlock::ground(*bigblock_pos), pos),
                 tp: r.tp,
+                silo: None,
             });
 
             r.watch.reset();
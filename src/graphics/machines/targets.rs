@@ -19,6 +19,7 @@ impl Plugin for TargetsPlugin {
                     Self::handle_move_start,
                     Self::handle_move.after(Self::handle_move_start),
                     Self::update_location.after(Self::handle_move),
+                    Self::despawn_orphans,
                 ),
             )
             .insert_resource(TargetBeingMoved(None));
@@ -109,6 +110,19 @@ impl TargetsPlugin {
     //     }
     // }
 
+    /// the machine owning the target is gone
+    fn despawn_orphans(
+        mut commands: Commands,
+        q_target_inst: Query<(Entity, &TargetInst)>,
+        q_targets: Query<(), With<Target>>,
+    ) {
+        for (e, t) in q_target_inst.iter() {
+            if q_targets.get(t.0).is_err() {
+                commands.entity(e).despawn_recursive();
+            }
+        }
+    }
+
     fn update_visibility(
         mut q_target_inst: Query<(&TargetInst, &mut Visibility)>,
         selected: Res<CurrentlySelected>,
@@ -226,7 +240,9 @@ impl TargetsPlugin {
         }
 
         let new_pos = (mouse.ground - delta).as_ivec2();
-        let mut conf = q_target_confs.get_mut(target).unwrap();
+        let Ok(mut conf) = q_target_confs.get_mut(target) else {
+            return;
+        };
 
         conf.global_pos = new_pos;
     }
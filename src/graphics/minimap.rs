@@ -0,0 +1,370 @@
+//! A top-down map of the parts around the camera: the taller a column, the
+//! brighter, in the color of its top voxel. Machines, their targets and what the
+//! camera sees are drawn over it, and clicking it moves the camera there.
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    ui::RelativeCursorPosition,
+    utils::{HashMap, HashSet},
+};
+
+use crate::game::Direction2D;
+
+use super::{
+    camera3d::{CameraRig, MainCamera},
+    machines::{targets::Target, MyMachine},
+    selectable::{CurrentlySelected, Selectable},
+    voxels3d::{
+        lazyworld::{trigger_part, LazyWorld, WorldGenTrigger},
+        VoxelBlock, VOXEL_BLOCK_SIZE,
+    },
+};
+
+/// parts across, centered on the one the camera looks at
+const MINIMAP_PARTS: i32 = 8;
+/// pixels across, one per column
+const MINIMAP_SIZE: i32 = MINIMAP_PARTS * VOXEL_BLOCK_SIZE;
+
+const NOT_LOADED: [u8; 4] = [0, 0, 0, 255];
+const CLEARED: [u8; 4] = [20, 20, 20, 255];
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                redraw_terrain,
+                draw_minimap.after(redraw_terrain),
+                handle_click,
+            ),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct Minimap {
+    image: Handle<Image>,
+    /// the part in the middle, `None` until first drawn
+    center: Option<IVec2>,
+    /// the image without anything drawn over it, kept up to date from changed parts
+    terrain: Vec<[u8; 4]>,
+    /// pixels of `terrain` that aren't on the image yet
+    terrain_changed: HashSet<usize>,
+    /// all of the image has to be redrawn, it moved to another part
+    recentered: bool,
+    /// what's drawn over the terrain on the image, by pixel
+    overlay: HashMap<usize, [u8; 4]>,
+}
+
+impl Minimap {
+    /// the column in the top left corner
+    fn origin(&self) -> IVec2 {
+        (self.center.unwrap_or_default() - MINIMAP_PARTS / 2) * VOXEL_BLOCK_SIZE
+    }
+
+    fn pixel(&self, col: IVec2) -> Option<usize> {
+        let p = col - self.origin();
+
+        (p.cmpge(IVec2::ZERO).all() && p.cmplt(IVec2::splat(MINIMAP_SIZE)).all())
+            .then_some((p.y * MINIMAP_SIZE + p.x) as usize)
+    }
+
+    /// `stack` is a part's chunks, ground first
+    fn draw_part(&mut self, stack: &[&VoxelBlock]) {
+        let Some(ground) = stack.first() else {
+            return;
+        };
+
+        for x in 0..VOXEL_BLOCK_SIZE {
+            for z in 0..VOXEL_BLOCK_SIZE {
+                let local = IVec2::new(x, z);
+
+                if let Some(i) = self.pixel(ground.part() * VOXEL_BLOCK_SIZE + local) {
+                    self.terrain[i] = column_color(stack, local);
+                    self.terrain_changed.insert(i);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct MinimapImage;
+
+fn column_color(stack: &[&VoxelBlock], col: IVec2) -> [u8; 4] {
+    let ground = stack[0];
+    let height = VoxelBlock::stack_height(stack, col);
+
+    if ground.cleared_columns[col.x as usize][col.y as usize] {
+        return CLEARED;
+    }
+
+    let top = (height > 0)
+        .then(|| {
+            let chunk = stack[((height - 1) / VOXEL_BLOCK_SIZE) as usize];
+
+            chunk[col.extend((height - 1) % VOXEL_BLOCK_SIZE).xzy()]
+        })
+        .flatten();
+
+    let color = match top {
+        Some(mat) => {
+            let color = Color::from(&mat);
+            // piles taller than a chunk are all about as bright
+            let lightness = (0.2 + 0.6 * height as f32 / VOXEL_BLOCK_SIZE as f32).min(0.9);
+
+            Color::hsl(color.h(), color.s(), lightness)
+        }
+        // the ground plane, a bit darker
+        None => Color::rgb(0.2, 0.35, 0.2),
+    };
+
+    color.as_rgba_u8()
+}
+
+fn setup(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: MINIMAP_SIZE as u32,
+            height: MINIMAP_SIZE as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &NOT_LOADED,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    // crisp columns
+    image.sampler = ImageSampler::nearest();
+
+    let image = images.add(image);
+
+    commands.insert_resource(Minimap {
+        image: image.clone(),
+        center: None,
+        terrain: vec![NOT_LOADED; (MINIMAP_SIZE * MINIMAP_SIZE) as usize],
+        terrain_changed: default(),
+        recentered: true,
+        overlay: default(),
+    });
+
+    commands.spawn((
+        Name::new("Minimap"),
+        MinimapImage,
+        Interaction::default(),
+        RelativeCursorPosition::default(),
+        ImageBundle {
+            image: UiImage::new(image),
+            style: Style {
+                position_type: PositionType::Absolute,
+
+                right: Val::Auto,
+                top: Val::Percent(1.),
+                bottom: Val::Auto,
+                left: Val::Percent(1.),
+
+                width: Val::Px(MINIMAP_SIZE as f32),
+                height: Val::Px(MINIMAP_SIZE as f32),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    ));
+}
+
+/// only the parts that changed, or all of them once the camera moves to another part
+fn redraw_terrain(
+    mut minimap: ResMut<Minimap>,
+    q_camera: Query<(&WorldGenTrigger, &GlobalTransform), With<MainCamera>>,
+    q_blocks: Query<Ref<VoxelBlock>>,
+    lazy_world: Res<LazyWorld>,
+) {
+    let Ok((trigger, trans)) = q_camera.get_single() else {
+        return;
+    };
+
+    let center = trigger_part(trigger, trans);
+    let recentered = minimap.center != Some(center);
+
+    if recentered {
+        minimap.center = Some(center);
+        minimap.terrain.fill(NOT_LOADED);
+        minimap.recentered = true;
+    }
+
+    // a column may go up through several chunks of its part
+    let parts = q_blocks
+        .iter()
+        .filter(|block| recentered || block.is_changed())
+        .map(|block| block.part())
+        .collect::<HashSet<_>>();
+
+    for part in parts {
+        let stack = lazy_world
+            .stack(part)
+            .map_while(|(_, e)| q_blocks.get(e).ok())
+            .collect::<Vec<_>>();
+        let stack = stack.iter().map(|block| &**block).collect::<Vec<_>>();
+
+        minimap.draw_part(&stack);
+    }
+}
+
+/// puts the terrain and what's over it on the image, only the pixels that
+/// changed unless it moved to another part
+fn draw_minimap(
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+    q_machines: Query<(Entity, &MyMachine, &Direction2D), With<Selectable>>,
+    q_targets: Query<&Target>,
+    q_camera: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    selected: Res<CurrentlySelected>,
+) {
+    let mut over = vec![];
+
+    for (e, mm, dir) in q_machines.iter() {
+        let color = if selected.0 == Some(e) {
+            Color::GREEN
+        } else {
+            Color::WHITE
+        };
+
+        over.extend(
+            mm.footprint
+                .columns(mm.pos, *dir)
+                .map(|col| (col, color.as_rgba_u8())),
+        );
+    }
+
+    for target in q_targets.iter() {
+        over.extend(
+            [IVec2::ZERO, IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                .map(|d| (target.global_pos + d, Color::RED.as_rgba_u8())),
+        );
+    }
+
+    if let Ok((camera, trans)) = q_camera.get_single() {
+        over.extend(
+            camera_view(camera, trans)
+                .into_iter()
+                .map(|col| (col, Color::YELLOW.as_rgba_u8())),
+        );
+    }
+
+    // later ones on top
+    let overlay = over
+        .into_iter()
+        .filter_map(|(col, color)| Some((minimap.pixel(col)?, color)))
+        .collect::<HashMap<_, _>>();
+
+    let pixels = if minimap.recentered {
+        (0..minimap.terrain.len()).collect::<HashSet<_>>()
+    } else {
+        // the terrain that changed, what's no longer drawn over and what is now
+        let old = &minimap.overlay;
+
+        minimap
+            .terrain_changed
+            .iter()
+            .copied()
+            .chain(old.keys().filter(|i| !overlay.contains_key(*i)).copied())
+            .chain(
+                overlay
+                    .iter()
+                    .filter(|(i, color)| old.get(*i) != Some(*color))
+                    .map(|(i, _)| *i),
+            )
+            .collect::<HashSet<_>>()
+    };
+
+    if pixels.is_empty() {
+        return;
+    }
+
+    let Some(image) = images.get_mut(&minimap.image) else {
+        return;
+    };
+
+    for i in pixels {
+        let color = overlay.get(&i).unwrap_or(&minimap.terrain[i]);
+
+        image.data[i * 4..i * 4 + 4].copy_from_slice(color);
+    }
+
+    minimap.overlay = overlay;
+    minimap.terrain_changed.clear();
+    minimap.recentered = false;
+}
+
+/// the outline of the ground the camera sees, in columns
+fn camera_view(camera: &Camera, trans: &GlobalTransform) -> Vec<IVec2> {
+    let Some(size) = camera.logical_viewport_size() else {
+        return vec![];
+    };
+
+    let corners = [
+        Vec2::ZERO,
+        Vec2::new(size.x, 0.0),
+        size,
+        Vec2::new(0.0, size.y),
+    ]
+    .into_iter()
+    .map(|corner| {
+        let ray = camera.viewport_to_world(trans, corner)?;
+        let distance = ray.intersect_plane(Vec3::ZERO, Vec3::Y)?;
+
+        Some(ray.get_point(distance).xz())
+    })
+    .collect::<Option<Vec<_>>>();
+
+    let Some(corners) = corners else {
+        return vec![];
+    };
+
+    let mut outline = vec![];
+
+    for (i, from) in corners.iter().enumerate() {
+        let to = corners[(i + 1) % corners.len()];
+        let steps = (to - *from).abs().max_element().ceil().max(1.0) as i32;
+
+        outline
+            .extend((0..steps).map(|s| from.lerp(to, s as f32 / steps as f32).round().as_ivec2()));
+    }
+
+    outline
+}
+
+/// moves the camera like clicking a machine in the machine list does
+fn handle_click(
+    minimap: Res<Minimap>,
+    q_minimap: Query<(&Interaction, &RelativeCursorPosition), With<MinimapImage>>,
+    mut camera: Query<&mut Transform, (With<Camera3d>, Without<Parent>)>,
+    mut rig: ResMut<CameraRig>,
+) {
+    for (inter, cursor) in q_minimap.iter() {
+        if *inter != Interaction::Pressed {
+            continue;
+        }
+
+        let Some(normalized) = cursor.normalized else {
+            continue;
+        };
+
+        let ground = minimap.origin().as_vec2() + normalized * MINIMAP_SIZE as f32;
+        let ground = ground.extend(0.0).xzy();
+
+        let tr = rig.look_at(ground);
+
+        // looking elsewhere now
+        rig.follow = false;
+
+        for mut cam in camera.iter_mut() {
+            *cam = tr;
+        }
+    }
+}
@@ -18,6 +18,7 @@ pub mod machines;
 pub mod dbgtext;
 // pub mod fps;
 pub mod gamemenu;
+pub mod minimap;
 pub mod stats;
 
 pub mod recolor;
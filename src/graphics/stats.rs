@@ -1,8 +1,12 @@
+use std::borrow::Cow;
+
 use bevy::prelude::*;
 
 use bevy::diagnostic::DiagnosticsStore;
 use bevy::utils::HashMap;
 
+use crate::{conf::Configuration, game::speed::GameSpeed};
+
 pub struct StatsPlugin;
 
 impl Plugin for StatsPlugin {
@@ -27,7 +31,7 @@ struct FpsRoot;
 struct DiagnosticText(&'static str);
 
 #[derive(Resource)]
-pub struct StatsValues(HashMap<&'static str, usize>);
+pub struct StatsValues(pub HashMap<Cow<'static, str>, usize>);
 
 impl StatsValues {
     pub fn new() -> Self {
@@ -39,7 +43,7 @@ impl StatsValues {
     }
 
     pub fn inc_n(&mut self, name: &'static str, n: usize) {
-        let value = self.0.entry(name).or_insert(0);
+        let value = self.0.entry(name.into()).or_insert(0);
         *value += n;
     }
 }
@@ -146,15 +150,44 @@ fn setup_fps_counter(mut commands: Commands) {
         ))
         .id();
 
-    commands.entity(root).push_children(&[won]);
+    let speed = commands
+        .spawn((
+            DiagnosticText("speed"),
+            TextBundle {
+                text: Text::from_sections([TextSection {
+                    value: String::new(),
+                    style: TextStyle {
+                        font_size: 16.0,
+                        color: Color::rgb(0.9, 0.9, 0.9),
+                        ..default()
+                    },
+                }]),
+                ..Default::default()
+            },
+        ))
+        .id();
+
+    commands.entity(root).push_children(&[won, speed]);
 }
 
 fn fps_text_update_system(
     stats_values: Res<StatsValues>,
+    speed: Res<GameSpeed>,
+    conf: Res<Configuration>,
     mut query: Query<(&mut Text, &DiagnosticText)>,
 ) {
     for (mut text, dt) in query.iter_mut() {
-        if dt.0 == "win" {
+        if dt.0 == "speed" {
+            text.sections[0].value = format!(
+                "Speed: {} ({:?} pauses, {:?}/{:?}/{:?} change it, {:?} steps)",
+                speed.describe(),
+                conf.keys.pause,
+                conf.keys.speed_1,
+                conf.keys.speed_2,
+                conf.keys.speed_4,
+                conf.keys.step,
+            );
+        } else if dt.0 == "win" {
             let value = stats_values.0.get("Recycled").unwrap_or(&0);
             if *value > 1000 {
                 text.sections[0].value = "You won!".into();
@@ -1,22 +1,130 @@
-use std::ops::Index;
+use std::ops::{Deref, Index};
 
-use bevy::prelude::*;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use bevy_meshem::prelude::get_neighbor;
 use bevy_meshem::prelude::Face;
 use bevy_meshem::prelude::MeshMD;
+use bevy_meshem::prelude::VoxelChange;
 use bevy_meshem::Dimensions;
 
 use crate::game::material::GameMaterial;
-use crate::graphics::voxels3d::CHUNK_LEN;
+use crate::game::material::MATERIAL_COUNT;
 use crate::graphics::voxels3d::VOXEL_BLOCK_SIZE;
 
+use super::storage::VoxelStorage;
+
+/// with more changes than this waiting, the mesh gets rebuilt instead of patched
+pub const MAX_MESH_LOG: usize = 512;
+
+/// One chunk of a part's stack, the ground one at `pos.y == 0` and whatever
+/// piled up higher on top of it. What's about whole columns (forbidden,
+/// catching, occupied) is kept by the ground one only.
 #[derive(Component)]
 pub struct VoxelBlock {
-    pub pos: IVec2,
-    pub meta: MeshMD<Option<GameMaterial>>,
-    pub grid: [Option<GameMaterial>; CHUNK_LEN],
+    /// in chunks, `xz` is the part
+    pub pos: IVec3,
+    /// `Some` while the mesh is meshem's culled one, which `update_mesh` can patch
+    pub meta: Option<MeshMD<Option<GameMaterial>>>,
+    /// changes not on the mesh yet
+    pub mesh_log: usize,
+    /// frame the mesh was last touched
+    pub mesh_updated: u32,
+    pub grid: VoxelStorage,
     pub mesh_id: AssetId<Mesh>,
+    /// nothing can land in these: the cleared ones and those kept empty by
+    /// machines and conveyors for now
     pub forbidden_columns: [[bool; VOXEL_BLOCK_SIZE as usize]; VOXEL_BLOCK_SIZE as usize],
+    /// kept empty for good, e.g. the start area
+    pub cleared_columns: [[bool; VOXEL_BLOCK_SIZE as usize]; VOXEL_BLOCK_SIZE as usize],
+    pub index: BlockIndex,
+    /// columns whose voxels go to an entity's `VoxelMailbox` instead of landing
+    pub catchers: HashMap<IVec2, Entity>,
+    /// catcher columns that were empty, so catching forbade them
+    pub catching_empty: HashSet<IVec2>,
+    /// what landed on a catcher and is yet to be delivered
+    pub caught: Vec<(Entity, IVec3, GameMaterial)>,
+    /// columns under a machine, forbidden while it stands there
+    pub occupied: HashMap<IVec2, Entity>,
+    /// columns that changed since `settle_slopes` last looked at them
+    pub unsettled: HashSet<IVec2>,
+}
+
+/// What a column holds, so searches can skip it without looking at its voxels.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColumnIndex {
+    /// one past the topmost voxel
+    pub height: u8,
+    /// by `GameMaterial::as_usize`
+    pub counts: [u8; MATERIAL_COUNT],
+}
+
+impl ColumnIndex {
+    pub fn mask(&self) -> u8 {
+        GameMaterial::all()
+            .iter()
+            .filter(|m| self.counts[m.as_usize()] > 0)
+            .fold(0, |acc, &m| acc | m as u8)
+    }
+
+    /// voxels in the column matching `mask`
+    pub fn count(&self, mask: u8) -> usize {
+        GameMaterial::all()
+            .iter()
+            .filter(|m| m.mask_contains(mask))
+            .map(|m| self.counts[m.as_usize()] as usize)
+            .sum()
+    }
+}
+
+/// Per-column and per-block material counts of a `VoxelBlock`, kept up to date
+/// by `_add_block` / `_take_block`.
+#[derive(Debug, Clone)]
+pub struct BlockIndex {
+    pub columns: [[ColumnIndex; VOXEL_BLOCK_SIZE as usize]; VOXEL_BLOCK_SIZE as usize],
+    pub counts: [u32; MATERIAL_COUNT],
+}
+
+impl BlockIndex {
+    pub fn new(grid: &VoxelStorage) -> Self {
+        let mut index = BlockIndex {
+            columns: [[ColumnIndex::default(); VOXEL_BLOCK_SIZE as usize];
+                VOXEL_BLOCK_SIZE as usize],
+            counts: [0; MATERIAL_COUNT],
+        };
+
+        for (idx, v) in grid.iter().enumerate() {
+            if let Some(mat) = v {
+                index.add(VoxelBlock::idx_to_pos(idx), mat);
+            }
+        }
+
+        index
+    }
+
+    fn add(&mut self, local_pos: IVec3, mat: GameMaterial) {
+        let col = &mut self.columns[local_pos.x as usize][local_pos.z as usize];
+
+        col.height = col.height.max(local_pos.y as u8 + 1);
+        col.counts[mat.as_usize()] += 1;
+        self.counts[mat.as_usize()] += 1;
+    }
+
+    fn take(&mut self, local_pos: IVec3, mat: GameMaterial) {
+        let col = &mut self.columns[local_pos.x as usize][local_pos.z as usize];
+
+        col.counts[mat.as_usize()] -= 1;
+        self.counts[mat.as_usize()] -= 1;
+    }
+
+    /// does the block have anything matching `mask` at all
+    pub fn contains_any(&self, mask: u8) -> bool {
+        GameMaterial::all()
+            .iter()
+            .any(|m| m.mask_contains(mask) && self.counts[m.as_usize()] > 0)
+    }
 }
 
 impl VoxelBlock {
@@ -31,7 +139,7 @@ impl VoxelBlock {
         for i in 0..6 {
             match get_neighbor(idx, Face::from(i), Self::DIMENSIONS) {
                 None => {}
-                Some(j) => r[i] = Some(self.grid[j]),
+                Some(j) => r[i] = Some(self.grid.get(j)),
             }
         }
         r
@@ -52,16 +160,13 @@ impl VoxelBlock {
 
         // assert!(idx >= 0);
 
-        assert!(self.grid[idx].is_none());
+        assert!(self.grid.get(idx).is_none());
         assert!(!self.forbidden_columns[local_pos.x as usize][local_pos.z as usize]);
 
-        self.grid[idx] = Some(mat);
-        self.meta.log(
-            bevy_meshem::prelude::VoxelChange::Added,
-            idx,
-            Some(mat),
-            self._meshem_neighbors(idx),
-        )
+        self.grid.set(idx, Some(mat));
+        self.index.add(local_pos, mat);
+        self.unsettled.insert(local_pos.xz());
+        self.log_mesh_change(VoxelChange::Added, idx, Some(mat));
     }
 
     pub fn _take_block(&mut self, local_pos: IVec3) -> Option<GameMaterial> {
@@ -79,13 +184,63 @@ impl VoxelBlock {
 
         // assert!(idx >= 0);
 
-        self.meta.log(
-            bevy_meshem::prelude::VoxelChange::Broken,
-            idx,
-            None,
-            self._meshem_neighbors(idx),
-        );
-        self.grid[idx].take()
+        self.log_mesh_change(VoxelChange::Broken, idx, None);
+
+        let taken = self.grid.replace(idx, None);
+
+        if let Some(mat) = taken {
+            self.index.take(local_pos, mat);
+
+            let col = local_pos.xz();
+            self.unsettled.insert(col);
+
+            if local_pos.y + 1 == self.height_at(col) {
+                let height = (0..local_pos.y)
+                    .rev()
+                    .find(|&y| self[col.extend(y).xzy()].is_some())
+                    .map_or(0, |y| y + 1);
+
+                self.index.columns[col.x as usize][col.y as usize].height = height as u8;
+            }
+        }
+
+        taken
+    }
+
+    fn idx_to_pos(idx: usize) -> IVec3 {
+        let idx = idx as i32;
+
+        IVec3::new(
+            idx % VOXEL_BLOCK_SIZE,
+            idx / (VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE),
+            idx / VOXEL_BLOCK_SIZE % VOXEL_BLOCK_SIZE,
+        )
+    }
+
+    pub fn column(&self, pos: IVec2) -> &ColumnIndex {
+        &self.index.columns[pos.x as usize][pos.y as usize]
+    }
+
+    /// one past the topmost voxel of the column, 0 if it's empty
+    pub fn height_at(&self, pos: IVec2) -> i32 {
+        self.column(pos).height as i32
+    }
+
+    fn log_mesh_change(&mut self, change: VoxelChange, idx: usize, voxel: Option<GameMaterial>) {
+        self.mesh_log += 1;
+
+        // it's getting rebuilt anyway, no point in keeping the log
+        if self.mesh_log > MAX_MESH_LOG {
+            self.meta = None;
+        }
+
+        if self.meta.is_some() {
+            let neighbors = self._meshem_neighbors(idx);
+
+            if let Some(meta) = self.meta.as_mut() {
+                meta.log(change, idx, voxel, neighbors);
+            }
+        }
     }
 
     pub fn within_bounds(pos: IVec3) -> bool {
@@ -97,51 +252,117 @@ impl VoxelBlock {
             && pos.z < VOXEL_BLOCK_SIZE
     }
 
-    pub fn normalize_pos(mut voxel_block_pos: IVec2, mut inner_pos: IVec3) -> (IVec2, IVec3) {
-        while inner_pos.x < 0 {
-            voxel_block_pos.x -= 1;
-            inner_pos.x += VOXEL_BLOCK_SIZE;
-        }
-        while inner_pos.z < 0 {
-            voxel_block_pos.y -= 1;
-            inner_pos.z += VOXEL_BLOCK_SIZE;
-        }
-        while inner_pos.x >= VOXEL_BLOCK_SIZE {
-            voxel_block_pos.x += 1;
-            inner_pos.x -= VOXEL_BLOCK_SIZE;
-        }
-        while inner_pos.z >= VOXEL_BLOCK_SIZE {
-            voxel_block_pos.y += 1;
-            inner_pos.z -= VOXEL_BLOCK_SIZE;
-        }
+    /// the chunk `inner_pos` really is in, counting from `voxel_block_pos`
+    pub fn normalize_pos(voxel_block_pos: IVec3, inner_pos: IVec3) -> (IVec3, IVec3) {
+        let size = IVec3::splat(VOXEL_BLOCK_SIZE);
 
-        (voxel_block_pos, inner_pos)
+        (
+            voxel_block_pos + inner_pos.div_euclid(size),
+            inner_pos.rem_euclid(size),
+        )
     }
 
+    /// the part a global column is in, and the column within it
+    pub fn normalize_col(col: IVec2) -> (IVec2, IVec2) {
+        let size = IVec2::splat(VOXEL_BLOCK_SIZE);
+
+        (col.div_euclid(size), col.rem_euclid(size))
+    }
+
+    /// the bottom chunk of the part's stack
+    pub fn ground(part: IVec2) -> IVec3 {
+        part.extend(0).xzy()
+    }
+
+    pub fn part(&self) -> IVec2 {
+        self.pos.xz()
+    }
+
+    /// clears the column for good
     pub fn forbid_column(&mut self, local_pos: IVec2) {
         for y in 0..VOXEL_BLOCK_SIZE {
             assert!(self[local_pos.extend(y).xzy()].is_none());
         }
 
-        self.forbidden_columns[local_pos.x as usize][local_pos.y as usize] = true;
+        self.cleared_columns[local_pos.x as usize][local_pos.y as usize] = true;
+        self.update_forbidden(local_pos);
+    }
+
+    /// forbidden while anything keeps the column empty
+    fn update_forbidden(&mut self, local_pos: IVec2) {
+        self.forbidden_columns[local_pos.x as usize][local_pos.y as usize] = self.cleared_columns
+            [local_pos.x as usize][local_pos.y as usize]
+            || self.occupied.contains_key(&local_pos)
+            || self.catching_empty.contains(&local_pos);
+    }
+
+    /// voxels landing on the column go to `catcher` from now on, the column is
+    /// forbidden too if nothing's in it
+    pub fn catch_column(&mut self, local_pos: IVec2, catcher: Entity) {
+        if self.height_at(local_pos) == 0 {
+            self.catching_empty.insert(local_pos);
+            self.update_forbidden(local_pos);
+        }
+
+        self.catchers.insert(local_pos, catcher);
     }
 
-    pub fn real_pos(voxel_block_pos: IVec2, inner_pos: IVec3) -> Vec3 {
-        ((voxel_block_pos * VOXEL_BLOCK_SIZE).extend(0).xzy() + inner_pos).as_vec3()
+    /// undoes `catch_column`, forbidden only if it was before
+    pub fn release_column(&mut self, local_pos: IVec2) {
+        if self.catchers.remove(&local_pos).is_some() {
+            self.catching_empty.remove(&local_pos);
+            self.update_forbidden(local_pos);
+        }
+    }
+
+    /// the column is under `machine` from now on, what was in it gets returned
+    /// to be put elsewhere
+    pub fn occupy_column(&mut self, local_pos: IVec2, machine: Entity) -> Vec<GameMaterial> {
+        let taken = self.take_column(local_pos);
+
+        self.occupied.insert(local_pos, machine);
+        self.update_forbidden(local_pos);
+
+        taken
     }
 
-    pub fn global_pos(voxel_block_pos: IVec2, inner_pos: IVec3) -> IVec3 {
-        (voxel_block_pos * VOXEL_BLOCK_SIZE).extend(0).xzy() + inner_pos
+    /// everything in the column, bottom up
+    pub fn take_column(&mut self, local_pos: IVec2) -> Vec<GameMaterial> {
+        let mut taken = vec![];
+
+        for y in 0..self.height_at(local_pos) {
+            let p = local_pos.extend(y).xzy();
+
+            if self[p].is_some() {
+                taken.extend(self._take_block(p));
+            }
+        }
+
+        taken
     }
 
+    /// undoes `occupy_column`, a cleared column stays forbidden
+    pub fn vacate_column(&mut self, local_pos: IVec2) {
+        if self.occupied.remove(&local_pos).is_some() {
+            self.update_forbidden(local_pos);
+        }
+    }
+
+    pub fn real_pos(voxel_block_pos: IVec3, inner_pos: IVec3) -> Vec3 {
+        Self::global_pos(voxel_block_pos, inner_pos).as_vec3()
+    }
+
+    /// `inner_pos` may be above the chunk, e.g. counted from the ground one
+    pub fn global_pos(voxel_block_pos: IVec3, inner_pos: IVec3) -> IVec3 {
+        voxel_block_pos * VOXEL_BLOCK_SIZE + inner_pos
+    }
+
+    /// the part and column `pos` is in, at the bottom; rounds down, negative
+    /// positions included
     pub fn inner_pos(pos: Vec3) -> (IVec2, IVec3) {
-        let voxel_block_pos = (pos.xz() / VOXEL_BLOCK_SIZE as f32).as_ivec2();
-        let inner_pos = (pos.xz() % VOXEL_BLOCK_SIZE as f32)
-            .as_ivec2()
-            .extend(0)
-            .xzy();
+        let (part, col) = Self::normalize_col(pos.xz().floor().as_ivec2());
 
-        (voxel_block_pos, inner_pos)
+        (part, col.extend(0).xzy())
     }
 
     pub fn is_column_empty(&self, pos: IVec2) -> bool {
@@ -153,10 +374,13 @@ impl VoxelBlock {
     /// pos in *local* coordinates
     pub fn closest_columns(&self, pos: IVec2, dist: f32) -> impl Iterator<Item = IVec2> {
         let dist_sq = dist * dist;
+        let reach = IVec2::splat(dist.ceil() as i32);
+        let lo = (pos - reach).max(IVec2::ZERO);
+        let hi = (pos + reach).min(IVec2::splat(VOXEL_BLOCK_SIZE - 1));
         let pos = pos.as_vec2();
 
-        (0..VOXEL_BLOCK_SIZE)
-            .flat_map(|x| (0..VOXEL_BLOCK_SIZE).map(move |z| IVec2::new(x, z)))
+        (lo.x..=hi.x)
+            .flat_map(move |x| (lo.y..=hi.y).map(move |z| IVec2::new(x, z)))
             .filter(move |col| {
                 let dist = (col.as_vec2() - pos).length_squared();
 
@@ -164,15 +388,48 @@ impl VoxelBlock {
             })
     }
 
-    pub fn material_in_col(
-        &self,
+    /// the voxels matching `mask` in a column through the whole `stack` of a
+    /// part, ground chunk first (`LazyWorld::stack_blocks`); positions count
+    /// from the ground chunk
+    pub fn material_in_col<'a>(
+        stack: &'a [&'a VoxelBlock],
         pos: IVec2,
         mask: u8,
-    ) -> impl Iterator<Item = (GameMaterial, IVec3)> + '_ {
-        (0..VOXEL_BLOCK_SIZE)
-            .map(move |y| pos.extend(y).xzy())
-            .filter_map(move |p| self[p].map(|mat| (mat, p)))
-            .filter(move |(mat, _)| mat.mask_contains(mask))
+    ) -> impl Iterator<Item = (GameMaterial, IVec3)> + 'a {
+        stack.iter().flat_map(move |&block| {
+            let column = block.column(pos);
+            let height = if column.mask() & mask != 0 {
+                column.height as i32
+            } else {
+                0
+            };
+            let above = IVec3::Y * block.pos.y * VOXEL_BLOCK_SIZE;
+
+            (0..height)
+                .map(move |y| pos.extend(y).xzy())
+                .filter_map(move |p| block[p].map(|mat| (mat, p + above)))
+                .filter(move |(mat, _)| mat.mask_contains(mask))
+        })
+    }
+
+    /// voxels matching `mask` in a column through the whole `stack`
+    pub fn count_in_col(stack: &[&VoxelBlock], pos: IVec2, mask: u8) -> usize {
+        stack
+            .iter()
+            .map(|block| block.column(pos).count(mask))
+            .sum()
+    }
+
+    /// one past the topmost voxel of a column through the whole `stack`, be it
+    /// borrowed or `Mut`
+    pub fn stack_height<B: Deref<Target = VoxelBlock>>(stack: &[B], pos: IVec2) -> i32 {
+        stack
+            .iter()
+            .rev()
+            .find(|block| block.height_at(pos) > 0)
+            .map_or(0, |block| {
+                block.pos.y * VOXEL_BLOCK_SIZE + block.height_at(pos)
+            })
     }
 }
 
@@ -1,59 +1,180 @@
+use std::time::Duration;
+
 use bevy::{
     diagnostic::{DiagnosticMeasurement, DiagnosticsStore},
     prelude::*,
-    utils::{HashMap, Instant},
+    tasks::ComputeTaskPool,
+    utils::{HashMap, HashSet, Instant},
 };
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::game::material::GameMaterial;
+use crate::game::{material::GameMaterial, seed::SessionRng};
 
 use super::{
-    lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock, APPLIED_CHANGES, CHANGED_BLOCKS,
-    POSTPONED_CHANGES,
+    lazyworld::LazyWorld,
+    partworld::PartWorld,
+    wholeworld::{push_voxel, WholeBlockWorld},
+    VoxelBlock, APPLIED_CHANGES, CHANGED_BLOCKS, POSTPONED_CHANGES,
 };
 
 #[derive(Resource, Default)]
 pub struct VoxelBlockChanges {
+    /// by part, positions count from its ground chunk
     pub added: HashMap<IVec2, Vec<(IVec3, GameMaterial)>>,
+    /// chunks to spawn on top of loaded parts, something's waiting to land there
+    pub wanted: HashSet<IVec3>,
+}
+
+#[derive(Debug, Resource)]
+pub struct ChangeBudget {
+    /// how long `apply_changes` may take a frame, the rest waits for the next
+    /// one; `None` for no limit, so headless runs come out the same every time
+    pub per_frame: Option<Duration>,
+}
+
+impl Default for ChangeBudget {
+    fn default() -> Self {
+        Self {
+            per_frame: Some(Duration::from_millis(4)),
+        }
+    }
 }
 
 impl VoxelBlockChanges {
     pub fn register_change(&mut self, global_pos: IVec3, mat: GameMaterial) {
-        let (voxel_block_pos, inner_pos) = VoxelBlock::normalize_pos(IVec2::ZERO, global_pos);
+        let (part, col) = VoxelBlock::normalize_col(global_pos.xz());
 
         self.added
-            .entry(voxel_block_pos)
+            .entry(part)
             .or_insert_with(Vec::new)
-            .push((inner_pos, mat));
+            .push((col.extend(global_pos.y).xzy(), mat));
+    }
+}
+
+/// what a part got through on its own in `apply_changes`
+struct PartChanges {
+    part: IVec2,
+    applied: usize,
+    /// reaching into other parts, by global position
+    spilled: Vec<(IVec3, GameMaterial)>,
+    /// over the budget, still relative to the ground chunk
+    left: Vec<(IVec3, GameMaterial)>,
+}
+
+fn past(deadline: Option<Instant>) -> bool {
+    deadline.is_some_and(|deadline| Instant::now() > deadline)
+}
+
+/// pushes a part's changes in order, as far as `deadline` allows
+fn apply_in_part(
+    mut part_world: PartWorld,
+    changes: Vec<(IVec3, GameMaterial)>,
+    rand: &mut impl Rng,
+    deadline: Option<Instant>,
+) -> PartChanges {
+    let part = part_world.part();
+    let ground = VoxelBlock::ground(part);
+
+    // it never postpones anything, it hands it over
+    let mut unused = VoxelBlockChanges::default();
+
+    let mut applied = 0;
+    let mut spilled = vec![];
+    let mut changes = changes.into_iter();
+
+    for (local_pos, mat) in changes.by_ref() {
+        let global_pos = VoxelBlock::global_pos(ground, local_pos);
+
+        match push_voxel(
+            &mut part_world,
+            global_pos,
+            mat,
+            &mut unused,
+            rand,
+            Some(Color::BLUE),
+        ) {
+            Some(()) => applied += 1,
+            None => spilled.push((global_pos, mat)),
+        }
+
+        if past(deadline) {
+            break;
+        }
+    }
+
+    PartChanges {
+        part,
+        applied,
+        spilled,
+        left: changes.collect(),
     }
 }
 
+/// Pushes what's waiting for the loaded parts in two phases: each part on its
+/// own on the compute task pool, then whatever spilled into other parts, one
+/// after another.
 pub fn apply_changes(
     mut changes: ResMut<VoxelBlockChanges>,
-    blocks: Query<&mut VoxelBlock>,
+    mut blocks: Query<&mut VoxelBlock>,
     mut diagnostics: ResMut<DiagnosticsStore>,
     lazy_world: Res<LazyWorld>,
+    budget: Res<ChangeBudget>,
+    mut rng: ResMut<SessionRng>,
 ) {
-    let mut whole_world = WholeBlockWorld { lazy_world, blocks };
+    let start = Instant::now();
+    // half of it for the parts, so there's time left for what spills out of them
+    let parts_deadline = budget.per_frame.map(|per_frame| start + per_frame / 2);
+    let deadline = budget.per_frame.map(|per_frame| start + per_frame);
 
     let mut total_changes = 0;
     let mut total_postponed = 0;
-    let mut changed_blocks = 0;
 
-    let rand = &mut rand::thread_rng();
-    let mut new_changes = VoxelBlockChanges::default();
+    let rand = &mut rng.0;
+
+    let part_worlds = PartWorld::split(blocks.iter_mut(), |part| {
+        changes.added.get(&part).is_some_and(|ch| !ch.is_empty())
+    });
 
-    for (block_pos, changes) in changes.added.iter_mut() {
-        if whole_world.is_initialized_by_blockpos(*block_pos) {
-            changed_blocks += 1;
+    let changed_blocks = part_worlds.len();
 
-            for (local_pos, mat) in changes.drain(..) {
-                total_changes += 1;
+    // drawn here so every part gets the same rng whatever thread it runs on
+    let tasks = part_worlds
+        .into_iter()
+        .map(|part_world| {
+            let part_changes = std::mem::take(changes.added.get_mut(&part_world.part()).unwrap());
 
-                let global_pos = VoxelBlock::global_pos(*block_pos, local_pos);
+            (part_world, part_changes, StdRng::seed_from_u64(rand.gen()))
+        })
+        .collect::<Vec<_>>();
 
-                whole_world.push_block(global_pos, mat, &mut new_changes, rand, Some(Color::BLUE));
-            }
+    let results = ComputeTaskPool::get().scope(|scope| {
+        for (part_world, part_changes, mut part_rand) in tasks {
+            scope.spawn(async move {
+                apply_in_part(part_world, part_changes, &mut part_rand, parts_deadline)
+            });
         }
+    });
+
+    let mut whole_world = WholeBlockWorld { lazy_world, blocks };
+    let mut new_changes = VoxelBlockChanges::default();
+    let mut spilled = vec![];
+
+    for result in results {
+        total_changes += result.applied;
+        spilled.extend(result.spilled);
+
+        changes.added.insert(result.part, result.left);
+    }
+
+    for (global_pos, mat) in spilled {
+        if past(deadline) {
+            changes.register_change(global_pos, mat);
+            continue;
+        }
+
+        total_changes += 1;
+
+        whole_world.push_block(global_pos, mat, &mut new_changes, rand, Some(Color::BLUE));
     }
 
     for (pos, ch) in new_changes.added.drain() {
@@ -61,6 +182,8 @@ pub fn apply_changes(
         changes.added.entry(pos).or_insert_with(Vec::new).extend(ch);
     }
 
+    changes.wanted.extend(new_changes.wanted);
+
     let measurements = [
         (APPLIED_CHANGES, total_changes),
         (POSTPONED_CHANGES, total_postponed),
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+
+use crate::game::material::GameMaterial;
+
+use super::{CHUNK_LEN, VOXEL_BLOCK_SIZE};
+
+const N: i32 = VOXEL_BLOCK_SIZE;
+
+fn at(grid: &[Option<GameMaterial>; CHUNK_LEN], p: [i32; 3]) -> Option<GameMaterial> {
+    if p.iter().any(|c| *c < 0 || *c >= N) {
+        return None;
+    }
+
+    // same layout as the grid: x, then z, then y
+    grid[(p[0] + p[2] * N + p[1] * N * N) as usize]
+}
+
+/// Merges same-material faces into as few quads as it can. Same looks as
+/// meshem's culling (voxels centered on their position, faces on the block
+/// border kept), but can't be patched with `update_mesh`.
+pub fn greedy_mesh(grid: &[Option<GameMaterial>; CHUNK_LEN]) -> Mesh {
+    let mut positions: Vec<[f32; 3]> = vec![];
+    let mut normals: Vec<[f32; 3]> = vec![];
+    let mut colors: Vec<[f32; 4]> = vec![];
+    let mut indices: Vec<u32> = vec![];
+
+    let mut mask = [None; (N * N) as usize];
+
+    for d in 0..3 {
+        // (d, u, v) is always a right-handed axis order
+        let u = (d + 1) % 3;
+        let v = (d + 2) % 3;
+
+        for sign in [1, -1] {
+            let mut normal = [0.0; 3];
+            normal[d] = sign as f32;
+
+            for slice in 0..N {
+                for j in 0..N {
+                    for i in 0..N {
+                        let mut p = [0; 3];
+                        p[d] = slice;
+                        p[u] = i;
+                        p[v] = j;
+
+                        let mut q = p;
+                        q[d] += sign;
+
+                        mask[(i + j * N) as usize] = at(grid, p).filter(|_| at(grid, q).is_none());
+                    }
+                }
+
+                for j in 0..N {
+                    let mut i = 0;
+
+                    while i < N {
+                        let Some(mat) = mask[(i + j * N) as usize] else {
+                            i += 1;
+                            continue;
+                        };
+
+                        let mut w = 1;
+                        while i + w < N && mask[(i + w + j * N) as usize] == Some(mat) {
+                            w += 1;
+                        }
+
+                        let mut h = 1;
+                        'grow: while j + h < N {
+                            for k in 0..w {
+                                if mask[(i + k + (j + h) * N) as usize] != Some(mat) {
+                                    break 'grow;
+                                }
+                            }
+                            h += 1;
+                        }
+
+                        for jj in 0..h {
+                            for ii in 0..w {
+                                mask[(i + ii + (j + jj) * N) as usize] = None;
+                            }
+                        }
+
+                        let first = positions.len() as u32;
+                        let corners = [(0, 0), (w, 0), (w, h), (0, h)];
+
+                        for (cu, cv) in corners {
+                            let mut pos = [0.0; 3];
+                            pos[d] = slice as f32 + sign as f32 * 0.5;
+                            pos[u] = (i + cu) as f32 - 0.5;
+                            pos[v] = (j + cv) as f32 - 0.5;
+
+                            positions.push(pos);
+                            normals.push(normal);
+                            colors.push(Color::from(&mat).as_rgba_f32());
+                        }
+
+                        // counter-clockwise seen from the outside
+                        let quad = if sign > 0 {
+                            [0, 1, 2, 0, 2, 3]
+                        } else {
+                            [0, 2, 1, 0, 3, 2]
+                        };
+
+                        indices.extend(quad.map(|k| first + k));
+
+                        i += w;
+                    }
+                }
+            }
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+
+    mesh
+}
@@ -5,12 +5,18 @@ use bevy::{
     prelude::*,
     utils::{HashMap, Instant},
 };
-use rand::Rng;
 use uuid::uuid;
 
-use crate::game::material::GameMaterial;
+use crate::game::seed::WorldSeed;
 
-use super::{generate_voxel_block, VoxelBlockChanges, VoxelResources, VOXEL_BLOCK_SIZE};
+use super::{
+    generate_voxel_block,
+    paging::{page_out_distant_parts, StoredPart},
+    storage::VoxelStorage,
+    terrain::{TerrainGenerator, WorldGenerator},
+    voxel_block_from_grid, VoxelBlock, VoxelBlockBundle, VoxelBlockChanges, VoxelGraphics,
+    VOXEL_BLOCK_SIZE,
+};
 
 pub struct LazyWorldPlugin;
 
@@ -18,45 +24,79 @@ pub const WORLD_PARTS_DIAGNOSTIC: DiagnosticId =
     DiagnosticId(uuid!("71a16bb7-7b2a-4be4-9bad-ddbc591f42f5"));
 pub const UNAPPLIED_CHANGES: DiagnosticId =
     DiagnosticId(uuid!("12a019af-d250-4d23-99b1-3079ee897d8f"));
+/// what the spawned chunks' voxels take, in kB
+pub const VOXEL_STORAGE: DiagnosticId = DiagnosticId(uuid!("3c0b6c1e-5f0e-4d7a-9a61-2b8f4d1e7c55"));
 
 impl Plugin for LazyWorldPlugin {
     fn build(&self, app: &mut App) {
         app.register_diagnostic(Diagnostic::new(WORLD_PARTS_DIAGNOSTIC, "world_parts", 1))
             .register_diagnostic(Diagnostic::new(UNAPPLIED_CHANGES, "unapplied_changes", 10))
+            .register_diagnostic(Diagnostic::new(VOXEL_STORAGE, "voxel_storage_kb", 10))
             .insert_resource(LazyWorld {
                 known_parts: HashMap::new(),
+                paged_out: HashMap::new(),
             })
-            .add_systems(Update, handle_camera)
+            .init_resource::<WorldGenerator>()
+            .add_systems(Update, (handle_camera, page_out_distant_parts, grow_stacks))
             .add_systems(Update, diagnostics);
     }
 }
 
 #[derive(Debug, Resource, Reflect)]
 pub struct LazyWorld {
-    pub known_parts: HashMap<IVec2, Entity>,
+    /// by chunk; a part is loaded with its ground chunk, the ones above it
+    /// stack up from there without gaps
+    pub known_parts: HashMap<IVec3, Entity>,
+    /// generated once, then despawned for being too far from everything; whole
+    /// stacks, ground chunk first
+    #[reflect(ignore)]
+    pub paged_out: HashMap<IVec2, Vec<StoredPart>>,
 }
 
 impl LazyWorld {
-    pub fn lookup_around(
-        &self,
-        center: IVec2,
-        radius: f32,
-    ) -> impl Iterator<Item = (IVec2, Entity)> + '_ {
+    /// the loaded parts around `center`
+    pub fn lookup_around(&self, center: IVec2, radius: f32) -> impl Iterator<Item = IVec2> + '_ {
         // to bigblock-space
         // a bit more to handle close columns of far blocks
         let radius = radius / VOXEL_BLOCK_SIZE as f32 + 1.42;
         let radius2 = radius * radius;
         let center = center.as_vec2() / VOXEL_BLOCK_SIZE as f32;
+        let min = (center - radius).floor().as_ivec2();
+        let max = (center + radius).ceil().as_ivec2();
 
-        self.known_parts.iter().filter_map(move |(part, &entity)| {
-            let dist = (part.as_vec2() - center).length_squared();
-            if dist <= radius2 {
-                Some((*part, entity))
-            } else {
-                None
-            }
+        // the parts in reach, not every known chunk
+        (min.x..=max.x)
+            .flat_map(move |x| (min.y..=max.y).map(move |z| IVec2::new(x, z)))
+            .filter(move |part| {
+                (part.as_vec2() - center).length_squared() <= radius2
+                    && self.known_parts.contains_key(&VoxelBlock::ground(*part))
+            })
+    }
+
+    pub fn ground(&self, part: IVec2) -> Option<Entity> {
+        self.known_parts.get(&VoxelBlock::ground(part)).copied()
+    }
+
+    /// the part's chunks, ground first
+    pub fn stack(&self, part: IVec2) -> impl Iterator<Item = (IVec3, Entity)> + '_ {
+        (0..).map_while(move |y| {
+            let chunk = part.extend(y).xzy();
+
+            self.known_parts.get(&chunk).map(|e| (chunk, *e))
         })
     }
+
+    /// the spawned chunks of the part, ground first, for `VoxelBlock::material_in_col`
+    /// and the like; empty if the part isn't loaded
+    pub fn stack_blocks<'a>(
+        &self,
+        part: IVec2,
+        blocks: &'a Query<&VoxelBlock>,
+    ) -> Vec<&'a VoxelBlock> {
+        self.stack(part)
+            .map_while(|(_, e)| blocks.get(e).ok())
+            .collect()
+    }
 }
 
 static AROUND_2D: &[IVec2] = &[
@@ -71,78 +111,77 @@ static AROUND_2D: &[IVec2] = &[
     IVec2::new(1, 1),
 ];
 
-fn generate_part(
-    commands: &mut Commands,
+/// the part's ground chunk as the generator has it, each column written in
+/// bottom up, so it comes out the same whatever was generated around it before
+fn generate_ground(
     part: IVec2,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    voxel_resources: &Res<VoxelResources>,
-    changes: &mut VoxelBlockChanges,
-) -> Entity {
-    let center = (part * VOXEL_BLOCK_SIZE).extend(0).xzy();
+    graphics: &mut VoxelGraphics,
+    seed: &WorldSeed,
+    generator: &dyn TerrainGenerator,
+) -> VoxelBlockBundle {
+    let mut grid = VoxelStorage::default();
+    let mut cleared = vec![];
 
-    // dbg!(&center);
+    let rand = &mut seed.part_rng(part);
 
-    let mut bundle = generate_voxel_block(part, meshes, voxel_resources);
-    bundle.pbr_bundle.transform = Transform::from_translation(center.as_vec3());
+    for x in 0..VOXEL_BLOCK_SIZE {
+        for z in 0..VOXEL_BLOCK_SIZE {
+            let col = IVec2::new(x, z);
+            let global_col = part * VOXEL_BLOCK_SIZE + col;
 
-    if center == IVec3::ZERO {
-        //     bdl.1.material = voxel_resources.debug_voxel_material.clone();
-
-        for x in 0..23 {
-            for z in 0..23 {
-                bundle.voxel_block.forbid_column(IVec2::new(x, z));
+            if generator.is_cleared(global_col) {
+                cleared.push(col);
+                continue;
             }
-        }
-    }
 
-    let half_chunk = VOXEL_BLOCK_SIZE / 2;
-    let rand = &mut rand::thread_rng();
+            // whatever doesn't fit in the ground chunk is left out
+            let column = generator.column(seed, global_col, rand);
 
-    for x in -half_chunk..half_chunk {
-        for z in -half_chunk..half_chunk {
-            let pos = IVec3::new(x, 0, z) + IVec3::new(half_chunk, 0, half_chunk);
+            for (y, mat) in column
+                .into_iter()
+                .take(VOXEL_BLOCK_SIZE as usize)
+                .enumerate()
+            {
+                let idx = x + z * VOXEL_BLOCK_SIZE + y as i32 * VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE;
 
-            let cnt = {
-                if rand.gen_range(1..50) == 1 {
-                    rand.gen_range(5..=10)
-                } else {
-                    rand.gen_range(0..=2)
-                }
-
-                // rand.gen_range(0..=2)
-            };
-            // let cnt = 1;
-            for _z in 0..cnt {
-                let global_pos = center + pos;
-
-                changes.register_change(
-                    global_pos + IVec3::new(0, VOXEL_BLOCK_SIZE, 0),
-                    GameMaterial::random(rand),
-                );
+                grid.set(idx as usize, Some(mat));
             }
         }
     }
 
-    commands.spawn(bundle).id()
+    let mut bundle = voxel_block_from_grid(VoxelBlock::ground(part), grid, graphics);
+
+    for col in cleared {
+        bundle.voxel_block.forbid_column(col);
+    }
+
+    bundle
 }
 
 #[derive(Component)]
 pub struct WorldGenTrigger(pub Vec2);
 
+/// the part the trigger is centered on
+pub fn trigger_part(trigger: &WorldGenTrigger, trans: &GlobalTransform) -> IVec2 {
+    ((trans.translation().xz() - trigger.0) / VOXEL_BLOCK_SIZE as f32)
+        .floor()
+        .as_ivec2()
+}
+
 fn handle_camera(
     // q_camera: Query<&GlobalTransform, With<MainCamera>>,
     q_trigger: Query<(&WorldGenTrigger, &GlobalTransform)>,
     mut lazy_world: ResMut<LazyWorld>,
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    voxel_resources: Res<VoxelResources>,
-    mut blockchanges: ResMut<VoxelBlockChanges>,
+    mut graphics: VoxelGraphics,
+    seed: Res<WorldSeed>,
+    generator: Res<WorldGenerator>,
 ) {
     // let camera = q_camera.single();
 
     for (trigger, trans) in q_trigger.iter() {
         // let center = ((camera.translation() - CAMERA_OFFSET).xz() / VOXEL_BLOCK_SIZE as f32).as_ivec2();
-        let center = ((trans.translation().xz() - trigger.0) / VOXEL_BLOCK_SIZE as f32).as_ivec2();
+        let center = trigger_part(trigger, trans);
 
         let all_around = AROUND_2D
             .iter()
@@ -152,19 +191,32 @@ fn handle_camera(
 
         for part in all_around {
             // println!("Checking part {:?}", part);
-            if !lazy_world.known_parts.contains_key(&part) {
+            if lazy_world.ground(part).is_none() {
+                // all of the stack or none of it
+                let restored = lazy_world.paged_out.remove(&part).and_then(|stack| {
+                    stack
+                        .iter()
+                        .map(|stored| stored.restore(&mut graphics))
+                        .collect::<Option<Vec<_>>>()
+                });
+
                 // println!("Generating part {:?} around {center:?}", part);
 
-                lazy_world.known_parts.insert(
-                    part,
-                    generate_part(
-                        &mut commands,
-                        part,
-                        &mut meshes,
-                        &voxel_resources,
-                        &mut blockchanges,
-                    ),
-                );
+                if let Some(bundles) = restored {
+                    for bundle in bundles {
+                        let chunk = bundle.voxel_block.pos;
+
+                        lazy_world
+                            .known_parts
+                            .insert(chunk, commands.spawn(bundle).id());
+                    }
+                } else {
+                    let bundle = generate_ground(part, &mut graphics, &seed, generator.0.as_ref());
+
+                    lazy_world
+                        .known_parts
+                        .insert(VoxelBlock::ground(part), commands.spawn(bundle).id());
+                }
                 // break;
             }
             // break;
@@ -172,10 +224,41 @@ fn handle_camera(
     }
 }
 
+/// spawns the chunks that voxels landed in above their part's stack, the
+/// changes waiting for them get applied once they're there
+fn grow_stacks(
+    mut commands: Commands,
+    mut lazy_world: ResMut<LazyWorld>,
+    mut changes: ResMut<VoxelBlockChanges>,
+    mut graphics: VoxelGraphics,
+) {
+    for chunk in changes.wanted.drain() {
+        // paged out in the meantime, it'll be back with the rest of the part
+        if lazy_world.ground(chunk.xz()).is_none() {
+            continue;
+        }
+
+        for y in 1..=chunk.y {
+            let chunk = chunk.xz().extend(y).xzy();
+
+            if lazy_world.known_parts.contains_key(&chunk) {
+                continue;
+            }
+
+            let e = commands
+                .spawn(generate_voxel_block(chunk, &mut graphics))
+                .id();
+
+            lazy_world.known_parts.insert(chunk, e);
+        }
+    }
+}
+
 fn diagnostics(
     mut diagnostics: ResMut<DiagnosticsStore>,
     lazy_world: ResMut<LazyWorld>,
     blockchanges: ResMut<VoxelBlockChanges>,
+    q_blocks: Query<&VoxelBlock>,
 ) {
     diagnostics
         .get_mut(WORLD_PARTS_DIAGNOSTIC)
@@ -192,4 +275,51 @@ fn diagnostics(
             time: Instant::now(),
             value: blockchanges.added.values().map(|v| v.len()).sum::<usize>() as f64,
         });
+
+    diagnostics
+        .get_mut(VOXEL_STORAGE)
+        .unwrap()
+        .add_measurement(DiagnosticMeasurement {
+            time: Instant::now(),
+            value: q_blocks.iter().map(|b| b.grid.heap_size()).sum::<usize>() as f64 / 1024.0,
+        });
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+
+    use crate::{
+        game::seed::WorldSeed,
+        graphics::voxels3d::{terrain::NoiseTerrain, VoxelGraphics},
+    };
+
+    use super::{generate_ground, AROUND_2D};
+
+    #[test]
+    fn part_generates_the_same_after_its_neighbours() {
+        let seed = WorldSeed(11);
+        let generator = NoiseTerrain::default();
+        let part = IVec2::new(1, -2);
+
+        let mut graphics = VoxelGraphics {
+            meshes: None,
+            voxel_resources: None,
+        };
+
+        let mut generate =
+            |part| generate_ground(part, &mut graphics, &seed, &generator).voxel_block;
+
+        let alone = generate(part);
+
+        for offset in AROUND_2D.iter().filter(|offset| **offset != IVec2::ZERO) {
+            generate(part + *offset);
+        }
+
+        let after = generate(part);
+
+        assert!(alone.grid.iter().any(|voxel| voxel.is_some()));
+        assert!(alone.grid.to_grid() == after.grid.to_grid());
+        assert_eq!(alone.forbidden_columns, after.forbidden_columns);
+    }
 }
@@ -1,29 +1,49 @@
-use crate::game::{material::GameMaterial, voxelmailbox::VoxelMailbox};
+use crate::game::{material::GameMaterial, seed::SessionRngSet, voxelmailbox::VoxelMailbox};
+
+use std::{cmp::Reverse, collections::BinaryHeap, time::Duration};
 
 use bevy::{
+    core::FrameCount,
     diagnostic::{Diagnostic, DiagnosticId, RegisterDiagnostic},
+    ecs::system::SystemParam,
     prelude::*,
+    utils::Instant,
 };
 use bevy_meshem::{prelude::*, Dimensions};
-use rand::prelude::Rng;
 
 use self::{
-    changes::{apply_changes, VoxelBlockChanges},
+    changes::{apply_changes, ChangeBudget, VoxelBlockChanges},
+    lazyworld::WorldGenTrigger,
+    slopes::{settle_slopes, SlopeStability},
     voxel_mesh::generate_colored_voxel_mesh,
 };
+use super::{camera3d::MainCamera, machines::radar::RadarType};
 use uuid::uuid;
 
 // mod meshem;
 pub mod blocks;
+mod greedy;
 pub mod lazyworld;
+pub mod paging;
+pub mod terrain;
 mod voxel_mesh;
 pub mod voxel_physics;
+use blocks::BlockIndex;
 pub use blocks::VoxelBlock;
+use storage::VoxelStorage;
 pub mod changes;
+pub mod partworld;
+pub mod slopes;
+pub mod storage;
+#[cfg(test)]
+pub mod testworld;
 pub mod wholeworld;
 
 pub const VOXEL_BLOCK_SIZE: i32 = 32;
-const CHUNK_LEN: usize = (VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE) as usize;
+pub const CHUNK_LEN: usize = (VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE) as usize;
+/// what's dropped onto a column starts falling from here, above any pile;
+/// `push_block` skips the air in between
+pub const DROP_HEIGHT: i32 = 1 << 20;
 
 pub const APPLIED_CHANGES: DiagnosticId =
     DiagnosticId(uuid!("a4a701b9-f1bc-4552-a9a0-7e0ec1a14bbc"));
@@ -39,12 +59,29 @@ impl Plugin for Voxels3dPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup)
             .add_systems(Update, update_meshes)
-            .add_plugins(voxel_physics::VoxelPhysics)
-            .add_systems(Update, (apply_changes, consume_mailbox))
-            .insert_resource(VoxelBlockChanges::default())
-            .register_diagnostic(Diagnostic::new(APPLIED_CHANGES, "applied_changes", 10))
-            .register_diagnostic(Diagnostic::new(POSTPONED_CHANGES, "postponed_changes", 10))
-            .register_diagnostic(Diagnostic::new(CHANGED_BLOCKS, "changed_blocks", 10));
+            .add_plugins((VoxelSimPlugin, voxel_physics::VoxelPhysics));
+    }
+}
+
+/// Voxel changes without any meshes, enough for headless runs.
+pub struct VoxelSimPlugin;
+impl Plugin for VoxelSimPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                apply_changes.in_set(SessionRngSet::Changes),
+                consume_mailbox,
+                deliver_caught.after(apply_changes),
+                settle_slopes.after(apply_changes),
+            ),
+        )
+        .insert_resource(VoxelBlockChanges::default())
+        .init_resource::<ChangeBudget>()
+        .init_resource::<SlopeStability>()
+        .register_diagnostic(Diagnostic::new(APPLIED_CHANGES, "applied_changes", 10))
+        .register_diagnostic(Diagnostic::new(POSTPONED_CHANGES, "postponed_changes", 10))
+        .register_diagnostic(Diagnostic::new(CHANGED_BLOCKS, "changed_blocks", 10));
     }
 }
 
@@ -178,9 +215,14 @@ pub struct VoxelBlockBundle {
     pub mailbox: VoxelMailbox,
 }
 
-pub fn generate_mesh_grid(
+pub fn generate_mesh_grid(grid: &[Option<GameMaterial>; CHUNK_LEN]) -> Mesh {
+    greedy::greedy_mesh(grid)
+}
+
+/// more triangles than `generate_mesh_grid`, but `update_mesh` can patch it
+pub fn generate_patchable_mesh(
     voxel_resources: &Res<VoxelResources>,
-    grid: &[Option<GameMaterial>; 32768],
+    grid: &[Option<GameMaterial>; CHUNK_LEN],
 ) -> (Mesh, MeshMD<Option<GameMaterial>>) {
     let dims: Dimensions = (
         VOXEL_BLOCK_SIZE as usize,
@@ -201,31 +243,55 @@ pub fn generate_mesh_grid(
     .unwrap()
 }
 
-pub fn generate_voxel_block(
-    pos: IVec2,
-    meshes: &mut ResMut<Assets<Mesh>>,
-    voxel_resources: &Res<VoxelResources>,
-) -> VoxelBlockBundle {
-    let _rand = &mut rand::thread_rng();
+/// What blocks need for their meshes. Both are missing in headless runs,
+/// blocks get no mesh then.
+#[derive(SystemParam)]
+pub struct VoxelGraphics<'w> {
+    pub meshes: Option<ResMut<'w, Assets<Mesh>>>,
+    pub voxel_resources: Option<Res<'w, VoxelResources>>,
+}
 
-    let g: [_; CHUNK_LEN] = [None; CHUNK_LEN]; // grid.try_into().unwrap();
+pub fn generate_voxel_block(pos: IVec3, graphics: &mut VoxelGraphics) -> VoxelBlockBundle {
+    voxel_block_from_grid(pos, VoxelStorage::default(), graphics)
+}
 
+/// a block with already known contents, e.g. restored from a save
+pub fn voxel_block_from_grid(
+    pos: IVec3,
+    grid: VoxelStorage,
+    graphics: &mut VoxelGraphics,
+) -> VoxelBlockBundle {
     // let texture_mesh = asset_server.load("array_texture.png");
-    let (culled_mesh, metadata) = generate_mesh_grid(voxel_resources, &g);
-
-    let culled_mesh_handle: Handle<Mesh> = meshes.add(culled_mesh.clone());
+    let (mesh_handle, material) = match (&mut graphics.meshes, &graphics.voxel_resources) {
+        (Some(meshes), Some(voxel_resources)) => (
+            meshes.add(generate_mesh_grid(&grid.to_grid())),
+            voxel_resources.voxel_material.clone(),
+        ),
+        _ => default(),
+    };
+    let index = BlockIndex::new(&grid);
 
     VoxelBlockBundle {
         voxel_block: VoxelBlock {
             pos,
-            meta: metadata,
-            grid: g,
-            mesh_id: culled_mesh_handle.id(),
+            meta: None,
+            mesh_log: 0,
+            mesh_updated: 0,
+            grid,
+            mesh_id: mesh_handle.id(),
             forbidden_columns: [[false; VOXEL_BLOCK_SIZE as usize]; VOXEL_BLOCK_SIZE as usize],
+            cleared_columns: [[false; VOXEL_BLOCK_SIZE as usize]; VOXEL_BLOCK_SIZE as usize],
+            index,
+            catchers: default(),
+            catching_empty: default(),
+            caught: vec![],
+            occupied: default(),
+            unsettled: default(),
         },
         pbr_bundle: PbrBundle {
-            mesh: culled_mesh_handle,
-            material: voxel_resources.voxel_material.clone(),
+            mesh: mesh_handle,
+            material,
+            transform: Transform::from_translation((pos * VOXEL_BLOCK_SIZE).as_vec3()),
             ..default()
         },
         mailbox: VoxelMailbox::default(),
@@ -245,46 +311,207 @@ pub fn generate_voxel_block(
 //     }
 // }
 
+/// time spent on chunk meshes per frame, at least one chunk gets done anyway
+const MESHING_BUDGET: Duration = Duration::from_millis(4);
+/// frames without changes before a chunk gets its mesh merged
+const SETTLE_FRAMES: u32 = 120;
+
 fn update_meshes(
     mut meshes: ResMut<Assets<Mesh>>,
-    mut blocks: Query<(&mut VoxelBlock, &mut Handle<Mesh>), Changed<VoxelBlock>>,
+    mut blocks: Query<(Entity, &mut VoxelBlock, &mut Handle<Mesh>)>,
     voxel_resources: Res<VoxelResources>,
+    q_camera: Query<(&WorldGenTrigger, &GlobalTransform), With<MainCamera>>,
+    frame: Res<FrameCount>,
 ) {
-    // let vr = voxel_resources.into_inner();
-    let rand = &mut rand::thread_rng();
+    let focus = q_camera
+        .get_single()
+        .map_or(Vec2::ZERO, |(trigger, trans)| {
+            trans.translation().xz() - trigger.0
+        });
+
+    // changed chunks before settled ones, nearest first
+    let mut queue = BinaryHeap::new();
+
+    for (e, block, _) in blocks.iter() {
+        let settled =
+            block.meta.is_some() && frame.0.wrapping_sub(block.mesh_updated) > SETTLE_FRAMES;
+
+        if block.mesh_log == 0 && !settled {
+            continue;
+        }
+
+        let center = (block.part() * VOXEL_BLOCK_SIZE).as_vec2() + VOXEL_BLOCK_SIZE as f32 / 2.0;
+        let dist = center.distance(focus) as u32;
+
+        queue.push(Reverse((block.mesh_log == 0, dist, e)));
+    }
 
-    for (mut block, mut mesh) in blocks.iter_mut() {
-        if rand.gen_range(0..=4) > 0 {
-            let Some(mesh) = meshes.get_mut(block.mesh_id) else {
-                continue;
-            };
+    let start = Instant::now();
 
-            update_mesh(mesh, &mut block.meta, &&*voxel_resources);
+    while let Some(Reverse((_, _, e))) = queue.pop() {
+        let (_, mut block, mut mesh) = blocks.get_mut(e).unwrap();
+        let block = &mut *block;
+
+        if block.mesh_log == 0 {
+            // nothing's happening here anymore
+            *mesh = meshes.add(generate_mesh_grid(&block.grid.to_grid()));
+            block.meta = None;
+            block.grid.compact();
+        } else if let (Some(meta), Some(mesh)) = (block.meta.as_mut(), meshes.get_mut(&*mesh)) {
+            update_mesh(mesh, meta, &&*voxel_resources);
         } else {
-            let (culled_mesh, metadata) = generate_mesh_grid(&voxel_resources, &block.grid);
-            let culled_mesh_handle: Handle<Mesh> = meshes.add(culled_mesh.clone());
+            let (culled_mesh, metadata) =
+                generate_patchable_mesh(&voxel_resources, &block.grid.to_grid());
+
+            *mesh = meshes.add(culled_mesh);
+            block.meta = Some(metadata);
+        }
 
-            block.meta = metadata;
-            block.mesh_id = culled_mesh_handle.id();
+        block.mesh_id = mesh.id();
+        block.mesh_log = 0;
+        block.mesh_updated = frame.0;
 
-            *mesh = culled_mesh_handle;
+        if start.elapsed() > MESHING_BUDGET {
+            break;
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use bevy::math::{IVec2, IVec3, Vec3};
+    use bevy::{
+        math::{IVec2, IVec3, Vec3, Vec3Swizzles},
+        prelude::Entity,
+    };
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::{
+        game::material::GameMaterial,
+        graphics::voxels3d::{
+            generate_voxel_block, paging::StoredPart, VoxelBlock, VoxelGraphics, VOXEL_BLOCK_SIZE,
+        },
+    };
 
-    use crate::graphics::voxels3d::VoxelBlock;
+    fn ground_block() -> VoxelBlock {
+        let mut graphics = VoxelGraphics {
+            meshes: None,
+            voxel_resources: None,
+        };
+
+        generate_voxel_block(IVec3::ZERO, &mut graphics).voxel_block
+    }
+
+    fn forbidden(block: &VoxelBlock, col: IVec2) -> bool {
+        block.forbidden_columns[col.x as usize][col.y as usize]
+    }
 
     #[test]
-    fn hmm() {
-        dbg!(VoxelBlock::real_pos(IVec2::new(0, 0), IVec3::new(0, 0, 0)));
-        dbg!(VoxelBlock::inner_pos(Vec3::new(0.0, 0.0, 0.0)));
-        dbg!(VoxelBlock::real_pos(IVec2::new(0, 1), IVec3::new(0, 0, 0)));
+    fn releasing_only_undoes_catching() {
+        let mut block = ground_block();
+        let conveyor = Entity::from_raw(1);
+        let (cleared, empty, full) = (IVec2::new(1, 1), IVec2::new(2, 1), IVec2::new(3, 1));
+
+        block.forbid_column(cleared);
+        block._add_block(full.extend(0).xzy(), GameMaterial::Reddish);
+
+        for col in [cleared, empty, full] {
+            block.catch_column(col, conveyor);
+        }
+
+        assert!(forbidden(&block, cleared) && forbidden(&block, empty) && !forbidden(&block, full));
 
-        dbg!(VoxelBlock::real_pos(IVec2::new(0, 1), IVec3::new(1, 0, 1)));
+        for col in [cleared, empty, full] {
+            block.release_column(col);
+        }
+
+        assert!(forbidden(&block, cleared));
+        assert!(!forbidden(&block, empty));
+        assert!(!forbidden(&block, full));
+    }
+
+    #[test]
+    fn vacating_leaves_cleared_columns_cleared() {
+        let mut block = ground_block();
+        let machine = Entity::from_raw(2);
+        let (cleared, full) = (IVec2::new(4, 4), IVec2::new(5, 4));
+
+        block.forbid_column(cleared);
+        block._add_block(full.extend(0).xzy(), GameMaterial::Blueish);
+
+        assert!(block.occupy_column(cleared, machine).is_empty());
+        assert_eq!(block.occupy_column(full, machine), [GameMaterial::Blueish]);
+        assert!(forbidden(&block, cleared) && forbidden(&block, full));
+
+        // paged out under the machine, it comes back cleared
+        assert_eq!(StoredPart::new(&block).cleared_columns, [cleared]);
+
+        block.vacate_column(cleared);
+        block.vacate_column(full);
+
+        assert!(forbidden(&block, cleared));
+        assert!(!forbidden(&block, full));
+    }
+
+    #[test]
+    fn normalize_pos_round_trips() {
+        let rand = &mut StdRng::seed_from_u64(1);
+
+        for _ in 0..10_000 {
+            let global = IVec3::new(
+                rand.gen_range(-500..500),
+                rand.gen_range(0..500),
+                rand.gen_range(-500..500),
+            );
+
+            let (block, local) = VoxelBlock::normalize_pos(IVec3::ZERO, global);
+
+            assert!(VoxelBlock::within_bounds(local), "{global} -> {local}");
+            assert_eq!(VoxelBlock::global_pos(block, local), global);
+
+            // starting from some other block makes no difference
+            let start = IVec3::new(
+                rand.gen_range(-20..20),
+                rand.gen_range(0..20),
+                rand.gen_range(-20..20),
+            );
+            let from_start = VoxelBlock::normalize_pos(start, global - start * VOXEL_BLOCK_SIZE);
+
+            assert_eq!(from_start, (block, local));
+
+            let (part, col) = VoxelBlock::normalize_col(global.xz());
+
+            assert_eq!((part, col), (block.xz(), local.xz()));
+        }
+    }
+
+    #[test]
+    fn inner_pos_rounds_down() {
+        let rand = &mut StdRng::seed_from_u64(2);
+
+        for _ in 0..10_000 {
+            let pos = Vec3::new(
+                rand.gen_range(-500.0..500.0),
+                0.0,
+                rand.gen_range(-500.0..500.0),
+            );
+
+            let (part, local) = VoxelBlock::inner_pos(pos);
+
+            assert!(VoxelBlock::within_bounds(local), "{pos} -> {local}");
+            assert_eq!(
+                VoxelBlock::global_pos(VoxelBlock::ground(part), local),
+                pos.floor().as_ivec3() * IVec3::new(1, 0, 1)
+            );
+        }
+
+        assert_eq!(
+            VoxelBlock::inner_pos(Vec3::new(-0.5, 0.0, -33.2)),
+            (IVec2::new(-1, -2), IVec3::new(31, 0, 30))
+        );
+        assert_eq!(
+            VoxelBlock::inner_pos(Vec3::new(31.9, 0.0, 32.0)),
+            (IVec2::new(0, 1), IVec3::new(31, 0, 0))
+        );
     }
 }
 
@@ -300,3 +527,24 @@ fn consume_mailbox(
         changes.register_change(target, vc);
     }
 }
+
+fn deliver_caught(
+    mut q_blocks: Query<&mut VoxelBlock>,
+    mut q_mailboxes: Query<&mut VoxelMailbox, Without<VoxelBlock>>,
+    mut changes: ResMut<VoxelBlockChanges>,
+) {
+    for mut block in q_blocks.iter_mut() {
+        if block.caught.is_empty() {
+            continue;
+        }
+
+        for (catcher, pos, mat) in std::mem::take(&mut block.caught) {
+            if let Ok(mut mb) = q_mailboxes.get_mut(catcher) {
+                mb.0.push_back((pos, mat, RadarType::Work));
+            } else {
+                // the catcher is gone, its column is let go of after this
+                changes.register_change(pos, mat);
+            }
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{game::voxelmailbox::VoxelMailbox, graphics::flyingvoxel::FlyingVoxel};
+
+use super::{
+    changes::VoxelBlockChanges,
+    lazyworld::{trigger_part, LazyWorld, WorldGenTrigger},
+    storage::VoxelStorage,
+    voxel_block_from_grid, VoxelBlock, VoxelBlockBundle, VoxelGraphics, VOXEL_BLOCK_SIZE,
+};
+
+/// parts further than this (in parts) from every trigger get paged out
+pub const KEEP_RADIUS: i32 = 5;
+/// so walking around doesn't stall a frame
+const MAX_PAGED_OUT_PER_FRAME: usize = 4;
+
+/// A chunk of a part that isn't spawned, its grid as compact as it gets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPart {
+    /// the chunk, see `VoxelBlock::pos`
+    pub pos: IVec3,
+    pub grid: VoxelStorage,
+    /// `VoxelBlock::cleared_columns`, machines and conveyors claim theirs again
+    /// once it's back
+    #[serde(alias = "forbidden_columns")]
+    pub cleared_columns: Vec<IVec2>,
+}
+
+impl StoredPart {
+    pub fn new(block: &VoxelBlock) -> Self {
+        let mut grid = block.grid.clone();
+        grid.compact();
+
+        let cleared_columns = (0..VOXEL_BLOCK_SIZE)
+            .flat_map(|x| (0..VOXEL_BLOCK_SIZE).map(move |z| IVec2::new(x, z)))
+            .filter(|col| block.cleared_columns[col.x as usize][col.y as usize])
+            .collect();
+
+        StoredPart {
+            pos: block.pos,
+            grid,
+            cleared_columns,
+        }
+    }
+
+    /// `None` if the stored grid is corrupted
+    pub fn restore(&self, graphics: &mut VoxelGraphics) -> Option<VoxelBlockBundle> {
+        if !self.grid.is_valid() {
+            return None;
+        }
+
+        let mut bundle = voxel_block_from_grid(self.pos, self.grid.clone(), graphics);
+
+        for col in self.cleared_columns.iter() {
+            bundle.voxel_block.cleared_columns[col.x as usize][col.y as usize] = true;
+            bundle.voxel_block.forbidden_columns[col.x as usize][col.y as usize] = true;
+        }
+
+        Some(bundle)
+    }
+}
+
+pub fn page_out_distant_parts(
+    mut commands: Commands,
+    q_trigger: Query<(&WorldGenTrigger, &GlobalTransform)>,
+    mut lazy_world: ResMut<LazyWorld>,
+    q_blocks: Query<(&VoxelBlock, &VoxelMailbox)>,
+    changes: Res<VoxelBlockChanges>,
+    q_flying: Query<&FlyingVoxel>,
+) {
+    let centers = q_trigger
+        .iter()
+        .map(|(trigger, trans)| trigger_part(trigger, trans))
+        .collect::<Vec<_>>();
+
+    let far = lazy_world
+        .known_parts
+        .keys()
+        .filter(|chunk| chunk.y == 0)
+        .map(|chunk| chunk.xz())
+        .filter(|part| {
+            centers
+                .iter()
+                .all(|c| (*part - *c).abs().max_element() > KEEP_RADIUS)
+        })
+        .collect::<Vec<_>>();
+
+    let mut paged_out = 0;
+
+    for part in far {
+        if paged_out >= MAX_PAGED_OUT_PER_FRAME {
+            break;
+        }
+
+        // the whole stack goes at once
+        let stack = lazy_world.stack(part).collect::<Vec<_>>();
+
+        let Some(blocks) = stack
+            .iter()
+            .map(|(_, e)| q_blocks.get(*e).ok())
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+
+        // it will settle down in a frame or two, we'll get it then
+        if blocks.iter().any(|(_, mailbox)| !mailbox.0.is_empty())
+            || changes.added.get(&part).is_some_and(|ch| !ch.is_empty())
+            || changes.wanted.iter().any(|chunk| chunk.xz() == part)
+        {
+            continue;
+        }
+
+        if q_flying
+            .iter()
+            .any(|fv| stack.iter().any(|(_, e)| fv.target_mailbox == *e))
+        {
+            continue;
+        }
+
+        let stored = blocks
+            .iter()
+            .map(|(block, _)| StoredPart::new(block))
+            .collect();
+
+        lazy_world.paged_out.insert(part, stored);
+
+        for (chunk, e) in stack {
+            lazy_world.known_parts.remove(&chunk);
+            commands.entity(e).despawn_recursive();
+        }
+
+        paged_out += 1;
+    }
+}
@@ -0,0 +1,145 @@
+//! One part's stack of chunks on its own, so `apply_changes` can push the
+//! changes of many parts at once on the compute task pool. Anything that would
+//! reach into another part is left to `WholeBlockWorld`.
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::game::material::GameMaterial;
+
+use super::{
+    changes::VoxelBlockChanges,
+    wholeworld::{BlockState, VoxelSpace},
+    VoxelBlock,
+};
+
+pub struct PartWorld<'a> {
+    part: IVec2,
+    /// ground first, up to the first one that isn't spawned
+    stack: Vec<Mut<'a, VoxelBlock>>,
+}
+
+impl<'a> PartWorld<'a> {
+    /// the `parts` among `blocks` whose ground chunk is there, in the same
+    /// order every time
+    pub fn split(
+        blocks: impl IntoIterator<Item = Mut<'a, VoxelBlock>>,
+        parts: impl Fn(IVec2) -> bool,
+    ) -> Vec<Self> {
+        let mut chunks: HashMap<IVec2, Vec<_>> = HashMap::default();
+
+        for block in blocks {
+            if parts(block.part()) {
+                chunks.entry(block.part()).or_default().push(block);
+            }
+        }
+
+        let mut part_worlds = chunks
+            .into_iter()
+            .filter_map(|(part, mut chunks)| {
+                chunks.sort_by_key(|block| block.pos.y);
+
+                let stack = chunks
+                    .into_iter()
+                    .enumerate()
+                    .map_while(|(y, block)| (block.pos.y == y as i32).then_some(block))
+                    .collect::<Vec<_>>();
+
+                (!stack.is_empty()).then_some(Self { part, stack })
+            })
+            .collect::<Vec<_>>();
+
+        part_worlds.sort_by_key(|part_world| (part_world.part.x, part_world.part.y));
+
+        part_worlds
+    }
+
+    pub fn part(&self) -> IVec2 {
+        self.part
+    }
+
+    /// the column within the part, `None` if it's in another one
+    fn local_col(&self, col: IVec2) -> Option<IVec2> {
+        let (part, local) = VoxelBlock::normalize_col(col);
+
+        (part == self.part).then_some(local)
+    }
+
+    /// the spawned chunk at `global_pos` and the position in it
+    fn chunk_for_pos(&mut self, global_pos: IVec3) -> Option<(&mut Mut<'a, VoxelBlock>, IVec3)> {
+        let (chunk, local_pos) = VoxelBlock::normalize_pos(IVec3::ZERO, global_pos);
+
+        if chunk.xz() != self.part {
+            return None;
+        }
+
+        let block = usize::try_from(chunk.y)
+            .ok()
+            .and_then(|y| self.stack.get_mut(y))?;
+
+        Some((block, local_pos))
+    }
+}
+
+impl VoxelSpace for PartWorld<'_> {
+    fn catch(&mut self, global_pos: IVec3, mat: GameMaterial) -> Option<bool> {
+        let local = self.local_col(global_pos.xz())?;
+        let ground = &mut self.stack[0];
+
+        let Some(catcher) = ground.catchers.get(&local).copied() else {
+            return Some(false);
+        };
+
+        ground.caught.push((catcher, global_pos, mat));
+
+        Some(true)
+    }
+
+    /// `None` if any of the columns around is in another part
+    fn pile_top(&mut self, col: IVec2) -> Option<i32> {
+        let mut top = 0;
+
+        for x in -1..=1 {
+            for z in -1..=1 {
+                let local = self.local_col(col + IVec2::new(x, z))?;
+
+                top = top.max(VoxelBlock::stack_height(&self.stack, local));
+            }
+        }
+
+        Some(top)
+    }
+
+    fn block_value(&mut self, global_pos: IVec3) -> Option<BlockState> {
+        let local = self.local_col(global_pos.xz())?;
+
+        if self.stack[0].forbidden_columns[local.x as usize][local.y as usize] {
+            return Some(BlockState::Forbidden);
+        }
+
+        let Some((block, local_pos)) = self.chunk_for_pos(global_pos) else {
+            return Some(BlockState::Empty);
+        };
+
+        Some(block[local_pos].map_or(BlockState::Empty, BlockState::Full))
+    }
+
+    fn land(&mut self, global_pos: IVec3, mat: GameMaterial) -> bool {
+        let Some((block, local_pos)) = self.chunk_for_pos(global_pos) else {
+            return false;
+        };
+
+        block._add_block(local_pos, mat);
+
+        true
+    }
+
+    /// it can't spawn the chunk it's waiting for, the whole world has to
+    fn postpone(
+        &mut self,
+        _global_pos: IVec3,
+        _mat: GameMaterial,
+        _change_collector: &mut VoxelBlockChanges,
+    ) -> Option<()> {
+        None
+    }
+}
@@ -0,0 +1,276 @@
+//! Slopes steeper than the angle of repose slump: voxels slide off the top of a
+//! column down onto a lower one nearby until the slope holds, also across parts. Only
+//! columns that changed get looked at, so a dug-out pit caves in while the rest
+//! of the landfill is left alone.
+
+use bevy::prelude::*;
+
+use super::{
+    changes::VoxelBlockChanges, lazyworld::LazyWorld, wholeworld::WholeBlockWorld, VoxelBlock,
+    VOXEL_BLOCK_SIZE,
+};
+
+static NEIGHBORS: [IVec2; 4] = [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y];
+
+/// how many columns away a slope is measured, so gentle angles are told apart
+/// from steeper ones instead of all coming down to one voxel per column
+const REACH: i32 = 4;
+
+#[derive(Debug, Resource)]
+pub struct SlopeStability {
+    /// in degrees, the steepest a pile stands without slumping
+    pub angle_of_repose: f32,
+    /// columns looked at per frame, the rest wait for the next one; a count
+    /// rather than a duration so headless runs come out the same every time
+    pub columns_per_frame: usize,
+}
+
+impl Default for SlopeStability {
+    fn default() -> Self {
+        Self {
+            angle_of_repose: 50.0,
+            columns_per_frame: 2048,
+        }
+    }
+}
+
+impl SlopeStability {
+    /// how many voxels a column may stand above another `dist` columns away,
+    /// never below one: each slide evens out two, flatter than that would just
+    /// rock back and forth
+    pub fn max_drop(&self, dist: i32) -> i32 {
+        ((dist as f32 * self.angle_of_repose.to_radians().tan()).floor() as i32).max(1)
+    }
+}
+
+pub fn settle_slopes(
+    stability: Res<SlopeStability>,
+    lazy_world: Res<LazyWorld>,
+    blocks: Query<&mut VoxelBlock>,
+    mut changes: ResMut<VoxelBlockChanges>,
+) {
+    let mut wbw = WholeBlockWorld { lazy_world, blocks };
+
+    settle(&mut wbw, &mut changes, &stability);
+}
+
+/// looks at up to `columns_per_frame` of the unsettled columns, returns how
+/// many that was
+pub fn settle(
+    wbw: &mut WholeBlockWorld,
+    changes: &mut VoxelBlockChanges,
+    stability: &SlopeStability,
+) -> usize {
+    // only the blocks with something to do, the rest shouldn't look changed
+    let unsettled = wbw
+        .blocks
+        .iter()
+        .filter(|b| !b.unsettled.is_empty())
+        .map(|b| b.pos)
+        .collect::<Vec<_>>();
+
+    let mut budget = stability.columns_per_frame;
+
+    for block_pos in unsettled {
+        let Some(&e) = wbw.lazy_world.known_parts.get(&block_pos) else {
+            continue;
+        };
+
+        let Ok(mut block) = wbw.blocks.get_mut(e) else {
+            continue;
+        };
+
+        let take = budget.min(block.unsettled.len());
+        let columns = block
+            .unsettled
+            .iter()
+            .take(take)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for col in columns.iter() {
+            block.unsettled.remove(col);
+        }
+
+        budget -= take;
+
+        for col in columns {
+            settle_column(
+                wbw,
+                changes,
+                block_pos.xz() * VOXEL_BLOCK_SIZE + col,
+                stability,
+            );
+        }
+
+        if budget == 0 {
+            break;
+        }
+    }
+
+    stability.columns_per_frame - budget
+}
+
+/// one slide on the steepest stretch through `col`, up to `REACH` columns
+/// long either way along each axis; the columns it changes and `col` itself
+/// end up unsettled again and get another look
+fn settle_column(
+    wbw: &mut WholeBlockWorld,
+    changes: &mut VoxelBlockChanges,
+    col: IVec2,
+    stability: &SlopeStability,
+) {
+    let Some(height) = wbw.open_column_height(col) else {
+        return;
+    };
+
+    // high end first, and how far over the limit it is
+    let mut steepest: Option<(Vec<(IVec2, i32)>, i32)> = None;
+
+    for d in NEIGHBORS.iter() {
+        for downhill in [true, false] {
+            let mut stretch = vec![(col, height)];
+
+            for dist in 1..=REACH {
+                let n = col + *d * dist;
+
+                let Some(h) = wbw.open_column_height(n) else {
+                    break;
+                };
+
+                // as long as it keeps going the same way
+                let prev = stretch[stretch.len() - 1].1;
+
+                if (downhill && h > prev) || (!downhill && h < prev) {
+                    break;
+                }
+
+                stretch.push((n, h));
+
+                let excess = (height - h).abs() - stability.max_drop(dist);
+
+                if excess > steepest.as_ref().map_or(0, |(_, e)| *e) {
+                    let mut stretch = stretch.clone();
+
+                    if !downhill {
+                        stretch.reverse();
+                    }
+
+                    steepest = Some((stretch, excess));
+                }
+            }
+        }
+    }
+
+    let Some((stretch, _)) = steepest else {
+        return;
+    };
+
+    let (from, from_height) = stretch[0];
+
+    // it stops at the first column two lower, so it can't rock back
+    let Some(&(to, _)) = stretch[1..].iter().find(|(_, h)| from_height - h >= 2) else {
+        return;
+    };
+
+    wbw.slide_top(from, to, changes);
+    wbw.unsettle(col);
+}
+
+#[cfg(test)]
+mod test {
+    use bevy::{prelude::*, utils::HashMap};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::{
+        game::material::GameMaterial,
+        graphics::voxels3d::{testworld::TestWorld, VOXEL_BLOCK_SIZE},
+    };
+
+    use super::SlopeStability;
+
+    fn stability(angle_of_repose: f32) -> SlopeStability {
+        SlopeStability {
+            angle_of_repose,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn gentler_angles_allow_less_over_a_distance() {
+        assert_eq!(stability(30.0).max_drop(1), 1);
+        assert_eq!(stability(50.0).max_drop(1), 1);
+        assert_eq!(stability(70.0).max_drop(1), 2);
+
+        assert_eq!(stability(30.0).max_drop(3), 1);
+        assert_eq!(stability(50.0).max_drop(3), 3);
+        assert_eq!(stability(70.0).max_drop(3), 8);
+    }
+
+    #[test]
+    fn dug_out_pit_caves_in() {
+        let rand = &mut StdRng::seed_from_u64(0);
+        let stability = SlopeStability::default();
+        let height = 6;
+        // four parts meeting at the pit
+        let parts = (-1..=0).flat_map(|x| (-1..=0).map(move |z| IVec2::new(x, z)));
+        let cols = (-VOXEL_BLOCK_SIZE..VOXEL_BLOCK_SIZE)
+            .flat_map(|x| (-VOXEL_BLOCK_SIZE..VOXEL_BLOCK_SIZE).map(move |z| IVec2::new(x, z)))
+            .collect::<Vec<_>>();
+        let pit = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| IVec2::new(x, z)))
+            .collect::<Vec<_>>();
+
+        let mut world = TestWorld::new(parts);
+
+        world.run(|wbw, _| {
+            for col in cols.iter() {
+                for y in 0..height {
+                    let (mut block, local) =
+                        wbw.get_voxel_block_for_pos(col.extend(y).xzy()).unwrap();
+
+                    block._add_block(local, GameMaterial::Brownish);
+                }
+            }
+        });
+
+        // flat, nothing to do
+        world.settle_slopes(&stability);
+
+        for col in pit.iter() {
+            for y in (0..height).rev() {
+                world.run(|wbw, changes| wbw.steal_block(col.extend(y).xzy(), changes, rand));
+            }
+        }
+
+        let counts = world.counts();
+
+        world.settle_slopes(&stability);
+
+        assert_eq!(world.counts(), counts);
+
+        let heights = world.run(|wbw, _| {
+            cols.iter()
+                .map(|col| (*col, wbw.column_height(*col).unwrap()))
+                .collect::<HashMap<_, _>>()
+        });
+
+        assert!(heights[&IVec2::ZERO] > 0, "the pit stayed a pit");
+
+        for (col, h) in heights.iter() {
+            for n in [*col + IVec2::X, *col + IVec2::Y] {
+                if let Some(n_height) = heights.get(&n) {
+                    assert!(
+                        (h - n_height).abs() <= stability.max_drop(1),
+                        "{col} to {n}"
+                    );
+                }
+            }
+
+            // the rest of the landfill is left alone
+            if col.abs().max_element() >= 12 {
+                assert_eq!(*h, height, "{col}");
+            }
+        }
+    }
+}
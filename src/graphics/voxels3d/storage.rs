@@ -0,0 +1,320 @@
+//! How a `VoxelBlock` keeps its voxels: a palette of the values in use and a
+//! bit-packed index into it per voxel, in the grid's order (x, then z, then y).
+//! Layers above the topmost one with anything in it aren't stored at all, so
+//! a chunk with a few layers of trash takes a few kB and an empty one nothing.
+
+use std::ops::Index;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::material::{GameMaterial, MATERIAL_COUNT};
+
+use super::{CHUNK_LEN, VOXEL_BLOCK_SIZE};
+
+const LAYER_LEN: usize = (VOXEL_BLOCK_SIZE * VOXEL_BLOCK_SIZE) as usize;
+const LAYERS: usize = VOXEL_BLOCK_SIZE as usize;
+
+/// what `Index` hands out references to, by `value_idx`
+static VALUES: [Option<GameMaterial>; MATERIAL_COUNT + 1] = [
+    None,
+    Some(GameMaterial::Reddish),
+    Some(GameMaterial::Greenish),
+    Some(GameMaterial::Blueish),
+    Some(GameMaterial::Brownish),
+];
+
+fn value_idx(v: Option<GameMaterial>) -> usize {
+    v.map_or(0, |mat| mat.as_usize() + 1)
+}
+
+/// bits per voxel for a palette of `len` values
+fn bits_for(len: usize) -> u32 {
+    if len <= 1 {
+        0
+    } else {
+        usize::BITS - (len - 1).leading_zeros()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoxelStorage {
+    /// the first one is always air
+    palette: Vec<Option<GameMaterial>>,
+    /// per voxel, 0 while there's nothing but air
+    bits: u32,
+    /// the stored layers, no voxel split between two words
+    words: Vec<u64>,
+    /// layers from the bottom that are stored, the rest is air
+    layers: usize,
+}
+
+impl Default for VoxelStorage {
+    fn default() -> Self {
+        Self {
+            palette: vec![None],
+            bits: 0,
+            words: vec![],
+            layers: 0,
+        }
+    }
+}
+
+impl VoxelStorage {
+    pub fn from_grid(grid: &[Option<GameMaterial>; CHUNK_LEN]) -> Self {
+        let mut storage = Self::default();
+
+        for (idx, v) in grid.iter().enumerate() {
+            storage.set(idx, *v);
+        }
+
+        storage
+    }
+
+    /// all of it, air included, for meshing
+    pub fn to_grid(&self) -> [Option<GameMaterial>; CHUNK_LEN] {
+        let mut grid = [None; CHUNK_LEN];
+
+        for (idx, v) in grid.iter_mut().enumerate().take(self.len()) {
+            *v = self.get(idx);
+        }
+
+        grid
+    }
+
+    /// every voxel in the grid's order, air included
+    pub fn iter(&self) -> impl Iterator<Item = Option<GameMaterial>> + '_ {
+        (0..CHUNK_LEN).map(|idx| self.get(idx))
+    }
+
+    pub fn get(&self, idx: usize) -> Option<GameMaterial> {
+        if idx >= self.len() {
+            return None;
+        }
+
+        self.palette[self.code(idx)]
+    }
+
+    /// puts `v` at `idx`, returning what was there
+    pub fn replace(&mut self, idx: usize, v: Option<GameMaterial>) -> Option<GameMaterial> {
+        let old = self.get(idx);
+
+        if old != v {
+            self.set(idx, v);
+        }
+
+        old
+    }
+
+    pub fn set(&mut self, idx: usize, v: Option<GameMaterial>) {
+        assert!(idx < CHUNK_LEN, "voxel index out of bounds: {idx}");
+
+        let code = match self.palette.iter().position(|p| *p == v) {
+            Some(code) => code,
+            None => {
+                self.palette.push(v);
+                self.repack(bits_for(self.palette.len()));
+
+                self.palette.len() - 1
+            }
+        };
+
+        if idx >= self.len() {
+            // air up there already
+            if code == 0 {
+                return;
+            }
+
+            self.resize(idx / LAYER_LEN + 1);
+        }
+
+        let (word, shift) = self.slot(idx);
+        let mask = (1u64 << self.bits) - 1;
+
+        self.words[word] = (self.words[word] & !(mask << shift)) | ((code as u64) << shift);
+    }
+
+    /// drops the palette entries nothing uses anymore and the layers that are
+    /// all air again
+    pub fn compact(&mut self) {
+        let grid = self.to_grid();
+        let top = grid
+            .chunks(LAYER_LEN)
+            .rposition(|layer| layer.iter().any(|v| v.is_some()))
+            .map_or(0, |y| y + 1);
+
+        let mut compacted = Self::default();
+
+        for (idx, v) in grid.iter().enumerate().take(top * LAYER_LEN) {
+            compacted.set(idx, *v);
+        }
+
+        *self = compacted;
+    }
+
+    /// bytes on the heap, for comparing with a dense grid's `CHUNK_LEN`
+    pub fn heap_size(&self) -> usize {
+        self.words.len() * std::mem::size_of::<u64>()
+            + self.palette.len() * std::mem::size_of::<Option<GameMaterial>>()
+    }
+
+    /// whether it could have come from `set`, e.g. after loading a save
+    pub fn is_valid(&self) -> bool {
+        let unique = self
+            .palette
+            .iter()
+            .enumerate()
+            .all(|(i, v)| !self.palette[..i].contains(v));
+
+        self.palette.first() == Some(&None)
+            && unique
+            && self.bits >= bits_for(self.palette.len())
+            && self.bits <= bits_for(VALUES.len())
+            && self.layers <= LAYERS
+            && (self.bits > 0 || self.layers == 0)
+            && self.words.len() == Self::words_for(self.layers * LAYER_LEN, self.bits)
+            && (0..self.len()).all(|idx| self.code(idx) < self.palette.len())
+    }
+
+    fn len(&self) -> usize {
+        self.layers * LAYER_LEN
+    }
+
+    fn per_word(bits: u32) -> usize {
+        (u64::BITS / bits) as usize
+    }
+
+    fn words_for(len: usize, bits: u32) -> usize {
+        if bits == 0 {
+            0
+        } else {
+            len.div_ceil(Self::per_word(bits))
+        }
+    }
+
+    fn slot(&self, idx: usize) -> (usize, u32) {
+        let per_word = Self::per_word(self.bits);
+
+        (idx / per_word, (idx % per_word) as u32 * self.bits)
+    }
+
+    fn code(&self, idx: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+
+        let (word, shift) = self.slot(idx);
+        let mask = (1u64 << self.bits) - 1;
+
+        ((self.words[word] >> shift) & mask) as usize
+    }
+
+    fn resize(&mut self, layers: usize) {
+        self.layers = layers;
+        self.words.resize(Self::words_for(self.len(), self.bits), 0);
+    }
+
+    /// the same voxels with `bits` per voxel
+    fn repack(&mut self, bits: u32) {
+        if bits <= self.bits {
+            return;
+        }
+
+        let codes = (0..self.len())
+            .map(|idx| self.code(idx))
+            .collect::<Vec<_>>();
+
+        self.bits = bits;
+        self.words = vec![0; Self::words_for(self.len(), bits)];
+
+        for (idx, code) in codes.into_iter().enumerate() {
+            let (word, shift) = self.slot(idx);
+
+            self.words[word] |= (code as u64) << shift;
+        }
+    }
+}
+
+impl Index<usize> for VoxelStorage {
+    type Output = Option<GameMaterial>;
+
+    fn index(&self, idx: usize) -> &Self::Output {
+        &VALUES[value_idx(self.get(idx))]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use crate::game::material::GameMaterial;
+
+    use super::{VoxelStorage, CHUNK_LEN, LAYER_LEN};
+
+    #[test]
+    fn same_as_a_dense_grid() {
+        let rand = &mut StdRng::seed_from_u64(3);
+        let mut storage = VoxelStorage::default();
+        let mut grid = [None; CHUNK_LEN];
+
+        assert_eq!(
+            storage.heap_size(),
+            std::mem::size_of::<Option<GameMaterial>>()
+        );
+
+        for round in 0..20_000 {
+            // mostly near the bottom, like landfill
+            let idx = rand.gen_range(0..LAYER_LEN * 6);
+            let v = if rand.gen_bool(0.3) {
+                None
+            } else {
+                Some(*GameMaterial::all().choose(rand).unwrap())
+            };
+
+            assert_eq!(storage.replace(idx, v), grid[idx], "round {round}");
+            grid[idx] = v;
+
+            if round % 5000 == 0 {
+                storage.compact();
+                assert!(storage.is_valid());
+            }
+        }
+
+        assert!(storage.is_valid());
+        assert_eq!(storage.to_grid(), grid);
+        assert!(storage.iter().eq(grid.iter().copied()));
+        assert!((0..CHUNK_LEN).all(|idx| storage[idx] == grid[idx]));
+        assert_eq!(VoxelStorage::from_grid(&grid).to_grid(), grid);
+
+        // a few layers of 3 bits each
+        assert!(storage.heap_size() < CHUNK_LEN / 2);
+
+        for idx in 0..CHUNK_LEN {
+            storage.set(idx, None);
+        }
+
+        storage.compact();
+
+        assert_eq!(storage, VoxelStorage::default());
+    }
+
+    #[test]
+    fn corrupted_is_invalid() {
+        let mut storage = VoxelStorage::default();
+        storage.set(LAYER_LEN + 5, Some(GameMaterial::Blueish));
+        storage.set(7, Some(GameMaterial::Reddish));
+
+        assert!(storage.is_valid());
+
+        let mut short = storage.clone();
+        short.words.pop();
+        assert!(!short.is_valid());
+
+        let mut no_air = storage.clone();
+        no_air.palette[0] = Some(GameMaterial::Reddish);
+        assert!(!no_air.is_valid());
+
+        let mut bad_code = storage.clone();
+        bad_code.words[0] = u64::MAX;
+        assert!(!bad_code.is_valid());
+    }
+}
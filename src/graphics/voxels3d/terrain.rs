@@ -0,0 +1,171 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng};
+
+use crate::game::{material::GameMaterial, seed::WorldSeed};
+
+use super::VOXEL_BLOCK_SIZE;
+
+/// Decides what lands on each column of a freshly generated part.
+/// Swap it by inserting another `WorldGenerator` before `LazyWorldPlugin`.
+pub trait TerrainGenerator: Send + Sync + 'static {
+    /// columns that must stay empty, e.g. the start area
+    fn is_cleared(&self, _global_col: IVec2) -> bool {
+        false
+    }
+
+    /// materials stacked on the column, bottom first
+    fn column(&self, seed: &WorldSeed, global_col: IVec2, rand: &mut StdRng) -> Vec<GameMaterial>;
+}
+
+#[derive(Resource)]
+pub struct WorldGenerator(pub Box<dyn TerrainGenerator>);
+
+impl WorldGenerator {
+    pub fn new(generator: impl TerrainGenerator) -> Self {
+        Self(Box::new(generator))
+    }
+}
+
+impl Default for WorldGenerator {
+    fn default() -> Self {
+        Self::new(NoiseTerrain::default())
+    }
+}
+
+/// the 23x23 area the first recycler goes to
+pub fn start_area() -> IRect {
+    IRect::new(0, 0, 22, 22)
+}
+
+/// The old landfill: mostly 0-2 voxels with an occasional spike.
+pub struct ScatteredPiles {
+    pub cleared_area: Option<IRect>,
+}
+
+impl TerrainGenerator for ScatteredPiles {
+    fn is_cleared(&self, global_col: IVec2) -> bool {
+        self.cleared_area.is_some_and(|r| r.contains(global_col))
+    }
+
+    fn column(
+        &self,
+        _seed: &WorldSeed,
+        _global_col: IVec2,
+        rand: &mut StdRng,
+    ) -> Vec<GameMaterial> {
+        let cnt = if rand.gen_range(1..50) == 1 {
+            rand.gen_range(5..=10)
+        } else {
+            rand.gen_range(0..=2)
+        };
+
+        (0..cnt).map(|_| GameMaterial::random(rand)).collect()
+    }
+}
+
+/// Big trash mounds with cleared valleys between them, and veins of
+/// the rarer materials running through.
+pub struct NoiseTerrain {
+    pub cleared_area: Option<IRect>,
+    /// roughly the width of a mound, in voxels
+    pub mound_scale: f32,
+    pub max_height: i32,
+    /// mound noise below this is a valley
+    pub valley_level: f32,
+    pub vein_scale: f32,
+    /// vein noise above this is a vein
+    pub vein_level: f32,
+    /// chance for a voxel inside a vein to be of the vein's material
+    pub vein_richness: f64,
+}
+
+impl Default for NoiseTerrain {
+    fn default() -> Self {
+        NoiseTerrain {
+            cleared_area: Some(start_area()),
+            mound_scale: 40.0,
+            max_height: VOXEL_BLOCK_SIZE * 2 / 3,
+            valley_level: 0.35,
+            vein_scale: 12.0,
+            vein_level: 0.72,
+            vein_richness: 0.5,
+        }
+    }
+}
+
+const MOUND_SALT: u64 = 1;
+const DETAIL_SALT: u64 = 2;
+const VEIN_SALT: u64 = 3;
+const VEIN_KIND_SALT: u64 = 4;
+
+impl TerrainGenerator for NoiseTerrain {
+    fn is_cleared(&self, global_col: IVec2) -> bool {
+        self.cleared_area.is_some_and(|r| r.contains(global_col))
+    }
+
+    fn column(&self, seed: &WorldSeed, global_col: IVec2, rand: &mut StdRng) -> Vec<GameMaterial> {
+        let p = global_col.as_vec2();
+
+        let mound = fbm(seed, MOUND_SALT, p / self.mound_scale, 4);
+        let detail = fbm(seed, DETAIL_SALT, p / 4.0, 2);
+
+        let shape = ((mound - self.valley_level) / (1.0 - self.valley_level)).clamp(0.0, 1.0);
+        // rounded tops, a bit of rubble everywhere
+        let height = self.max_height as f32 * shape * shape * (3.0 - 2.0 * shape) + detail * 2.0;
+        let height = (height.round() as i32).clamp(0, self.max_height);
+
+        let vein = fbm(seed, VEIN_SALT, p / self.vein_scale, 3);
+        let vein_mat = if value_noise(seed, VEIN_KIND_SALT, p / (self.vein_scale * 4.0)) < 0.5 {
+            GameMaterial::Greenish
+        } else {
+            GameMaterial::Blueish
+        };
+
+        (0..height)
+            .map(|_| {
+                if vein > self.vein_level && rand.gen_bool(self.vein_richness) {
+                    vein_mat
+                } else {
+                    GameMaterial::random(rand)
+                }
+            })
+            .collect()
+    }
+}
+
+/// smooth noise in 0..1 with features about 1 unit apart
+pub fn value_noise(seed: &WorldSeed, salt: u64, p: Vec2) -> f32 {
+    let cell = p.floor();
+    let t = p - cell;
+    let t = t * t * (Vec2::splat(3.0) - 2.0 * t);
+    let cell = cell.as_ivec2();
+
+    let corner =
+        |offset: IVec2| (seed.hash(salt, cell + offset) >> 40) as f32 / (1u64 << 24) as f32;
+
+    let bottom = lerp(corner(IVec2::new(0, 0)), corner(IVec2::new(1, 0)), t.x);
+    let top = lerp(corner(IVec2::new(0, 1)), corner(IVec2::new(1, 1)), t.x);
+
+    lerp(bottom, top, t.y)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+/// a few octaves of `value_noise`, still in 0..1
+pub fn fbm(seed: &WorldSeed, salt: u64, p: Vec2, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+
+    for octave in 0..octaves {
+        sum += amplitude * value_noise(seed, salt + octave as u64 * 1000, p * frequency);
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    sum / total
+}
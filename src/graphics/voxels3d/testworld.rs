@@ -0,0 +1,229 @@
+//! `VoxelBlock`s in a bare `World`, for testing `WholeBlockWorld` without an `App`.
+
+use bevy::{ecs::system::SystemState, prelude::*};
+use rand::Rng;
+
+use crate::game::material::{GameMaterial, MATERIAL_COUNT};
+
+use super::{
+    changes::VoxelBlockChanges,
+    generate_voxel_block,
+    lazyworld::LazyWorld,
+    partworld::PartWorld,
+    slopes::{settle, SlopeStability},
+    wholeworld::WholeBlockWorld,
+    VoxelBlock, VoxelGraphics, VOXEL_BLOCK_SIZE,
+};
+
+type WholeWorldParams = (
+    Res<'static, LazyWorld>,
+    Query<'static, 'static, &'static mut VoxelBlock>,
+);
+
+pub struct TestWorld {
+    world: World,
+    state: SystemState<WholeWorldParams>,
+    /// what went to parts that aren't loaded
+    pub changes: VoxelBlockChanges,
+}
+
+impl TestWorld {
+    /// empty `parts`, everything else isn't loaded
+    pub fn new(parts: impl IntoIterator<Item = IVec2>) -> Self {
+        let mut world = World::new();
+        let mut lazy_world = LazyWorld {
+            known_parts: default(),
+            paged_out: default(),
+        };
+
+        let mut graphics = VoxelGraphics {
+            meshes: None,
+            voxel_resources: None,
+        };
+
+        for part in parts {
+            let chunk = VoxelBlock::ground(part);
+            let bundle = generate_voxel_block(chunk, &mut graphics);
+            let e = world.spawn(bundle.voxel_block).id();
+
+            lazy_world.known_parts.insert(chunk, e);
+        }
+
+        world.insert_resource(lazy_world);
+
+        let state = SystemState::new(&mut world);
+
+        Self {
+            world,
+            state,
+            changes: default(),
+        }
+    }
+
+    /// like columns where the terrain is cleared, they have to be empty still
+    pub fn with_forbidden(mut self, cols: impl IntoIterator<Item = IVec2>) -> Self {
+        for col in cols {
+            self.run(|wbw, _| {
+                let (mut block, local) = wbw
+                    .get_voxel_block_for_pos(col.extend(0).xzy())
+                    .expect("forbidden column outside the loaded parts");
+
+                block.forbid_column(local.xz());
+            });
+        }
+
+        self
+    }
+
+    pub fn run<R>(
+        &mut self,
+        f: impl FnOnce(&mut WholeBlockWorld, &mut VoxelBlockChanges) -> R,
+    ) -> R {
+        let (lazy_world, blocks) = self.state.get_mut(&mut self.world);
+
+        f(
+            &mut WholeBlockWorld { lazy_world, blocks },
+            &mut self.changes,
+        )
+    }
+
+    /// like `run`, with the part on its own the way `apply_changes` hands it
+    /// to a task
+    pub fn run_part<R>(
+        &mut self,
+        part: IVec2,
+        f: impl FnOnce(&mut PartWorld, &mut VoxelBlockChanges) -> R,
+    ) -> R {
+        let (_, mut blocks) = self.state.get_mut(&mut self.world);
+
+        let mut part_world = PartWorld::split(blocks.iter_mut(), |p| p == part)
+            .pop()
+            .expect("the part isn't loaded");
+
+        f(&mut part_world, &mut self.changes)
+    }
+
+    /// what `grow_stacks` would get done: the chunks asked for, spawned
+    fn grow_stacks(&mut self) {
+        for wanted in std::mem::take(&mut self.changes.wanted) {
+            for y in 1..=wanted.y {
+                let chunk = wanted.xz().extend(y).xzy();
+
+                if self
+                    .world
+                    .resource::<LazyWorld>()
+                    .known_parts
+                    .contains_key(&chunk)
+                {
+                    continue;
+                }
+
+                let mut graphics = VoxelGraphics {
+                    meshes: None,
+                    voxel_resources: None,
+                };
+
+                let e = self
+                    .world
+                    .spawn(generate_voxel_block(chunk, &mut graphics).voxel_block)
+                    .id();
+
+                self.world
+                    .resource_mut::<LazyWorld>()
+                    .known_parts
+                    .insert(chunk, e);
+            }
+        }
+    }
+
+    /// what `grow_stacks` and `apply_changes` would get done over the next
+    /// frames, until only changes for parts that aren't loaded are left
+    pub fn apply_changes(&mut self, rand: &mut impl Rng) {
+        loop {
+            self.grow_stacks();
+
+            let lazy_world = self.world.resource::<LazyWorld>();
+            let loaded = self
+                .changes
+                .added
+                .keys()
+                .copied()
+                .filter(|part| lazy_world.ground(*part).is_some())
+                .collect::<Vec<_>>();
+
+            let waiting = loaded
+                .into_iter()
+                .flat_map(|part| {
+                    let ground = VoxelBlock::ground(part);
+
+                    self.changes
+                        .added
+                        .remove(&part)
+                        .into_iter()
+                        .flatten()
+                        .map(move |(local, mat)| (VoxelBlock::global_pos(ground, local), mat))
+                })
+                .collect::<Vec<_>>();
+
+            if waiting.is_empty() {
+                return;
+            }
+
+            for (pos, mat) in waiting {
+                self.run(|wbw, changes| wbw.push_block(pos, mat, changes, rand, None));
+            }
+        }
+    }
+
+    /// what `settle_slopes` would get done over the next frames, until every
+    /// slope holds
+    pub fn settle_slopes(&mut self, stability: &SlopeStability) {
+        loop {
+            self.grow_stacks();
+
+            if self.run(|wbw, changes| settle(wbw, changes, stability)) == 0 {
+                return;
+            }
+        }
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &VoxelBlock> + '_ {
+        self.world
+            .iter_entities()
+            .filter_map(|e| e.get::<VoxelBlock>())
+    }
+
+    /// every voxel in the loaded parts, by global position
+    pub fn voxels(&self) -> Vec<(IVec3, GameMaterial)> {
+        let mut voxels = vec![];
+
+        for block in self.blocks() {
+            for x in 0..VOXEL_BLOCK_SIZE {
+                for z in 0..VOXEL_BLOCK_SIZE {
+                    for y in 0..block.height_at(IVec2::new(x, z)) {
+                        let local = IVec3::new(x, y, z);
+
+                        if let Some(mat) = block[local] {
+                            voxels.push((VoxelBlock::global_pos(block.pos, local), mat));
+                        }
+                    }
+                }
+            }
+        }
+
+        voxels
+    }
+
+    /// voxels in the loaded parts and waiting for the others, by `GameMaterial::as_usize`
+    pub fn counts(&self) -> [usize; MATERIAL_COUNT] {
+        let mut counts = [0; MATERIAL_COUNT];
+
+        let waiting = self.changes.added.values().flatten().map(|(_, mat)| *mat);
+
+        for mat in self.voxels().into_iter().map(|(_, mat)| mat).chain(waiting) {
+            counts[mat.as_usize()] += 1;
+        }
+
+        counts
+    }
+}
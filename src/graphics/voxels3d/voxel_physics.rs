@@ -2,7 +2,7 @@ use bevy::prelude::*;
 
 use crate::game::material::GameMaterial;
 
-use super::{VoxelBlock, VoxelBlockChanges, VOXEL_BLOCK_SIZE};
+use super::{VoxelBlock, VoxelBlockChanges, DROP_HEIGHT};
 
 pub struct VoxelPhysics;
 impl Plugin for VoxelPhysics {
@@ -12,23 +12,17 @@ impl Plugin for VoxelPhysics {
 }
 
 impl VoxelBlock {
-    /// find an empty cell at column
-    pub fn empty_at_col(&self, col: IVec2) -> Option<IVec3> {
-        if self.forbidden_columns[col.x as usize][col.y as usize] {
-            return None;
-        }
-
-        let mut empty = IVec3::new(col.x, 0, col.y);
+    /// the empty cell on top of a column through the whole `stack` of a part,
+    /// ground chunk first, counted from the ground chunk; `None` if the column
+    /// is forbidden
+    pub fn empty_at_col(stack: &[&VoxelBlock], col: IVec2) -> Option<IVec3> {
+        let ground = stack.first()?;
 
-        for y in 0..VOXEL_BLOCK_SIZE {
-            empty.y = y;
-
-            if self[empty].is_some() {
-                return Some(empty);
-            }
+        if ground.forbidden_columns[col.x as usize][col.y as usize] {
+            return None;
         }
 
-        None
+        Some(col.extend(Self::stack_height(stack, col)).xzy())
     }
 }
 
@@ -45,9 +39,6 @@ fn handle_debug_keyboard(keys: Res<Input<KeyCode>>, mut blockchanges: ResMut<Vox
         // .unwrap();
         let pos = IVec2::new(0, 0);
         // println!("======");
-        blockchanges.register_change(
-            pos.extend(VOXEL_BLOCK_SIZE).xzy(),
-            GameMaterial::random(rnd),
-        );
+        blockchanges.register_change(pos.extend(DROP_HEIGHT).xzy(), GameMaterial::random(rnd));
     }
 }
@@ -5,7 +5,7 @@ use rand::{seq::SliceRandom, Rng};
 
 use crate::game::material::GameMaterial;
 
-use super::{changes::VoxelBlockChanges, lazyworld::LazyWorld, VoxelBlock, VOXEL_BLOCK_SIZE};
+use super::{changes::VoxelBlockChanges, lazyworld::LazyWorld, VoxelBlock, DROP_HEIGHT};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockState {
@@ -25,6 +25,31 @@ pub struct WholeBlockWorld<'qres, 'qq, 'world, 'state> {
     pub blocks: Query<'world, 'state, &'qq mut VoxelBlock>,
 }
 
+/// What `push_voxel` looks at and changes while something falls: the whole
+/// world, or one part on its own (`PartWorld`). A `None` is something the space
+/// can't tell, the push gives up on it then, before anything has changed.
+pub trait VoxelSpace {
+    /// hands it to whatever catches on its column, `false` if nothing does
+    fn catch(&mut self, global_pos: IVec3, mat: GameMaterial) -> Option<bool>;
+
+    /// the highest column around `col`, where something dropped onto it stops
+    /// falling straight down; `None` and it's postponed
+    fn pile_top(&mut self, col: IVec2) -> Option<i32>;
+
+    fn block_value(&mut self, global_pos: IVec3) -> Option<BlockState>;
+
+    /// puts it into the empty cell, `false` if there's no chunk there yet
+    fn land(&mut self, global_pos: IVec3, mat: GameMaterial) -> bool;
+
+    /// leaves it to land in a later frame
+    fn postpone(
+        &mut self,
+        global_pos: IVec3,
+        mat: GameMaterial,
+        change_collector: &mut VoxelBlockChanges,
+    ) -> Option<()>;
+}
+
 fn blocks_around(pos: IVec3, dist: i32) -> impl Iterator<Item = IVec3> {
     (-dist..=dist)
         .cartesian_product(-dist..=dist)
@@ -33,35 +58,155 @@ fn blocks_around(pos: IVec3, dist: i32) -> impl Iterator<Item = IVec3> {
 }
 
 impl<'qres, 'qq, 'world, 'state> WholeBlockWorld<'qres, 'qq, 'world, 'state> {
-    pub fn is_initialized_by_blockpos(&self, block_pos: IVec2) -> bool {
-        self.lazy_world.known_parts.contains_key(&block_pos)
-            && self
-                .blocks
-                .get(self.lazy_world.known_parts[&block_pos])
-                .is_ok()
+    pub fn is_initialized_by_blockpos(&self, block_pos: IVec3) -> bool {
+        self.lazy_world
+            .known_parts
+            .get(&block_pos)
+            .is_some_and(|e| self.blocks.get(*e).is_ok())
     }
 
-    pub fn is_in_forbidden_column(&mut self, global_pos: IVec3) -> bool {
-        let Some((block, local_pos)) = self.get_voxel_block_for_pos(global_pos) else {
+    /// its ground chunk is there, the ones above may still be on their way
+    pub fn is_part_loaded(&self, part: IVec2) -> bool {
+        self.is_initialized_by_blockpos(VoxelBlock::ground(part))
+    }
+
+    pub fn is_in_forbidden_column(&self, global_pos: IVec3) -> bool {
+        let Some((block, local)) = self.block_for_col(global_pos.xz()) else {
             return false;
         };
 
-        block.forbidden_columns[local_pos.x as usize][local_pos.z as usize]
+        block.forbidden_columns[local.x as usize][local.y as usize]
     }
 
     pub fn get_voxel_block_for_pos(
         &mut self,
         global_pos: IVec3,
     ) -> Option<(Mut<'_, VoxelBlock>, IVec3)> {
-        let (block_pos, local_pos) = VoxelBlock::normalize_pos(IVec2::ZERO, global_pos);
+        let (block_pos, local_pos) = VoxelBlock::normalize_pos(IVec3::ZERO, global_pos);
 
         let block = self.lazy_world.known_parts.get(&block_pos)?;
 
-        let block = self.blocks.get_mut(*block).unwrap();
+        let block = self.blocks.get_mut(*block).ok()?;
 
         Some((block, local_pos))
     }
 
+    /// the ground chunk of the column's part, which knows about the column
+    fn block_for_col(&self, col: IVec2) -> Option<(&VoxelBlock, IVec2)> {
+        let (part, local_pos) = VoxelBlock::normalize_col(col);
+
+        let block = self
+            .lazy_world
+            .ground(part)
+            .and_then(|e| self.blocks.get(e).ok())?;
+
+        Some((block, local_pos))
+    }
+
+    /// the spawned chunks of the column's part, ground first
+    fn stack_for_col(&self, col: IVec2) -> (Vec<&VoxelBlock>, IVec2) {
+        let (part, local_pos) = VoxelBlock::normalize_col(col);
+
+        let stack = self
+            .lazy_world
+            .stack(part)
+            .map_while(|(_, e)| self.blocks.get(e).ok())
+            .collect();
+
+        (stack, local_pos)
+    }
+
+    /// one past the topmost voxel of the column, through all of its chunks;
+    /// `None` if its part isn't loaded
+    pub fn column_height(&self, col: IVec2) -> Option<i32> {
+        let (stack, local) = self.stack_for_col(col);
+
+        (!stack.is_empty()).then(|| VoxelBlock::stack_height(&stack, local))
+    }
+
+    /// voxels standing in the column
+    pub fn column_voxels(&self, col: IVec2) -> usize {
+        let (stack, local) = self.stack_for_col(col);
+
+        VoxelBlock::count_in_col(
+            &stack,
+            local,
+            GameMaterial::any_of_mask(GameMaterial::all()),
+        )
+    }
+
+    /// the machine standing on the column, or the conveyor catching what lands on it
+    pub fn column_holder(&self, col: IVec2) -> Option<Entity> {
+        let (block, local) = self.block_for_col(col)?;
+
+        block
+            .occupied
+            .get(&local)
+            .or_else(|| block.catchers.get(&local))
+            .copied()
+    }
+
+    /// how tall the column stands, `None` if nothing can slide into or out of it:
+    /// forbidden, catching or not loaded
+    pub fn open_column_height(&self, col: IVec2) -> Option<i32> {
+        let (block, local) = self.block_for_col(col)?;
+
+        if block.forbidden_columns[local.x as usize][local.y as usize]
+            || block.catchers.contains_key(&local)
+        {
+            return None;
+        }
+
+        self.column_height(col)
+    }
+
+    /// moves the topmost voxel of `from` on top of `to`, both have to be open;
+    /// if `to` needs a chunk that isn't there yet, it's asked for and nothing moves
+    pub fn slide_top(&mut self, from: IVec2, to: IVec2, change_collector: &mut VoxelBlockChanges) {
+        let (Some(from_height), Some(to_height)) =
+            (self.open_column_height(from), self.open_column_height(to))
+        else {
+            return;
+        };
+
+        let to_pos = to.extend(to_height).xzy();
+
+        if from_height == 0 {
+            return;
+        }
+
+        if self.get_voxel_block_for_pos(to_pos).is_none() {
+            let (chunk, _) = VoxelBlock::normalize_pos(IVec3::ZERO, to_pos);
+
+            change_collector.wanted.insert(chunk);
+            return;
+        }
+
+        let Some(mat) = self
+            .get_voxel_block_for_pos(from.extend(from_height - 1).xzy())
+            .and_then(|(mut block, local_pos)| block._take_block(local_pos))
+        else {
+            return;
+        };
+
+        if let Some((mut block, local_pos)) = self.get_voxel_block_for_pos(to_pos) {
+            block._add_block(local_pos, mat);
+        }
+    }
+
+    /// gets `settle_slopes` to look at the column again
+    pub fn unsettle(&mut self, col: IVec2) {
+        let (part, local) = VoxelBlock::normalize_col(col);
+
+        let Some(e) = self.lazy_world.ground(part) else {
+            return;
+        };
+
+        if let Ok(mut block) = self.blocks.get_mut(e) {
+            block.unsettled.insert(local);
+        }
+    }
+
     pub fn get_block_value(&mut self, global_pos: IVec3) -> BlockState {
         if self.is_in_forbidden_column(global_pos) {
             return BlockState::Forbidden;
@@ -86,30 +231,29 @@ impl<'qres, 'qq, 'world, 'state> WholeBlockWorld<'qres, 'qq, 'world, 'state> {
     ) -> Option<GameMaterial> {
         let (mut block, local_pos) = self.get_voxel_block_for_pos(global_pos)?;
 
-        let block_pos = block.pos;
-
         let mt = block._take_block(local_pos).take()?;
 
+        let height = self.column_height(global_pos.xz()).unwrap_or(0);
+
+        // whatever stood on it, up through the chunks above
         let mut mats = vec![];
 
-        for y in local_pos.y + 1..VOXEL_BLOCK_SIZE {
-            let lp = IVec3::new(local_pos.x, y, local_pos.z);
+        for y in global_pos.y + 1..height {
+            let p = IVec3::new(global_pos.x, y, global_pos.z);
 
-            mats.push(block._take_block(lp).take());
+            if let Some((mut block, lp)) = self.get_voxel_block_for_pos(p) {
+                mats.push(block._take_block(lp));
+            }
         }
 
-        let mut y = local_pos.y;
-
-        for m in mats {
-            if let Some(m) = m {
-                let lp = IVec3::new(local_pos.x, y, local_pos.z);
-                y += 1;
+        let mut y = global_pos.y;
 
-                let gp = (block_pos * VOXEL_BLOCK_SIZE).extend(0).xzy() + lp;
+        for m in mats.into_iter().flatten() {
+            let gp = IVec3::new(global_pos.x, y, global_pos.z);
+            y += 1;
 
-                // self._add_block(lp, m);
-                self.push_block(gp, m, change_collector, rand, Some(Color::FUCHSIA));
-            }
+            // self._add_block(lp, m);
+            self.push_block(gp, m, change_collector, rand, Some(Color::FUCHSIA));
         }
 
         Some(mt)
@@ -123,152 +267,259 @@ impl<'qres, 'qq, 'world, 'state> WholeBlockWorld<'qres, 'qq, 'world, 'state> {
         rand: &mut impl Rng,
         debug_color: Option<Color>,
     ) {
-        // dbg!(global_pos);
-
-        if global_pos.y >= VOXEL_BLOCK_SIZE {
-            if self.get_block_value(global_pos - IVec3::new(0, 1, 0)) == BlockState::Empty {
-                // println!("1");
-                return self.push_block(
-                    // global_pos - IVec3::new(0, 1, 0),
-                    IVec3::new(global_pos.x, VOXEL_BLOCK_SIZE - 1, global_pos.z),
-                    mat,
-                    change_collector,
-                    rand,
-                    debug_color,
-                );
-            }
+        // the whole world can tell about anything, loaded or not
+        push_voxel(self, global_pos, mat, change_collector, rand, debug_color)
+            .expect("the whole world gave up on a voxel");
+    }
 
-            for rad in 1..30 {
-                let empties_below = blocks_around(global_pos - IVec3::new(0, 1, 0), rad)
-                    .filter(|p| self.get_block_value(*p) == BlockState::Empty)
-                    .collect_vec();
-
-                if !empties_below.is_empty() {
-                    // println!("2:{rad}");
-                    return self.push_block(
-                        *empties_below.choose(rand).unwrap(),
-                        mat,
-                        change_collector,
-                        rand,
-                        debug_color,
-                    );
-                }
-            }
+    /// forbids the column for `machine`, pushing whatever stood in it aside;
+    /// `false` if its part isn't loaded
+    pub fn occupy_column(
+        &mut self,
+        col: IVec2,
+        machine: Entity,
+        change_collector: &mut VoxelBlockChanges,
+        rand: &mut impl Rng,
+    ) -> bool {
+        let (part, local_pos) = VoxelBlock::normalize_col(col);
 
-            warn!("no empty space found below, discarding block");
-            return;
+        if !self.is_part_loaded(part) {
+            return false;
         }
 
-        if global_pos.y > 0 {
-            let cells_below = blocks_around(global_pos - IVec3::new(0, 1, 0), 1)
-                .chain([global_pos - IVec3::new(0, 1, 0)])
-                .map(|p| (p, self.get_block_value(p)))
-                .collect_vec();
+        let mut taken = vec![];
 
-            let empties_below = cells_below
-                .iter()
-                .filter(|(_, s)| *s == BlockState::Empty)
-                .copied()
-                .collect_vec();
+        for (_, e) in self.lazy_world.stack(part) {
+            let Ok(mut block) = self.blocks.get_mut(e) else {
+                break;
+            };
 
-            let allowed_below = cells_below
-                .iter()
-                .filter(|(_, s)| *s != BlockState::Forbidden)
-                .count();
-
-            if empties_below.len() == allowed_below {
-                // println!("3");
-                // don't wiggle in the air
-                return self.push_block(
-                    global_pos - IVec3::new(0, 1, 0),
-                    mat,
-                    change_collector,
-                    rand,
-                    debug_color,
-                );
+            if block.pos.y == 0 {
+                taken.extend(block.occupy_column(local_pos, machine));
+            } else {
+                taken.extend(block.take_column(local_pos));
             }
+        }
 
-            // println!("empties below: {}/{allowed_below} ", empties_below.len());
-
-            let must_fall = !empties_below.is_empty()
-                && (self.get_block_value(global_pos - IVec3::new(0, 1, 0)) == BlockState::Empty
-                    || empties_below.len() >= allowed_below - 1
-                    || rand.gen_range(0..=allowed_below) > empties_below.len()
-                    || rand.gen_range(0..=allowed_below) > empties_below.len()
-                    || rand.gen_range(0..=allowed_below) > empties_below.len());
-
-            if must_fall {
-                // println!("4");
-                return self.push_block(
-                    empties_below.choose(rand).unwrap().0,
-                    mat,
-                    change_collector,
-                    rand,
-                    debug_color,
-                );
-            }
+        for mat in taken {
+            self.drop_block(col, mat, change_collector, rand);
         }
 
-        if self.get_block_value(global_pos) == BlockState::Empty {
-            if let Some((mut block, local_pos)) = self.get_voxel_block_for_pos(global_pos) {
-                // debug3d::draw_gizmos(2.0, move |gizmos| {
-                //     gizmos.sphere(
-                //         global_pos.as_vec3(),
-                //         Quat::IDENTITY,
-                //         3.0,
-                //         debug_color.unwrap_or(Color::BLUE),
-                //     );
-                // });
-
-                block._add_block(local_pos, mat);
-            } else {
-                change_collector.register_change(global_pos, mat);
-            }
+        true
+    }
 
-            return;
+    pub fn drop_block(
+        &mut self,
+        global_pos_xz: IVec2,
+        mat: GameMaterial,
+        change_collector: &mut VoxelBlockChanges,
+        rand: &mut impl Rng,
+    ) {
+        self.push_block(
+            global_pos_xz.extend(DROP_HEIGHT).xzy(),
+            mat,
+            change_collector,
+            rand,
+            Some(Color::RED),
+        );
+    }
+}
+
+impl VoxelSpace for WholeBlockWorld<'_, '_, '_, '_> {
+    fn catch(&mut self, global_pos: IVec3, mat: GameMaterial) -> Option<bool> {
+        // the ground chunk knows about the column
+        let Some((mut block, local_pos)) =
+            self.get_voxel_block_for_pos(global_pos * IVec3::new(1, 0, 1))
+        else {
+            return Some(false);
+        };
+
+        let Some(catcher) = block.catchers.get(&local_pos.xz()).copied() else {
+            return Some(false);
+        };
+
+        block.caught.push((catcher, global_pos, mat));
+
+        Some(true)
+    }
+
+    /// `None` if its part isn't loaded
+    fn pile_top(&mut self, col: IVec2) -> Option<i32> {
+        self.column_height(col)?;
+
+        blocks_around(col.extend(0).xzy(), 1)
+            .map(|p| p.xz())
+            .chain([col])
+            .filter_map(|c| self.column_height(c))
+            .max()
+    }
+
+    fn block_value(&mut self, global_pos: IVec3) -> Option<BlockState> {
+        Some(self.get_block_value(global_pos))
+    }
+
+    fn land(&mut self, global_pos: IVec3, mat: GameMaterial) -> bool {
+        let Some((mut block, local_pos)) = self.get_voxel_block_for_pos(global_pos) else {
+            return false;
+        };
+
+        block._add_block(local_pos, mat);
+
+        true
+    }
+
+    fn postpone(
+        &mut self,
+        global_pos: IVec3,
+        mat: GameMaterial,
+        change_collector: &mut VoxelBlockChanges,
+    ) -> Option<()> {
+        let (chunk, _) = VoxelBlock::normalize_pos(IVec3::ZERO, global_pos);
+
+        // above the part's stack, it gets another chunk for it
+        if self.is_part_loaded(chunk.xz()) {
+            change_collector.wanted.insert(chunk);
         }
 
-        for rad in 1..30 {
-            let mut empties_around = blocks_around(global_pos, rad)
-                .filter(|p| self.get_block_value(*p) == BlockState::Empty)
-                .collect_vec();
+        change_collector.register_change(global_pos, mat);
 
-            let global_pos_above = global_pos + IVec3::new(0, 2, 0);
+        Some(())
+    }
+}
 
-            empties_around.push(global_pos_above);
-            empties_around.push(global_pos_above);
-            empties_around.push(global_pos_above);
+/// lets `mat` fall from `global_pos` until it lands, is caught or has to wait;
+/// `None` if `space` gave up on it
+pub fn push_voxel<S: VoxelSpace>(
+    space: &mut S,
+    global_pos: IVec3,
+    mat: GameMaterial,
+    change_collector: &mut VoxelBlockChanges,
+    rand: &mut impl Rng,
+    debug_color: Option<Color>,
+) -> Option<()> {
+    // dbg!(global_pos);
+
+    if space.catch(global_pos, mat)? {
+        return Some(());
+    }
+
+    // it'll fall once the part is back
+    let Some(top) = space.pile_top(global_pos.xz()) else {
+        return space.postpone(global_pos, mat, change_collector);
+    };
 
-            // if !empties_around.is_empty() {
-            // println!("6 :{rad}");
-            return self.push_block(
-                *empties_around.choose(rand).unwrap(),
+    // nothing but air down to there
+    if global_pos.y > top {
+        return push_voxel(
+            space,
+            IVec3::new(global_pos.x, top, global_pos.z),
+            mat,
+            change_collector,
+            rand,
+            debug_color,
+        );
+    }
+
+    if global_pos.y > 0 {
+        let cells_below = blocks_around(global_pos - IVec3::new(0, 1, 0), 1)
+            .chain([global_pos - IVec3::new(0, 1, 0)])
+            .map(|p| Some((p, space.block_value(p)?)))
+            .collect::<Option<Vec<_>>>()?;
+
+        let empties_below = cells_below
+            .iter()
+            .filter(|(_, s)| *s == BlockState::Empty)
+            .copied()
+            .collect_vec();
+
+        let allowed_below = cells_below
+            .iter()
+            .filter(|(_, s)| *s != BlockState::Forbidden)
+            .count();
+
+        if empties_below.len() == allowed_below {
+            // println!("3");
+            // don't wiggle in the air
+            return push_voxel(
+                space,
+                global_pos - IVec3::new(0, 1, 0),
                 mat,
                 change_collector,
                 rand,
                 debug_color,
             );
-            // }
         }
 
-        warn!("no empty space found, discarding block");
+        // println!("empties below: {}/{allowed_below} ", empties_below.len());
+
+        let must_fall = !empties_below.is_empty()
+            && (space.block_value(global_pos - IVec3::new(0, 1, 0))? == BlockState::Empty
+                || empties_below.len() >= allowed_below - 1
+                || rand.gen_range(0..=allowed_below) > empties_below.len()
+                || rand.gen_range(0..=allowed_below) > empties_below.len()
+                || rand.gen_range(0..=allowed_below) > empties_below.len());
+
+        if must_fall {
+            // println!("4");
+            return push_voxel(
+                space,
+                empties_below.choose(rand).unwrap().0,
+                mat,
+                change_collector,
+                rand,
+                debug_color,
+            );
+        }
     }
 
-    pub fn drop_block(
-        &mut self,
-        global_pos_xz: IVec2,
-        mat: GameMaterial,
-        change_collector: &mut VoxelBlockChanges,
-        rand: &mut impl Rng,
-    ) {
-        self.push_block(
-            global_pos_xz.extend(VOXEL_BLOCK_SIZE).xzy(),
+    if space.block_value(global_pos)? == BlockState::Empty {
+        // debug3d::draw_gizmos(2.0, move |gizmos| {
+        //     gizmos.sphere(
+        //         global_pos.as_vec3(),
+        //         Quat::IDENTITY,
+        //         3.0,
+        //         debug_color.unwrap_or(Color::BLUE),
+        //     );
+        // });
+
+        if space.land(global_pos, mat) {
+            return Some(());
+        }
+
+        return space.postpone(global_pos, mat, change_collector);
+    }
+
+    for rad in 1..30 {
+        let mut empties_around = blocks_around(global_pos, rad)
+            .map(|p| Some((p, space.block_value(p)?)))
+            .collect::<Option<Vec<_>>>()?
+            .into_iter()
+            .filter(|(_, s)| *s == BlockState::Empty)
+            .map(|(p, _)| p)
+            .collect_vec();
+
+        let global_pos_above = global_pos + IVec3::new(0, 2, 0);
+
+        empties_around.push(global_pos_above);
+        empties_around.push(global_pos_above);
+        empties_around.push(global_pos_above);
+
+        // if !empties_around.is_empty() {
+        // println!("6 :{rad}");
+        return push_voxel(
+            space,
+            *empties_around.choose(rand).unwrap(),
             mat,
             change_collector,
             rand,
-            Some(Color::RED),
+            debug_color,
         );
+        // }
     }
+
+    warn!("no empty space found, discarding block");
+
+    Some(())
 }
 
 // pub fn apply_changes(
@@ -282,3 +533,196 @@ impl<'qres, 'qq, 'world, 'state> WholeBlockWorld<'qres, 'qq, 'world, 'state> {
 //         blocks: &mut blocks,
 //     };
 // }
+
+#[cfg(test)]
+mod test {
+    use bevy::prelude::*;
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use crate::{
+        game::material::{GameMaterial, MATERIAL_COUNT},
+        graphics::voxels3d::{
+            blocks::BlockIndex, testworld::TestWorld, VoxelBlock, DROP_HEIGHT, VOXEL_BLOCK_SIZE,
+        },
+    };
+
+    use super::push_voxel;
+
+    /// 3x3 parts around the origin, some columns forbidden
+    fn test_world(rand: &mut StdRng) -> TestWorld {
+        let parts = (-1..=1).flat_map(|x| (-1..=1).map(move |z| IVec2::new(x, z)));
+        let forbidden = (0..40)
+            .map(|_| IVec2::new(rand.gen_range(-48..48), rand.gen_range(-48..48)))
+            .collect::<Vec<_>>();
+
+        TestWorld::new(parts).with_forbidden(forbidden)
+    }
+
+    /// drops and steals at random, near part borders too, and checks what has
+    /// to hold no matter what
+    fn check_seed(seed: u64) {
+        let rand = &mut StdRng::seed_from_u64(seed);
+        let mut world = test_world(rand);
+        let mut expected = [0; MATERIAL_COUNT];
+
+        for _ in 0..60 {
+            for _ in 0..10 {
+                let col = IVec2::new(rand.gen_range(-40..40), rand.gen_range(-40..40));
+                let mat = *GameMaterial::all().choose(rand).unwrap();
+
+                world.run(|wbw, changes| wbw.drop_block(col, mat, changes, rand));
+                expected[mat.as_usize()] += 1;
+            }
+
+            for _ in 0..3 {
+                let Some(&(pos, mat)) = world.voxels().choose(rand) else {
+                    continue;
+                };
+
+                let stolen = world.run(|wbw, changes| wbw.steal_block(pos, changes, rand));
+
+                assert_eq!(stolen, Some(mat), "seed {seed}: stole from {pos}");
+                expected[mat.as_usize()] -= 1;
+            }
+
+            assert_eq!(world.counts(), expected, "seed {seed}");
+        }
+
+        check_world(&world, seed);
+    }
+
+    /// forbidden columns are empty, the indices are right and nothing floats
+    fn check_world(world: &TestWorld, seed: u64) {
+        let voxels = world.voxels();
+
+        for block in world.blocks() {
+            for x in 0..VOXEL_BLOCK_SIZE {
+                for z in 0..VOXEL_BLOCK_SIZE {
+                    let col = IVec2::new(x, z);
+
+                    if block.forbidden_columns[x as usize][z as usize] {
+                        assert_eq!(
+                            block.column(col).counts,
+                            [0; MATERIAL_COUNT],
+                            "seed {seed}: something in the forbidden column {col} of {}",
+                            block.pos
+                        );
+                    }
+                }
+            }
+
+            let index = BlockIndex::new(&block.grid);
+
+            assert_eq!(index.counts, block.index.counts, "seed {seed}");
+
+            for (kept, fresh) in block
+                .index
+                .columns
+                .iter()
+                .flatten()
+                .zip(index.columns.iter().flatten())
+            {
+                assert_eq!(kept.height, fresh.height, "seed {seed}");
+                assert_eq!(kept.counts, fresh.counts, "seed {seed}");
+            }
+        }
+
+        for (pos, _) in voxels.iter() {
+            if pos.y == 0 {
+                continue;
+            }
+
+            let below = *pos - IVec3::Y;
+
+            assert!(
+                voxels.iter().any(|(p, _)| *p == below),
+                "seed {seed}: {pos} floats"
+            );
+        }
+    }
+
+    #[test]
+    fn push_and_steal_conserve_materials() {
+        for seed in 0..16 {
+            check_seed(seed);
+        }
+    }
+
+    #[test]
+    fn parts_push_alone_or_hand_over() {
+        let seed = 5;
+        let rand = &mut StdRng::seed_from_u64(seed);
+        let mut world = test_world(rand);
+        let mut expected = [0; MATERIAL_COUNT];
+        let mut handed_over = 0;
+
+        for _ in 0..1000 {
+            let pos = IVec3::new(rand.gen_range(0..32), DROP_HEIGHT, rand.gen_range(0..32));
+            let mat = *GameMaterial::all().choose(rand).unwrap();
+
+            let pushed = world.run_part(IVec2::ZERO, |part_world, changes| {
+                push_voxel(part_world, pos, mat, changes, rand, None)
+            });
+
+            // nothing changed, the whole world takes it from the start
+            if pushed.is_none() {
+                handed_over += 1;
+                world.run(|wbw, changes| wbw.push_block(pos, mat, changes, rand, None));
+            }
+
+            expected[mat.as_usize()] += 1;
+        }
+
+        world.apply_changes(rand);
+
+        assert!(handed_over > 0 && handed_over < 500, "{handed_over}");
+        assert_eq!(world.counts(), expected);
+        check_world(&world, seed);
+    }
+
+    #[test]
+    fn piles_grow_taller_than_a_chunk() {
+        let rand = &mut StdRng::seed_from_u64(0);
+        let col = IVec2::new(5, 7);
+        // a well, so everything stacks up in the one column
+        let walls = (-1..=1)
+            .flat_map(|x| (-1..=1).map(move |z| col + IVec2::new(x, z)))
+            .filter(|c| *c != col);
+        let mut world = TestWorld::new([IVec2::ZERO]).with_forbidden(walls);
+
+        let dropped = VOXEL_BLOCK_SIZE * 2 + 10;
+
+        for _ in 0..dropped {
+            world.run(|wbw, changes| wbw.drop_block(col, GameMaterial::Brownish, changes, rand));
+            world.apply_changes(rand);
+        }
+
+        assert!(world.changes.added.values().all(|ch| ch.is_empty()));
+        assert_eq!(world.run(|wbw, _| wbw.column_height(col)), Some(dropped));
+        assert_eq!(world.blocks().count(), 3);
+
+        let mut stack = world.blocks().collect::<Vec<_>>();
+        stack.sort_by_key(|b| b.pos.y);
+
+        let found = VoxelBlock::material_in_col(&stack, col, GameMaterial::Brownish as u8)
+            .map(|(_, pos)| pos.y)
+            .collect::<Vec<_>>();
+
+        assert_eq!(found, (0..dropped).collect::<Vec<_>>());
+        assert_eq!(
+            VoxelBlock::empty_at_col(&stack, col),
+            Some(col.extend(dropped).xzy())
+        );
+    }
+
+    #[test]
+    fn steal_from_nothing() {
+        let rand = &mut StdRng::seed_from_u64(0);
+        let mut world = test_world(rand);
+
+        let stolen = world.run(|wbw, changes| wbw.steal_block(IVec3::new(3, 0, -5), changes, rand));
+
+        assert_eq!(stolen, None);
+        assert_eq!(world.counts(), [0; MATERIAL_COUNT]);
+    }
+}
@@ -0,0 +1,349 @@
+//! Runs the game logic without a window, as fast as it goes, for balancing:
+//!
+//! `trashure --headless headless/plower_feeds_recycler.json [--seconds 600] [--out report.json]`
+//!
+//! Prints (or writes to `--out`) the stats and a time series of every machine as json.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::{ecs::schedule::ExecutorKind, prelude::*, time::TimeUpdateStrategy, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    game::{
+        self, machines::GameMachineSettingsDiscriminants, seed::WorldSeed, Direction2D,
+        GameUtilsPlugin,
+    },
+    graphics::{
+        flyingvoxel::FlyingVoxelPlugin,
+        machines::{
+            building::{finish_building, machine_bundle, place_machine},
+            definition::{MachineDefinition, MACHINE_DEFINITIONS},
+            machine_transform,
+            radar::RadarSimPlugin,
+            targets::Target,
+            BuiltMachine, MachineType, MyMachine,
+        },
+        sceneobjectfinder::SceneObjectsFound,
+        stats::StatsValues,
+        voxels3d::{changes::ChangeBudget, lazyworld::LazyWorldPlugin, VoxelSimPlugin},
+    },
+};
+
+/// simulated frames per second, the fixed timestep runs on top of these
+const STEPS_PER_SECOND: f32 = 30.0;
+
+pub struct HeadlessArgs {
+    pub setup: PathBuf,
+    pub seconds: f32,
+    pub out: Option<PathBuf>,
+}
+
+impl HeadlessArgs {
+    /// `None` when the game wasn't started with `--headless`
+    pub fn from_env() -> Option<Result<Self, String>> {
+        let mut args = std::env::args().skip(1);
+
+        if args.next().as_deref() != Some("--headless") {
+            return None;
+        }
+
+        Some(Self::parse(args))
+    }
+
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let usage = "usage: trashure --headless <setup.json> [--seconds N] [--out report.json]";
+
+        let mut setup = None;
+        let mut seconds = 300.0;
+        let mut out = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seconds" => {
+                    seconds = args
+                        .next()
+                        .and_then(|s| s.parse().ok())
+                        .ok_or_else(|| usage.to_string())?;
+                }
+                "--out" => out = Some(args.next().ok_or_else(|| usage.to_string())?.into()),
+                _ if setup.is_none() => setup = Some(arg.into()),
+                _ => return Err(usage.to_string()),
+            }
+        }
+
+        Ok(HeadlessArgs {
+            setup: setup.ok_or_else(|| usage.to_string())?,
+            seconds,
+            out,
+        })
+    }
+}
+
+/// The scripted start of a headless run.
+#[derive(Debug, Resource, Deserialize)]
+pub struct HeadlessSetup {
+    pub seed: Option<u64>,
+    /// seconds between two samples of the machines
+    #[serde(default = "default_sample_every")]
+    pub sample_every: f32,
+    pub machines: Vec<ScriptedMachine>,
+}
+
+fn default_sample_every() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScriptedMachine {
+    pub gmt: GameMachineSettingsDiscriminants,
+    pub name: Option<String>,
+    pub pos: IVec2,
+    pub direction: Direction2D,
+    /// skips building, the machine starts working right away
+    #[serde(default)]
+    pub built: bool,
+    #[serde(default)]
+    pub fuel: u8,
+    /// plowers only
+    pub target: Option<IVec2>,
+}
+
+#[derive(Debug, Serialize)]
+struct MachineSample {
+    t: f32,
+    pos: IVec2,
+    direction: Direction2D,
+    built: bool,
+    fuel: u8,
+    needed_maintenance: u8,
+    still_building: u8,
+    useful_ish_work_done: f32,
+}
+
+#[derive(Debug, Serialize)]
+struct MachineSeries {
+    gmt: GameMachineSettingsDiscriminants,
+    samples: Vec<MachineSample>,
+}
+
+#[derive(Resource, Default)]
+struct Recording {
+    next_sample: f32,
+    machines: BTreeMap<String, MachineSeries>,
+}
+
+#[derive(Serialize)]
+struct HeadlessReport {
+    seed: u64,
+    seconds: f32,
+    stats: BTreeMap<String, usize>,
+    machines: BTreeMap<String, MachineSeries>,
+}
+
+pub fn run(args: HeadlessArgs) -> Result<(), String> {
+    let setup = read_setup(&args.setup)?;
+    let report = simulate(setup, args.seconds);
+
+    let json = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+
+    match args.out {
+        Some(out) => {
+            std::fs::write(&out, json).map_err(|e| format!("can't write {}: {e}", out.display()))?
+        }
+        None => println!("{json}"),
+    }
+
+    Ok(())
+}
+
+fn read_setup(path: &Path) -> Result<HeadlessSetup, String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str::<HeadlessSetup>(&json).map_err(|e| e.to_string()))
+        .map_err(|e| format!("can't read {}: {e}", path.display()))
+}
+
+fn simulate(setup: HeadlessSetup, seconds: f32) -> HeadlessReport {
+    let mut app = App::new();
+
+    if let Some(seed) = setup.seed {
+        app.insert_resource(WorldSeed(seed));
+    }
+
+    app.add_plugins((
+        MinimalPlugins,
+        bevy::log::LogPlugin::default(),
+        TransformPlugin,
+        HierarchyPlugin,
+    ))
+    .insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f32(
+        1.0 / STEPS_PER_SECOND,
+    )))
+    .insert_resource(StatsValues::new())
+    .add_plugins((
+        VoxelSimPlugin,
+        LazyWorldPlugin,
+        GameUtilsPlugin,
+        RadarSimPlugin,
+        FlyingVoxelPlugin,
+        game::voxelmailbox::VoxelMailboxPlugin,
+    ))
+    // everything every frame, however fast the machine
+    .insert_resource(ChangeBudget { per_frame: None })
+    .insert_resource(setup)
+    .init_resource::<Recording>()
+    .add_systems(Startup, spawn_scripted_machines)
+    .add_systems(Update, (finish_building, place_machines, record_machines));
+
+    // `SessionRngSet` orders the systems drawing from the rng, this the rest of
+    // them, so a run replays from its seed
+    app.edit_schedule(Update, |schedule| {
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+    })
+    .edit_schedule(FixedUpdate, |schedule| {
+        schedule.set_executor_kind(ExecutorKind::SingleThreaded);
+    });
+
+    app.finish();
+    app.cleanup();
+
+    let steps = (seconds * STEPS_PER_SECOND).ceil() as u64;
+
+    for _ in 0..steps {
+        app.update();
+    }
+
+    let world = &mut app.world;
+
+    HeadlessReport {
+        seed: world.resource::<WorldSeed>().0,
+        seconds,
+        stats: world
+            .resource::<StatsValues>()
+            .0
+            .iter()
+            .map(|(name, v)| (name.to_string(), *v))
+            .collect(),
+        machines: std::mem::take(&mut world.resource_mut::<Recording>().machines),
+    }
+}
+
+fn spawn_scripted_machines(mut commands: Commands, setup: Res<HeadlessSetup>) {
+    let mut types = HashMap::default();
+
+    // no asset server, so no hot reload and no scenes either
+    for path in MACHINE_DEFINITIONS {
+        match MachineDefinition::read(path) {
+            Ok(def) => {
+                let mt = MachineType::new(&def, Handle::default(), Handle::default());
+                types.insert(mt.gmt, (commands.spawn_empty().id(), mt));
+            }
+            Err(e) => warn!("{e}"),
+        }
+    }
+
+    let mut counter = HashMap::<GameMachineSettingsDiscriminants, usize>::default();
+
+    for sm in setup.machines.iter() {
+        let Some((tp, mt)) = types.get(&sm.gmt) else {
+            warn!("no definition for {:?}, skipping it", sm.gmt);
+            continue;
+        };
+        let n = counter.entry(sm.gmt).and_modify(|c| *c += 1).or_insert(1);
+        let name = sm
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{:?} ({n})", sm.gmt));
+
+        let mut mm = MyMachine::new(*tp, mt, sm.pos);
+        mm.fuel = sm.fuel.min(mm.max_fuel);
+
+        if sm.built {
+            mm.still_building = 0;
+        }
+
+        let e = commands
+            .spawn(machine_bundle(name.clone(), mt, mm, sm.direction))
+            // no scene to find anything in
+            .insert(SceneObjectsFound(default()))
+            .id();
+
+        place_machine(&mut commands, e, mt, name);
+
+        if let Some(target) = sm.target {
+            commands.entity(e).insert(Target::new(target));
+        }
+    }
+
+    for (tp, mt) in types.into_values() {
+        commands.entity(tp).insert(mt);
+    }
+}
+
+/// what `update_machines` does along with spawning the scene
+fn place_machines(mut q_machines: Query<(&MyMachine, &Direction2D, &mut Transform)>) {
+    for (mm, dir, mut trans) in q_machines.iter_mut() {
+        *trans = machine_transform(mm, dir);
+    }
+}
+
+fn record_machines(
+    time: Res<Time>,
+    setup: Res<HeadlessSetup>,
+    mut recording: ResMut<Recording>,
+    q_machines: Query<(&Name, &MyMachine, &Direction2D, Option<&BuiltMachine>)>,
+) {
+    let t = time.elapsed_seconds();
+
+    if t < recording.next_sample {
+        return;
+    }
+
+    recording.next_sample = t + setup.sample_every;
+
+    for (name, mm, dir, built) in q_machines.iter() {
+        recording
+            .machines
+            .entry(name.to_string())
+            .or_insert_with(|| MachineSeries {
+                gmt: mm.gmt,
+                samples: vec![],
+            })
+            .samples
+            .push(MachineSample {
+                t,
+                pos: mm.pos,
+                direction: *dir,
+                built: built.is_some(),
+                fuel: mm.fuel,
+                needed_maintenance: mm.needed_maintenance,
+                still_building: mm.still_building,
+                useful_ish_work_done: mm.useful_ish_work_done,
+            });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{read_setup, simulate};
+
+    #[test]
+    fn same_seed_same_report() {
+        let path = Path::new("headless/plower_feeds_recycler.json");
+
+        let reports = [(); 2].map(|_| {
+            let setup = read_setup(path).unwrap();
+
+            serde_json::to_string(&simulate(setup, 20.0)).unwrap()
+        });
+
+        assert_eq!(reports[0], reports[1]);
+    }
+}
@@ -3,6 +3,7 @@ mod conf;
 mod debugeditor;
 mod game;
 mod graphics;
+mod headless;
 #[allow(unused_imports)]
 use bevy::{asset::AssetMetaCheck, prelude::*};
 
@@ -13,6 +14,15 @@ use bevy::{asset::AssetMetaCheck, prelude::*};
 // };
 
 fn main() {
+    if let Some(args) = headless::HeadlessArgs::from_env() {
+        if let Err(e) = args.and_then(headless::run) {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
     let mut app = App::new();
     app.insert_resource(AssetMetaCheck::Never)
         // outside
@@ -32,7 +42,7 @@ fn main() {
         // .add_plugins(bevy_inspector_egui::quick::WorldInspectorPlugin::new())
         // mine
         .add_plugins((
-            conf::ConfigPlugin,
+            conf::ConfigPlugin::default(),
             #[cfg(feature = "dbg")]
             debugeditor::DebugEditorPlugin,
         ))
@@ -48,6 +58,7 @@ fn main() {
             // graphics::fps::FpsPlugin,
             // graphics::dbgtext::DbgTextPlugin
             graphics::stats::StatsPlugin,
+            graphics::minimap::MinimapPlugin,
         ))
         .add_plugins((
             game::GameUtilsPlugin,
@@ -58,6 +69,10 @@ fn main() {
             graphics::selectable::SelectablePlugin,
             graphics::flyingvoxel::FlyingVoxelPlugin,
             game::voxelmailbox::VoxelMailboxPlugin,
+            game::save::SavePlugin,
+            game::waypoint::WaypointControlsPlugin,
+            game::supply::SupplyControlsPlugin,
+            game::speed::GameSpeedPlugin,
             graphics::debug3d::Debug3dPlugin,
             graphics::scenerenderlayer::SceneRenderLayersPlugin,
             graphics::sceneobjectfinder::SceneObjectFinderPlugin,